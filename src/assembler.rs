@@ -0,0 +1,741 @@
+use crate::chip8::{Address, Register, PROGRAM_START};
+use crate::instructions::Instructions;
+use crate::instructions::Instructions::*;
+use crate::memory::MEMORY_SIZE;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Mnemonics of the instructions and directives, as written in Cowgod's technical reference.
+/// `db` and `dw` (or `.byte` and `.word`) insert bytes and big-endian words
+const MNEMONICS: [&str; 31] = [
+    "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE", "SNE",
+    "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND", "DRW", "SKP", "SKNP",
+    "PLANE", "AUDIO", "PITCH", "DB", "DW",
+];
+/// Deepest chain of included files. Files that include each other stop there
+const MAX_INCLUDE_DEPTH: usize = 16;
+/// Deepest chain of constants defined by other constants. Constants that refer to each other stop there
+const MAX_CONSTANT_DEPTH: usize = 32;
+
+/// Error found while assembling, with where it was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssembleError {
+    /// File the error is in
+    pub file: String,
+    /// Line of the error, starting at 1
+    pub line: usize,
+    /// Column of the error, starting at 1
+    pub column: usize,
+    /// What is wrong
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+/// An assembled program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    /// The ROM, to be loaded at PROGRAM_START
+    pub rom: Vec<u8>,
+    /// Labels and their addresses, in the order they were defined
+    pub symbols: Vec<(String, Address)>,
+}
+
+impl Program {
+    /// Returns the symbol file of the program: a label per line, after its address in hex
+    pub fn symbol_file(&self) -> String {
+        self.symbols
+            .iter()
+            .map(|(name, addr)| format!("{:04X} {}\n", addr, name))
+            .collect()
+    }
+}
+
+/// Where something is in the source code
+#[derive(Debug, Clone)]
+struct Location {
+    file: String,
+    line: usize,
+    column: usize,
+}
+
+impl Location {
+    /// Returns an error at this location
+    fn error<S: Into<String>>(&self, message: S) -> AssembleError {
+        AssembleError {
+            file: self.file.clone(),
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+
+    /// Returns the same location, at another column of the line
+    fn at(&self, column: usize) -> Location {
+        Location {
+            column,
+            ..self.clone()
+        }
+    }
+}
+
+/// Operand of an instruction or a directive, as written
+#[derive(Debug, Clone)]
+struct Operand {
+    text: String,
+    location: Location,
+}
+
+/// Instruction or data directive, waiting for the labels to be known
+#[derive(Debug, Clone)]
+struct Statement {
+    /// Mnemonic, in upper case
+    mnemonic: String,
+    operands: Vec<Operand>,
+    location: Location,
+}
+
+/// State of the assembler. The first pass reads the source, placing the statements and defining the labels and
+/// the constants. The second one encodes the statements
+struct Assembler {
+    statements: Vec<Statement>,
+    labels: HashMap<String, Address>,
+    /// Labels in the order they were defined
+    symbols: Vec<(String, Address)>,
+    /// Constants, with the expression that defines them
+    constants: HashMap<String, Operand>,
+    /// Address of the next statement
+    addr: usize,
+}
+
+/// Assembles source code read from the given path. Files it includes are looked up relative to that path.
+///
+/// ```text
+/// sprite equ 0x20
+///
+/// start:  LD I, smiley         ; comments start with a semicolon
+///         LD V0, sprite - 0x10
+///         DRW V0, V0, 5
+/// loop:   JP loop
+/// smiley: db 0x24, 0x24, 0x00, 0x81, 0x7E
+/// ```
+pub fn assemble(source: &str, path: &Path) -> Result<Program, AssembleError> {
+    let mut assembler = Assembler {
+        statements: vec![],
+        labels: HashMap::new(),
+        symbols: vec![],
+        constants: HashMap::new(),
+        addr: PROGRAM_START as usize,
+    };
+    assembler.read(source, path, 0)?;
+
+    let mut rom = vec![];
+    for statement in &assembler.statements {
+        rom.extend(assembler.encode(statement)?);
+    }
+
+    Ok(Program {
+        rom,
+        symbols: assembler.symbols,
+    })
+}
+
+impl Assembler {
+    /// Reads a source file, placing its statements and defining its labels and constants
+    fn read(&mut self, source: &str, path: &Path, depth: usize) -> Result<(), AssembleError> {
+        for (index, text) in source.lines().enumerate() {
+            let location = Location {
+                file: path.display().to_string(),
+                line: index + 1,
+                column: 1,
+            };
+            self.read_line(strip_comment(text), &location, path, depth)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a line of source code, without its comment
+    fn read_line(
+        &mut self,
+        text: &str,
+        location: &Location,
+        path: &Path,
+        depth: usize,
+    ) -> Result<(), AssembleError> {
+        let (mut word, mut column, mut rest) = match next_word(text, 0) {
+            Some(word) => word,
+            None => return Ok(()),
+        };
+
+        // A label can come before the statement
+        if let Some(label) = word.strip_suffix(':') {
+            self.define_label(label, &location.at(column))?;
+            match next_word(text, rest) {
+                Some(next) => (word, column, rest) = next,
+                None => return Ok(()),
+            }
+        }
+        let location = location.at(column);
+
+        // Constants are written as `name equ value`
+        if let Some((equ, _, value)) = next_word(text, rest) {
+            if equ.eq_ignore_ascii_case("equ") {
+                let column = next_word(text, value).map_or(value + 1, |(_, column, _)| column);
+                return self.define_constant(word, text[value..].trim(), &location, column);
+            }
+        }
+
+        if word.eq_ignore_ascii_case("include") {
+            return self.include(text[rest..].trim(), &location, path, depth);
+        }
+
+        let mnemonic = match word.to_uppercase().as_str() {
+            ".BYTE" => "DB".to_string(),
+            ".WORD" => "DW".to_string(),
+            mnemonic => mnemonic.to_string(),
+        };
+        if !MNEMONICS.contains(&mnemonic.as_str()) {
+            return Err(location.error(format!("Unknown instruction: {}", word)));
+        }
+
+        let operands = split_operands(text, rest, &location);
+        let size = match mnemonic.as_str() {
+            "DB" => operands.len(),
+            "DW" => operands.len() * 2,
+            // LD I, LONG nnnn spans two words
+            "LD" if operands
+                .get(1)
+                .map_or(false, |operand| long_address(&operand.text).is_some()) =>
+            {
+                4
+            }
+            _ => 2,
+        };
+        if operands.is_empty() && (mnemonic == "DB" || mnemonic == "DW") {
+            return Err(location.error(format!("{} needs at least one value", word)));
+        }
+
+        self.addr += size;
+        if self.addr > MEMORY_SIZE {
+            return Err(location.error("The program does not fit in memory"));
+        }
+        self.statements.push(Statement {
+            mnemonic,
+            operands,
+            location,
+        });
+        Ok(())
+    }
+
+    /// Defines a label at the current address
+    fn define_label(&mut self, name: &str, location: &Location) -> Result<(), AssembleError> {
+        self.check_name(name, location)?;
+        self.labels.insert(name.to_string(), self.addr as Address);
+        self.symbols.push((name.to_string(), self.addr as Address));
+        Ok(())
+    }
+
+    /// Defines a constant. Its value is worked out when it is used, so it may refer to labels defined later
+    fn define_constant(
+        &mut self,
+        name: &str,
+        value: &str,
+        location: &Location,
+        column: usize,
+    ) -> Result<(), AssembleError> {
+        self.check_name(name, location)?;
+        let operand = Operand {
+            text: value.to_string(),
+            location: location.at(column),
+        };
+        self.constants.insert(name.to_string(), operand);
+        Ok(())
+    }
+
+    /// Fails if the name cannot be given to a label or a constant, or if it already is
+    fn check_name(&self, name: &str, location: &Location) -> Result<(), AssembleError> {
+        let valid = name
+            .chars()
+            .next()
+            .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && register(name).is_none();
+        if !valid {
+            return Err(location.error(format!("Invalid name: {}", name)));
+        }
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return Err(location.error(format!("{} is already defined", name)));
+        }
+        Ok(())
+    }
+
+    /// Reads the file named by an include directive, relative to the file that includes it
+    fn include(
+        &mut self,
+        name: &str,
+        location: &Location,
+        path: &Path,
+        depth: usize,
+    ) -> Result<(), AssembleError> {
+        let name = name
+            .strip_prefix('"')
+            .and_then(|name| name.strip_suffix('"'))
+            .ok_or_else(|| location.error("Expected a file name in double quotes"))?;
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(location.error("Too many nested includes"));
+        }
+
+        let included = path.parent().unwrap_or_else(|| Path::new("")).join(name);
+        let source = fs::read_to_string(&included).map_err(|err| {
+            location.error(format!("Could not read {}: {}", included.display(), err))
+        })?;
+        self.read(&source, &included, depth + 1)
+    }
+
+    /// Encodes a statement
+    fn encode(&self, statement: &Statement) -> Result<Vec<u8>, AssembleError> {
+        match statement.mnemonic.as_str() {
+            "DB" => statement
+                .operands
+                .iter()
+                .map(|operand| Ok(self.value(operand, -0x80, 0xFF)? as u8))
+                .collect(),
+            "DW" => {
+                let mut bytes = vec![];
+                for operand in &statement.operands {
+                    bytes.extend(&(self.value(operand, -0x8000, 0xFFFF)? as u16).to_be_bytes());
+                }
+                Ok(bytes)
+            }
+            _ => Ok(self.instruction(statement)?.encode()),
+        }
+    }
+
+    /// Works out the instruction a statement stands for
+    fn instruction(&self, statement: &Statement) -> Result<Instructions, AssembleError> {
+        let ops = &statement.operands;
+        let invalid = || {
+            statement
+                .location
+                .error(format!("Invalid operands for {}", statement.mnemonic))
+        };
+        let vx = |i: usize| {
+            register(&ops[i].text)
+                .ok_or_else(|| ops[i].location.error("Expected a register (V0 to VF)"))
+        };
+        let is = |i: usize, keyword: &str| ops[i].text.eq_ignore_ascii_case(keyword);
+
+        let inst = match (statement.mnemonic.as_str(), ops.len()) {
+            ("CLS", 0) => ClearDisplay,
+            ("RET", 0) => Return,
+            ("SCR", 0) => ScrollRight,
+            ("SCL", 0) => ScrollLeft,
+            ("EXIT", 0) => Exit,
+            ("LOW", 0) => LowRes,
+            ("HIGH", 0) => HighRes,
+            ("AUDIO", 0) => LoadAudioPattern,
+            ("SCD", 1) => ScrollDown(self.value(&ops[0], 0, 0xF)? as u8),
+            ("SCU", 1) => ScrollUp(self.value(&ops[0], 0, 0xF)? as u8),
+            ("PLANE", 1) => SelectPlanes(self.value(&ops[0], 0, 0xF)? as u8),
+            ("JP", 1) => Jump(self.address(&ops[0])?),
+            ("JP", 2) if register(&ops[0].text) == Some(0) => JumpPlusV0(self.address(&ops[1])?),
+            ("CALL", 1) => Call(self.address(&ops[0])?),
+            ("SE", 2) => match register(&ops[1].text) {
+                Some(y) => SkipIfEquals(vx(0)?, y),
+                None => SkipIfEqualsByte(vx(0)?, self.byte(&ops[1])?),
+            },
+            ("SNE", 2) => match register(&ops[1].text) {
+                Some(y) => SkipIfNotEquals(vx(0)?, y),
+                None => SkipIfNotEqualsByte(vx(0)?, self.byte(&ops[1])?),
+            },
+            ("ADD", 2) if is(0, "I") => AddRegisterI(vx(1)?),
+            ("ADD", 2) => match register(&ops[1].text) {
+                Some(y) => Add(vx(0)?, y),
+                None => AddByte(vx(0)?, self.byte(&ops[1])?),
+            },
+            ("OR", 2) => Or(vx(0)?, vx(1)?),
+            ("AND", 2) => And(vx(0)?, vx(1)?),
+            ("XOR", 2) => Xor(vx(0)?, vx(1)?),
+            ("SUB", 2) => Sub(vx(0)?, vx(1)?),
+            ("SUBN", 2) => ReverseSub(vx(0)?, vx(1)?),
+            // Without vy, vx is shifted in place whatever the quirks
+            ("SHR", 1) => ShiftRight(vx(0)?, vx(0)?),
+            ("SHR", 2) => ShiftRight(vx(0)?, vx(1)?),
+            ("SHL", 1) => ShiftLeft(vx(0)?, vx(0)?),
+            ("SHL", 2) => ShiftLeft(vx(0)?, vx(1)?),
+            ("RND", 2) => SetRandAnd(vx(0)?, self.byte(&ops[1])?),
+            ("DRW", 3) => Draw(vx(0)?, vx(1)?, self.value(&ops[2], 0, 0xF)? as u8),
+            ("SKP", 1) => SkipIfKeyPressed(vx(0)?),
+            ("SKNP", 1) => SkipIfKeyNotPressed(vx(0)?),
+            ("PITCH", 1) => SetPitch(vx(0)?),
+            ("LD", 2) => self.load(ops).ok_or_else(invalid)??,
+            _ => return Err(invalid()),
+        };
+        Ok(inst)
+    }
+
+    /// Works out which of the many LD instructions the operands stand for. Returns None if none
+    fn load(&self, ops: &[Operand]) -> Option<Result<Instructions, AssembleError>> {
+        let (dst, src) = (ops[0].text.to_uppercase(), ops[1].text.to_uppercase());
+        let vx = |i: usize| register(&ops[i].text);
+
+        let inst = match (dst.as_str(), src.as_str()) {
+            ("I", _) => match long_address(&ops[1].text) {
+                Some(addr) => {
+                    let operand = Operand {
+                        text: addr.to_string(),
+                        ..ops[1].clone()
+                    };
+                    self.value(&operand, 0, 0xFFFF)
+                        .map(|addr| SetLongI(addr as Address))
+                }
+                None => self.address(&ops[1]).map(SetI),
+            },
+            ("DT", _) => Ok(SetDelayTimer(vx(1)?)),
+            ("ST", _) => Ok(SetSoundTimer(vx(1)?)),
+            ("F", _) => Ok(SetSpriteI(vx(1)?)),
+            ("HF", _) => Ok(SetBigSpriteI(vx(1)?)),
+            ("B", _) => Ok(BCDRepresentation(vx(1)?)),
+            ("R", _) => Ok(SaveFlags(vx(1)?)),
+            ("[I]", _) => match register_range(&ops[1].text) {
+                Some((x, y)) => Ok(CopyRangeMemory(x, y)),
+                None => Ok(CopyRegistersMemory(vx(1)?)),
+            },
+            (_, "[I]") => match register_range(&ops[0].text) {
+                Some((x, y)) => Ok(SetRangeMemory(x, y)),
+                None => Ok(SetRegistersMemory(vx(0)?)),
+            },
+            (_, "DT") => Ok(SetToDelayTimer(vx(0)?)),
+            (_, "K") => Ok(WaitKeyPress(vx(0)?)),
+            (_, "R") => Ok(LoadFlags(vx(0)?)),
+            _ => {
+                let x = vx(0)?;
+                match vx(1) {
+                    Some(y) => Ok(SetRegister(x, y)),
+                    None => self.byte(&ops[1]).map(|byte| SetRegisterByte(x, byte)),
+                }
+            }
+        };
+        Some(inst)
+    }
+
+    /// Evaluates an operand that must be a byte. Negative values are stored in two's complement
+    fn byte(&self, operand: &Operand) -> Result<u8, AssembleError> {
+        Ok(self.value(operand, -0x80, 0xFF)? as u8)
+    }
+
+    /// Evaluates an operand that must be a 12-bit address
+    fn address(&self, operand: &Operand) -> Result<Address, AssembleError> {
+        Ok(self.value(operand, 0, 0xFFF)? as Address)
+    }
+
+    /// Evaluates an operand and checks that it lies between min and max
+    fn value(&self, operand: &Operand, min: i64, max: i64) -> Result<i64, AssembleError> {
+        let value = self.evaluate(operand, 0)?;
+        if value < min || value > max {
+            return Err(operand.location.error(format!(
+                "{} does not fit: expected a value from {} to {:#X}",
+                operand.text, min, max
+            )));
+        }
+        Ok(value)
+    }
+
+    /// Evaluates an expression: numbers, labels and constants added and subtracted
+    fn evaluate(&self, operand: &Operand, depth: usize) -> Result<i64, AssembleError> {
+        let text = operand.text.trim();
+        if text.is_empty() {
+            return Err(operand.location.error("Expected a value"));
+        }
+
+        let mut total: i64 = 0;
+        // Each term comes with the sign in front of it
+        let mut terms = vec![];
+        let mut start = 0;
+        let mut sign = 1;
+        for (j, c) in text.char_indices() {
+            if (c == '+' || c == '-') && !text[start..j].trim().is_empty() {
+                terms.push((sign, &text[start..j]));
+                sign = if c == '+' { 1 } else { -1 };
+                start = j + 1;
+            } else if c == '-' && text[start..j].trim().is_empty() {
+                sign = -sign;
+                start = j + 1;
+            }
+        }
+        terms.push((sign, &text[start..]));
+
+        let overflow = || {
+            operand
+                .location
+                .error(format!("{} is too big", operand.text))
+        };
+        for (sign, term) in terms {
+            let value = self.term(term.trim(), operand, depth)?;
+            total = value
+                .checked_mul(sign)
+                .and_then(|value| total.checked_add(value))
+                .ok_or_else(overflow)?;
+        }
+        Ok(total)
+    }
+
+    /// Evaluates a number, a label or a constant
+    fn term(&self, term: &str, operand: &Operand, depth: usize) -> Result<i64, AssembleError> {
+        if let Some(value) = parse_number(term) {
+            return Ok(value);
+        }
+        if let Some(addr) = self.labels.get(term) {
+            return Ok(*addr as i64);
+        }
+        if let Some(constant) = self.constants.get(term) {
+            if depth >= MAX_CONSTANT_DEPTH {
+                return Err(operand
+                    .location
+                    .error(format!("{} is defined in terms of itself", term)));
+            }
+            return self.evaluate(constant, depth + 1);
+        }
+        Err(operand.location.error(format!("Unknown value: {}", term)))
+    }
+}
+
+/// Returns the line without its comment, which starts with a semicolon outside of double quotes
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    for (j, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..j],
+            _ => (),
+        }
+    }
+    text
+}
+
+/// Returns the first word of the line at or after byte `from`, its column and the byte right after it
+fn next_word(text: &str, from: usize) -> Option<(&str, usize, usize)> {
+    let start = from + text[from..].find(|c: char| !c.is_whitespace())?;
+    let end = text[start..]
+        .find(char::is_whitespace)
+        .map_or(text.len(), |len| start + len);
+    Some((&text[start..end], start + 1, end))
+}
+
+/// Splits the operands that start at byte `from` of the line, which are separated by commas
+fn split_operands(text: &str, from: usize, location: &Location) -> Vec<Operand> {
+    if text[from..].trim().is_empty() {
+        return vec![];
+    }
+
+    let mut operands = vec![];
+    let mut start = from;
+    for part in text[from..].split(',') {
+        let column = start + (part.len() - part.trim_start().len()) + 1;
+        operands.push(Operand {
+            text: part.trim().to_string(),
+            location: location.at(column),
+        });
+        start += part.len() + 1;
+    }
+    operands
+}
+
+/// Parses a number written in decimal, in hex (`0x1F` or `$1F`) or in binary (`0b1010`)
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('$')) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+/// Returns the register an operand names (V0 to VF), if it names one
+fn register(text: &str) -> Option<Register> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(x), None) | (Some('V'), Some(x), None) => {
+            x.to_digit(16).map(|x| x as Register)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the registers of an operand written as a range of registers, such as `V2-V5`
+fn register_range(text: &str) -> Option<(Register, Register)> {
+    let (x, y) = text.split_once('-')?;
+    Some((register(x.trim())?, register(y.trim())?))
+}
+
+/// Returns the address of an operand written as `LONG nnnn`
+fn long_address(text: &str) -> Option<&str> {
+    let (keyword, addr) = text.split_once(char::is_whitespace)?;
+    if keyword.eq_ignore_ascii_case("long") {
+        Some(addr.trim())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assembles source code that does not include other files
+    fn assemble_str(source: &str) -> Result<Program, AssembleError> {
+        assemble(source, Path::new("test.s"))
+    }
+
+    #[test]
+    fn encode_round_trips() {
+        for raw in 0..=0xFFFF {
+            if let Some(inst) = Instructions::new(raw) {
+                assert_eq!(inst.encode(), raw.to_be_bytes(), "{:?}", inst);
+                assert_eq!(Instructions::new(raw), Some(inst));
+            }
+        }
+        let long = SetLongI(0xABCD);
+        assert_eq!(long.encode(), [0xF0, 0x00, 0xAB, 0xCD]);
+        assert_eq!(Instructions::new_long(0xF000, 0xABCD), Some(long));
+    }
+
+    #[test]
+    fn labels() {
+        let program = assemble_str(
+            "start:  LD V0, 5\n\
+             loop:   ADD V0, 1    ; forever\n\
+             \x20       JP loop\n\
+             \x20       CALL later\n\
+             later:  RET\n",
+        )
+        .unwrap();
+        assert_eq!(
+            program.rom,
+            [0x60, 0x05, 0x70, 0x01, 0x12, 0x02, 0x22, 0x08, 0x00, 0xEE]
+        );
+        assert_eq!(
+            program.symbols,
+            [
+                ("start".to_string(), 0x200),
+                ("loop".to_string(), 0x202),
+                ("later".to_string(), 0x208)
+            ]
+        );
+        assert_eq!(program.symbol_file(), "0200 start\n0202 loop\n0208 later\n");
+    }
+
+    #[test]
+    fn constants() {
+        let program = assemble_str(
+            "sprite equ base + 2\n\
+             base equ 0x300\n\
+             LD I, sprite\n\
+             LD V1, -1\n\
+             LD I, LONG end + 0x1000\n\
+             end:\n",
+        )
+        .unwrap();
+        assert_eq!(
+            program.rom,
+            [0xA3, 0x02, 0x61, 0xFF, 0xF0, 0x00, 0x12, 0x08]
+        );
+    }
+
+    #[test]
+    fn data() {
+        let program = assemble_str(
+            "data: db 1, 0x02, -1\n\
+             .word 0x1234, data\n\
+             DW -2\n",
+        )
+        .unwrap();
+        assert_eq!(
+            program.rom,
+            [0x01, 0x02, 0xFF, 0x12, 0x34, 0x02, 0x00, 0xFF, 0xFE]
+        );
+    }
+
+    #[test]
+    fn includes() {
+        let dir = std::env::temp_dir().join(format!("chip8-assembler-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("lib").join("sub.s"), "sub: RET\n").unwrap();
+        fs::write(dir.join("lib").join("bad.s"), "RET\nNOP\n").unwrap();
+
+        let main = dir.join("main.s");
+        let program = assemble("CALL sub\ninclude \"lib/sub.s\"\n", &main).unwrap();
+        assert_eq!(program.rom, [0x22, 0x02, 0x00, 0xEE]);
+        assert_eq!(program.symbols, [("sub".to_string(), 0x202)]);
+
+        // Errors point at the included file
+        let err = assemble("CLS\ninclude \"lib/bad.s\"\n", &main).unwrap_err();
+        assert_eq!(
+            err.file,
+            dir.join("lib").join("bad.s").display().to_string()
+        );
+        assert_eq!((err.line, err.column), (2, 1));
+
+        let err = assemble("include \"missing.s\"\n", &main).unwrap_err();
+        assert_eq!((err.line, err.column), (1, 1));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn error_locations() {
+        let error = |source: &str| {
+            let err = assemble_str(source).unwrap_err();
+            (err.line, err.column, err.message)
+        };
+        assert_eq!(
+            error("  CLS\n  FOO V0\n"),
+            (2, 3, "Unknown instruction: FOO".to_string())
+        );
+        assert_eq!(
+            error("CLS\n  ADD V0, 0x100\n"),
+            (
+                2,
+                11,
+                "0x100 does not fit: expected a value from -128 to 0xFF".to_string()
+            )
+        );
+        assert_eq!(
+            error("CLS\n  LD VG, 1\n"),
+            (2, 3, "Invalid operands for LD".to_string())
+        );
+        assert_eq!(
+            error("  JP nowhere\n"),
+            (1, 6, "Unknown value: nowhere".to_string())
+        );
+        assert_eq!(
+            error("a: CLS\nb: CLS\n  a: CLS\n"),
+            (3, 3, "a is already defined".to_string())
+        );
+        assert_eq!(
+            error("x equ y\ny equ x\nLD V0, x\n"),
+            (2, 7, "x is defined in terms of itself".to_string())
+        );
+        assert_eq!(
+            error("LD V0, 0x7FFFFFFFFFFFFFFF + 1\n"),
+            (1, 8, "0x7FFFFFFFFFFFFFFF + 1 is too big".to_string())
+        );
+        assert_eq!(
+            error("big equ 0x7FFFFFFFFFFFFFFF\nLD V0, - big - big\n"),
+            (2, 8, "- big - big is too big".to_string())
+        );
+    }
+}
@@ -0,0 +1,336 @@
+use crate::chip8::AUDIO_PATTERN_SIZE;
+
+#[cfg(feature = "rodio")]
+use rodio::{Device, Sink, Source};
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Default frequency of the beep (Hz)
+pub const TONE_FREQUENCY: u32 = 440;
+/// Default volume of the sound, from 0.0 to 1.0
+pub const DEFAULT_VOLUME: f32 = 0.25;
+/// Sample rate of the rendered audio (Hz)
+pub const SAMPLE_RATE: u32 = 44100;
+/// Rate at which frames are emulated (Hz). Each frame covers SAMPLE_RATE / FRAME_RATE samples
+pub const FRAME_RATE: u32 = 60;
+
+/// Anything able to play the CHIP-8 beeper
+pub trait AudioBackend {
+    /// Starts playing the tone. Has no effect if it is already playing
+    fn start_tone(&mut self);
+    /// Stops playing the tone. Has no effect if it is already stopped
+    fn stop_tone(&mut self);
+    /// Changes the frequency of the tone (Hz) and the volume of the sound (0.0 to 1.0)
+    fn set_tone(&mut self, _frequency: u32, _volume: f32) {}
+    /// Replaces the tone with an XO-CHIP audio pattern: 128 one-bit samples, looped at `rate` samples per second
+    fn set_pattern(&mut self, _pattern: &[u8; AUDIO_PATTERN_SIZE], _rate: f32) {}
+    /// Called at the end of every emulated frame. Backends that generate samples themselves use it to keep track of time
+    fn end_frame(&mut self) {}
+}
+
+/// Returns the sample (1 or 0) at the given position of an audio pattern. The position wraps around
+fn pattern_bit(pattern: &[u8; AUDIO_PATTERN_SIZE], position: usize) -> u8 {
+    let position = position % (AUDIO_PATTERN_SIZE * 8);
+    (pattern[position / 8] >> (7 - position % 8)) & 0x01
+}
+
+/// Audio backend that plays nothing
+pub struct NullAudio;
+
+impl AudioBackend for NullAudio {
+    fn start_tone(&mut self) {}
+
+    fn stop_tone(&mut self) {}
+}
+
+/// Audio backend that renders the beeper to a WAV file (16-bit mono PCM).
+/// Samples are generated per emulated frame, so the output does not depend on how fast the host runs
+pub struct WavAudio {
+    /// Output file
+    file: BufWriter<File>,
+    /// Whether the tone is playing
+    playing: bool,
+    /// Amount of samples written so far
+    samples: u32,
+    /// XO-CHIP audio pattern and its playback rate, if any
+    pattern: Option<([u8; AUDIO_PATTERN_SIZE], f32)>,
+    /// Position in the audio pattern, in pattern samples
+    pattern_position: f32,
+    /// Frequency of the tone (Hz)
+    frequency: u32,
+    /// Volume of the sound, from 0.0 to 1.0
+    volume: f32,
+}
+
+impl WavAudio {
+    /// Creates the WAV file at the given path
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<WavAudio> {
+        let mut file = BufWriter::new(File::create(path)?);
+        // Data size is not known yet. The header is rewritten with the right values when we are done
+        write_wav_header(&mut file, 0)?;
+
+        Ok(WavAudio {
+            file,
+            playing: false,
+            samples: 0,
+            pattern: None,
+            pattern_position: 0.0,
+            frequency: TONE_FREQUENCY,
+            volume: DEFAULT_VOLUME,
+        })
+    }
+
+    /// Writes the samples of one frame
+    fn write_frame(&mut self) -> io::Result<()> {
+        let amplitude = i16::MAX as f32 * self.volume;
+
+        for _ in 0..SAMPLE_RATE / FRAME_RATE {
+            let sample = match (self.playing, &self.pattern) {
+                (false, _) => 0,
+                (true, Some((pattern, rate))) => {
+                    let bit = pattern_bit(pattern, self.pattern_position as usize);
+                    self.pattern_position += rate / SAMPLE_RATE as f32;
+                    if bit == 1 {
+                        amplitude as i16
+                    } else {
+                        -amplitude as i16
+                    }
+                }
+                (true, None) => {
+                    let t = self.samples as f32 / SAMPLE_RATE as f32;
+                    (amplitude * (2.0 * std::f32::consts::PI * self.frequency as f32 * t).sin())
+                        as i16
+                }
+            };
+            self.file.write_all(&sample.to_le_bytes())?;
+            self.samples += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Rewrites the header with the final size and flushes the file
+    fn finish(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        write_wav_header(&mut self.file, self.samples * 2)?;
+        self.file.flush()
+    }
+}
+
+impl AudioBackend for WavAudio {
+    fn start_tone(&mut self) {
+        self.playing = true;
+    }
+
+    fn stop_tone(&mut self) {
+        self.playing = false;
+    }
+
+    fn set_tone(&mut self, frequency: u32, volume: f32) {
+        self.frequency = frequency;
+        self.volume = volume;
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; AUDIO_PATTERN_SIZE], rate: f32) {
+        self.pattern = Some((*pattern, rate));
+        self.pattern_position %= (AUDIO_PATTERN_SIZE * 8) as f32;
+    }
+
+    fn end_frame(&mut self) {
+        self.write_frame().expect("Error writing WAV file");
+    }
+}
+
+impl Drop for WavAudio {
+    /// Finishes the file. Errors are only reported, since panicking here could abort the program while unwinding
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            eprintln!("Error writing WAV file: {}", err);
+        }
+    }
+}
+
+/// Writes the RIFF header of a 16-bit mono PCM WAV file with `data_size` bytes of samples
+fn write_wav_header<W: Write>(out: &mut W, data_size: u32) -> io::Result<()> {
+    let channels: u16 = 1;
+    let bits_per_sample: u16 = 16;
+    let block_align = channels * bits_per_sample / 8;
+    let byte_rate = SAMPLE_RATE * block_align as u32;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_size).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+    out.write_all(b"fmt ")?;
+    // Size of the fmt chunk and audio format (1 = PCM)
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&channels.to_le_bytes())?;
+    out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    out.write_all(&byte_rate.to_le_bytes())?;
+    out.write_all(&block_align.to_le_bytes())?;
+    out.write_all(&bits_per_sample.to_le_bytes())?;
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())
+}
+
+/// Audio backend that plays the beeper on the default sound device through rodio.
+/// If there is no sound device, no sound will play
+#[cfg(feature = "rodio")]
+pub struct RodioAudio {
+    /// The sound device
+    device: Option<Device>,
+    /// Handle to the sound device
+    sink: Option<Sink>,
+    /// Whether the tone is playing
+    playing: bool,
+    /// Volume of the sound, from 0.0 to 1.0
+    volume: f32,
+}
+
+#[cfg(feature = "rodio")]
+impl RodioAudio {
+    /// Creates a new audio backend using the default sound device
+    pub fn new() -> RodioAudio {
+        let device = rodio::default_output_device();
+
+        let sink = match &device {
+            Some(device) => {
+                // If there is a sound device, create a source and add it to the sink (handle to the device)
+                let source = rodio::source::SineWave::new(TONE_FREQUENCY);
+                let sink = Sink::new(device);
+                sink.set_volume(DEFAULT_VOLUME);
+                // The beep is always the same until a program sets an audio pattern, so we create it here add it to the sink
+                sink.append(source);
+                // We must pause it to prevent it from playing right now
+                sink.pause();
+                Some(sink)
+            }
+            None => {
+                println!("No sound device available!");
+                None
+            }
+        };
+
+        RodioAudio {
+            device,
+            sink,
+            playing: false,
+            volume: DEFAULT_VOLUME,
+        }
+    }
+
+    /// Replaces the sink with a new one playing the given source, keeping the tone paused or playing
+    fn play_source<S>(&mut self, source: S)
+    where
+        S: Source + Send + 'static,
+        S::Item: rodio::Sample + Send,
+    {
+        if let Some(device) = &self.device {
+            // A sink cannot drop its sources, so every new sound gets a new one
+            let sink = Sink::new(device);
+            sink.append(source);
+            sink.set_volume(self.volume);
+            if !self.playing {
+                sink.pause();
+            }
+            self.sink = Some(sink);
+        }
+    }
+}
+
+#[cfg(feature = "rodio")]
+impl Default for RodioAudio {
+    fn default() -> RodioAudio {
+        RodioAudio::new()
+    }
+}
+
+#[cfg(feature = "rodio")]
+impl AudioBackend for RodioAudio {
+    fn start_tone(&mut self) {
+        self.playing = true;
+        if let Some(sink) = &self.sink {
+            sink.play();
+        }
+    }
+
+    fn stop_tone(&mut self) {
+        self.playing = false;
+        // sink.pause() has no effect if it is already paused
+        if let Some(sink) = &self.sink {
+            sink.pause();
+        }
+    }
+
+    fn set_tone(&mut self, frequency: u32, volume: f32) {
+        self.volume = volume;
+        self.play_source(rodio::source::SineWave::new(frequency));
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; AUDIO_PATTERN_SIZE], rate: f32) {
+        let samples: Vec<i16> = (0..AUDIO_PATTERN_SIZE * 8)
+            .map(|position| {
+                if pattern_bit(pattern, position) == 1 {
+                    i16::MAX
+                } else {
+                    -i16::MAX
+                }
+            })
+            .collect();
+        let source = rodio::buffer::SamplesBuffer::new(1, rate as u32, samples);
+        self.play_source(source.repeat_infinite());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::frontend::Frontend;
+    use crate::video::HeadlessVideo;
+
+    /// Samples written for each frame
+    const FRAME_SAMPLES: usize = (SAMPLE_RATE / FRAME_RATE) as usize;
+    /// Size of the header of a WAV file
+    const HEADER_SIZE: usize = 44;
+
+    #[test]
+    fn wav_follows_sound_timer() {
+        let path =
+            std::env::temp_dir().join(format!("chip8-audio-test-{}.wav", std::process::id()));
+        let frames = 20;
+
+        // va := 10, buzzer := va, then loop forever
+        let mut chip8 = Chip8::new();
+        chip8
+            .load_rom(&[0x6A, 0x0A, 0xFA, 0x18, 0x12, 0x04])
+            .unwrap();
+        let video = Box::new(HeadlessVideo::new(Some(frames as u64)));
+        let audio = Box::new(WavAudio::new(&path).unwrap());
+        let mut frontend = Frontend::new(video, audio);
+        frontend.run(&mut chip8);
+        // Dropping the frontend finishes the file
+        drop(frontend);
+
+        let wav = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(
+            u32::from_le_bytes([wav[40], wav[41], wav[42], wav[43]]) as usize,
+            frames * FRAME_SAMPLES * 2
+        );
+
+        let samples: Vec<i16> = wav[HEADER_SIZE..]
+            .chunks(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect();
+        assert_eq!(samples.len(), frames * FRAME_SAMPLES);
+
+        // The timer is ticked at the end of each frame and beeps while above 1: from 9 down to 2, that is 8 frames
+        for (frame, samples) in samples.chunks(FRAME_SAMPLES).enumerate() {
+            let playing = samples.iter().any(|sample| *sample != 0);
+            assert_eq!(playing, frame < 8, "frame {}", frame);
+        }
+    }
+}
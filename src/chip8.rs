@@ -1,15 +1,18 @@
 extern crate rand;
 
 use crate::cpu::CPU;
-use crate::display::Display;
+use crate::debugger::{BreakReason, Condition, Watchpoint};
+use crate::display::{Display, PLANE_COUNT};
 use crate::instructions::Instructions;
-use crate::memory::Memory;
+use crate::keypad::{Keypad, KEY_COUNT};
+use crate::memory::{Memory, MEMORY_SIZE, ORIGINAL_MEMORY_SIZE};
+use crate::quirks::Quirks;
+use crate::random::{Random, RandomMode};
+use crate::state::{StateReader, StateWriter};
+use crate::timing::{self, VIP_CYCLES_PER_FRAME, VIP_FETCH_CYCLES, VIP_INTERRUPT_CYCLES};
 
 use rand::random;
-use std::thread::sleep;
-use std::time::{Duration, Instant};
-
-use rodio::Sink;
+use std::collections::HashSet;
 
 /// Type aliases
 ///
@@ -28,229 +31,898 @@ pub const ORIGINAL_WIDTH: usize = 64;
 pub const ORIGINAL_HEIGHT: usize = 32;
 /// Length of the coord buffer. This value represents the amount of pixels the original CHIP-8 had
 pub const COORD_LENGTH: usize = ORIGINAL_WIDTH * ORIGINAL_HEIGHT;
+/// Window width of SUPER-CHIP in hires mode.
+pub const HIRES_WIDTH: usize = 128;
+/// Window height of SUPER-CHIP in hires mode.
+pub const HIRES_HEIGHT: usize = 64;
+/// Amount of RPL user flags. SUPER-CHIP uses the first 8 (SCHIP_FLAG_COUNT), XO-CHIP all of them
+pub const FLAG_COUNT: usize = 0x10;
+/// Amount of RPL user flags available to SUPER-CHIP programs
+pub const SCHIP_FLAG_COUNT: usize = 8;
+/// Size of the XO-CHIP audio pattern buffer in bytes. Each bit is one sample
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+/// Default pitch of the XO-CHIP audio pattern playback, which plays it at 4000 samples per second
+pub const DEFAULT_PITCH: u8 = 64;
 
 // The following constants cam be altered to change the emulator behavior
 //
-/// Scale for the window size of the emulator. Since 64x32 is too tiny of a window for today's screens, a scale is necessary
-/// Enlarges the window by a factor of WINDOW_SCALE
-pub const WINDOW_SCALE: usize = 8;
-/// Color of the pixel. White by default
-pub const PIXEL_COLOR: u32 = 0x00FF_FFFF;
-/// Clock speed (instructions per second).
-pub const CLOCK: u32 = 60 * 20;
+/// Default amount of instructions run in each 60 Hz frame. 20 gives 1200 instructions per second
+pub const INSTRUCTIONS_PER_FRAME: u32 = 20;
+/// Most instructions run in each frame. More would take longer than a frame to run
+pub const MAX_INSTRUCTIONS_PER_FRAME: u32 = 1_000_000;
 
 /// This struct ties together all components of the emulator.
+/// It does not own a window nor a sound device, so it can be embedded anywhere. See `frontend` for those
 pub struct Chip8 {
     /// The memory. Notable addresses:
     /// 0x000 to 0x4F - Used to store default font sprites (0-F sequentially)
+    /// 0x050 to 0xEF - Used to store big font sprites (0-F sequentially)
     /// 0x200 - Start of most Chip-8 programs
     /// 0xFFF - End of Chip-8 RAM
+    /// 0xFFFF - End of XO-CHIP RAM
     ram: Memory,
     /// CPU. Handles registers and instructions
     cpu: CPU,
-    /// User interface. Handles keyboard input and graphics output
+    /// The screen. Holds the state of every pixel
     display: Display,
-    /// Audio interface
-    audio: Option<Sink>,
+    /// Keys currently held down. Fed by whoever is driving the emulator
+    keypad: Keypad,
+    /// Register that receives the key when Fx0A is waiting for one. No instructions are run until then
+    waiting_key: Option<Register>,
+    /// Keys that went down since Fx0A started waiting. With the wait key release quirk, only releasing one of them resumes it
+    wait_pressed: u16,
+    /// Interpreter behaviors used to run ambiguous instructions
+    quirks: Quirks,
+    /// RPL user flags. SUPER-CHIP programs use them to keep data such as high scores
+    flags: [u8; FLAG_COUNT],
+    /// Set when a program writes to the RPL user flags, so they can be persisted
+    flags_changed: bool,
+    /// SHA-1 of the loaded ROM, as a hex string. Empty until a ROM is loaded
+    rom_hash: String,
+    /// Set when the program exits (SUPER-CHIP). No more instructions are run after that
+    exited: bool,
+    /// Set when a sprite is drawn with the display wait quirk. No more instructions are run until the next vertical
+    /// blank, which is when the timers tick
+    waiting_vblank: bool,
+    /// Enables XO-CHIP semantics: i addresses 64 KiB and skips jump over the whole F000 NNNN instruction
+    xo_chip: bool,
+    /// XO-CHIP audio pattern buffer. None until a program loads one, in which case the regular beep is played
+    audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    /// XO-CHIP audio pattern playback pitch
+    pitch: u8,
+    /// Generator of the random numbers of Cxkk
+    random: Random,
+    /// Machine cycles left in the current frame of the COSMAC VIP timing model.
+    /// Negative when the last instruction of the previous frame overran it
+    vip_cycles: i32,
+    /// Addresses where the CPU halts before running the instruction, for the debugger
+    breakpoints: HashSet<Address>,
+    /// Why the CPU is halted for the debugger, if it is. No instructions are run until `resume()` is called
+    break_reason: Option<BreakReason>,
+    /// Set by `step()`. The CPU halts again once the next instruction has run
+    stepping: bool,
+    /// Set by `step_over()`: the CPU halts when it reaches this address with the stack pointer at or below this level
+    step_over: Option<(Address, usize)>,
+    /// Address of the instruction the CPU resumed on. Its breakpoint is ignored once, so resuming does not halt right away
+    resume_pc: Option<Address>,
 }
 
 impl Chip8 {
-    /// Creates and returns a new instance of the emulator.
-    /// For the audio interface, the default sound device is used.
-    /// If there is none, no sound will play.
+    /// Creates and returns a new instance of the emulator
     pub fn new() -> Chip8 {
-        let device = rodio::default_output_device();
-
         Chip8 {
             ram: Memory::new(),
             cpu: CPU::new(),
-            display: Display::new(
-                ORIGINAL_WIDTH * WINDOW_SCALE,
-                ORIGINAL_HEIGHT * WINDOW_SCALE,
-            ),
-            audio: match device {
-                Some(device) => {
-                    // If there is a sound device, create a source and add it to the sink (handle to the device)
-                    let source = rodio::source::SineWave::new(440);
-                    let sink = Sink::new(&device);
-                    // The beep is always the same, so we create it here add it to the sink
-                    sink.append(source);
-                    // We must pause it to prevent it from playing right now
-                    sink.pause();
-                    Some(sink)
-                }
-                None => {
-                    println!("No sound device available!");
-                    None
-                }
-            },
+            display: Display::new(),
+            keypad: Keypad::new(),
+            waiting_key: None,
+            wait_pressed: 0,
+            quirks: Quirks::default(),
+            flags: [0; FLAG_COUNT],
+            flags_changed: false,
+            rom_hash: String::new(),
+            exited: false,
+            waiting_vblank: false,
+            xo_chip: false,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            random: Random::new(random()),
+            vip_cycles: 0,
+            breakpoints: HashSet::new(),
+            break_reason: None,
+            stepping: false,
+            step_over: None,
+            resume_pc: None,
         }
     }
 
-    /// Starts the emulator and executes instructions from the provided ROM
-    pub fn run(&mut self) {
-        let mut timer = Instant::now();
-        let period = Duration::from_secs_f32(1.0 / CLOCK as f32);
+    /// Restarts the random number generator from the given seed, which makes runs reproducible.
+    /// A new machine is seeded randomly
+    pub fn set_seed(&mut self, seed: u64) {
+        self.random.seed(seed);
+    }
+
+    /// Sets the algorithm used to generate random numbers
+    pub fn set_random_mode(&mut self, mode: RandomMode) {
+        self.random.set_mode(mode);
+    }
+
+    /// Returns the algorithm used to generate random numbers
+    pub fn get_random_mode(&self) -> RandomMode {
+        self.random.get_mode()
+    }
+
+    /// Enables or disables XO-CHIP semantics
+    pub fn set_xo_chip(&mut self, xo_chip: bool) {
+        self.xo_chip = xo_chip;
+    }
+
+    /// Returns true if XO-CHIP semantics are enabled
+    pub fn is_xo_chip(&self) -> bool {
+        self.xo_chip
+    }
+
+    /// Sets the interpreter behaviors used to run ambiguous instructions
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
 
-        // This is the emulator's main loop
-        while self.display.is_window_open() {
-            // Every cycle we run a new instruction
+    /// Returns the interpreter behaviors used to run ambiguous instructions
+    pub fn get_quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Runs the given amount of instructions and then ticks the timers once.
+    /// Calling this 60 times per second with INSTRUCTIONS_PER_FRAME instructions gives the default speed.
+    /// With the display wait quirk, a sprite draw ends the frame early
+    pub fn run_frame(&mut self, instructions: u32) {
+        for _ in 0..instructions {
+            if self.waiting_vblank
+                || self.waiting_key.is_some()
+                || self.exited
+                || self.break_reason.is_some()
+            {
+                break;
+            }
             self.run_next_instruction();
+        }
+        self.tick_timers();
+    }
 
-            if timer.elapsed().as_micros() > 16667 {
-                timer = Instant::now();
+    /// Runs one frame on the timing model of the COSMAC VIP, then ticks the timers once.
+    /// Each instruction is charged the machine cycles it took on the original interpreter, and the display interrupt
+    /// takes its share of the frame, so programs run at the speed they did on the original hardware.
+    /// An instruction that overruns the frame borrows from the next one
+    pub fn run_vip_frame(&mut self) {
+        self.vip_cycles += VIP_CYCLES_PER_FRAME - VIP_INTERRUPT_CYCLES;
 
-                // If st value is greater than 1, play beep sound
-                if self.cpu.get_st() > 1 {
-                    match &self.audio {
-                        Some(sink) => sink.play(),
-                        None => (),
-                    }
-                // Else, pause beep sound. sink.pause() has no effect if it is already paused
-                } else {
-                    match &self.audio {
-                        Some(sink) => sink.pause(),
-                        None => (),
-                    }
+        while self.vip_cycles > 0 && !self.exited {
+            // The interpreter idles until the interrupt or a key, so the rest of the frame is lost
+            if self.waiting_vblank || self.waiting_key.is_some() {
+                self.vip_cycles = 0;
+                break;
+            }
+            // The debugger halts the CPU in the middle of the frame. Its timing is lost anyway
+            if self.break_reason.is_some() || self.hit_breakpoint() {
+                self.vip_cycles = 0;
+                break;
+            }
+
+            let pc = self.cpu.get_pc();
+            let cycles = match self.get_next_instruction() {
+                Some(inst) => {
+                    let x = match inst {
+                        Instructions::Draw(reg, _, _) => self.cpu.get_vx(reg),
+                        _ => 0,
+                    };
+                    let skipped = self.run_instruction(inst);
+                    timing::vip_cycles(&inst, skipped, x)
                 }
+                // Unknown instructions are not run, but the interpreter still spends time fetching them
+                None => VIP_FETCH_CYCLES,
+            };
+            self.end_instruction(pc);
+            self.vip_cycles -= cycles as i32;
+        }
+        self.tick_timers();
+    }
+
+    /// Ticks the delay and sound timers. Should be called at a rate of 60 Hz.
+    /// This is the vertical blank, so a program waiting for it resumes
+    pub fn tick_timers(&mut self) {
+        self.cpu.tick_timers();
+        self.waiting_vblank = false;
+    }
+
+    /// Returns true if the program drew a sprite with the display wait quirk and waits for the next vertical blank
+    pub fn is_waiting_vblank(&self) -> bool {
+        self.waiting_vblank
+    }
+
+    /// Returns the screen coordinates, row by row. Each value holds a bit per plane the pixel is lit on, bit 0 for the
+    /// first plane and bit 1 for the second, so it ranges from 0 to 3. Programs that only use the first plane, as
+    /// every program before XO-CHIP does, only give 0 (pixel off) and 1 (pixel on)
+    pub fn get_framebuffer(&self) -> &[u8] {
+        self.display.get_coords()
+    }
+
+    /// Returns the width and height of the framebuffer
+    pub fn get_resolution(&self) -> (usize, usize) {
+        (self.display.get_width(), self.display.get_height())
+    }
+
+    /// Returns true if the program has exited
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Returns the RPL user flags. There are 8 of them, or 16 in XO-CHIP mode
+    pub fn get_flags(&self) -> &[u8] {
+        &self.flags[..self.flag_count()]
+    }
 
-                // Ticks the timers and updates display
-                self.cpu.tick_timers();
-                self.display.draw();
+    /// Sets the RPL user flags, such as the ones saved by a previous run. Extra values are ignored
+    pub fn set_flags(&mut self, flags: &[u8]) {
+        for (flag, value) in self.flags.iter_mut().zip(flags) {
+            *flag = *value;
+        }
+    }
+
+    /// Returns true if the program wrote to the RPL user flags since the last call
+    pub fn take_flags_changed(&mut self) -> bool {
+        let changed = self.flags_changed;
+        self.flags_changed = false;
+        changed
+    }
+
+    /// Returns the amount of RPL user flags available to the program
+    fn flag_count(&self) -> usize {
+        if self.xo_chip {
+            FLAG_COUNT
+        } else {
+            SCHIP_FLAG_COUNT
+        }
+    }
+
+    /// Returns the SHA-1 of the loaded ROM as a hex string. Empty if no ROM was loaded
+    pub fn get_rom_hash(&self) -> &str {
+        &self.rom_hash
+    }
+
+    /// Returns the XO-CHIP audio pattern, if a program loaded one, along with its playback rate in samples per second
+    pub fn get_audio_pattern(&self) -> Option<([u8; AUDIO_PATTERN_SIZE], f32)> {
+        let rate = 4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0);
+        self.audio_pattern.map(|pattern| (pattern, rate))
+    }
+
+    /// Returns true if the buzzer should be beeping
+    pub fn is_beeping(&self) -> bool {
+        self.cpu.get_st() > 1
+    }
+
+    /// Puts a key of the keypad (0x0 to 0xF) down. Resumes a program waiting for a key press
+    pub fn press_key(&mut self, key: u8) {
+        if key >= KEY_COUNT || self.keypad.is_pressed(key) {
+            return;
+        }
+        self.keypad.press(key);
+
+        if self.waiting_key.is_some() {
+            if self.quirks.wait_key_release {
+                self.wait_pressed |= 1 << key;
+            } else {
+                self.resume_key_wait(key);
             }
-            // Waits for the clock to catch up
-            sleep(period);
         }
     }
 
-    /// Loads ROM to RAM memory
-    pub fn load_rom(&mut self, rom: &[u8]) {
+    /// Releases a key of the keypad (0x0 to 0xF). Resumes a program waiting for a key release
+    pub fn release_key(&mut self, key: u8) {
+        if key >= KEY_COUNT || !self.keypad.is_pressed(key) {
+            return;
+        }
+        self.keypad.release(key);
+
+        if self.waiting_key.is_some() && self.wait_pressed & 1 << key != 0 {
+            self.resume_key_wait(key);
+        }
+    }
+
+    /// Returns true if the program waits for a key (Fx0A)
+    pub fn is_waiting_key(&self) -> bool {
+        self.waiting_key.is_some()
+    }
+
+    /// Ends the wait of Fx0A, handing it the given key
+    fn resume_key_wait(&mut self, key: u8) {
+        if let Some(reg) = self.waiting_key.take() {
+            self.cpu.set_vx(reg, key);
+        }
+        self.wait_pressed = 0;
+        // The key is stored by Fx0A, which PC already moved past
+        self.check_watches(self.cpu.get_pc().wrapping_sub(2));
+    }
+
+    /// Returns the keypad, which tells which keys are down
+    pub fn get_keypad(&self) -> &Keypad {
+        &self.keypad
+    }
+
+    /// Returns the value of the Vx register
+    pub fn get_vx(&self, x: Register) -> u8 {
+        self.cpu.get_vx(x)
+    }
+
+    /// Returns the value of the I register
+    pub fn get_i(&self) -> Address {
+        self.cpu.get_i()
+    }
+
+    /// Returns the value of Program Counter (PC)
+    pub fn get_pc(&self) -> Address {
+        self.cpu.get_pc()
+    }
+
+    /// Returns the value of the Delay Timer (DT)
+    pub fn get_dt(&self) -> u8 {
+        self.cpu.get_dt()
+    }
+
+    /// Returns the value of the Sound Timer (ST)
+    pub fn get_st(&self) -> u8 {
+        self.cpu.get_st()
+    }
+
+    /// Returns the value of the Stack Pointer (SP), which is also the amount of addresses on the stack
+    pub fn get_sp(&self) -> usize {
+        self.cpu.get_sp()
+    }
+
+    /// Returns the return addresses on the stack, from the bottom to the top
+    pub fn get_stack(&self) -> &[Address] {
+        self.cpu.get_stack()
+    }
+
+    /// Returns `len` bytes of memory starting at the given address. The program does not notice the read.
+    /// Reads past the end of memory wrap around to the start, and at most the whole memory is returned
+    pub fn read_memory(&self, addr: Address, len: usize) -> Vec<u8> {
+        let memory_size = if self.xo_chip {
+            MEMORY_SIZE
+        } else {
+            ORIGINAL_MEMORY_SIZE
+        };
+        (0..len.min(memory_size))
+            .map(|j| {
+                self.ram
+                    .peek_byte(self.wrap_address(addr.wrapping_add(j as Address)))
+            })
+            .collect()
+    }
+
+    /// Decodes the instruction at the given address without running it. Returns None if the opcode is unknown.
+    /// The XO-CHIP instructions are only known in XO-CHIP mode
+    pub fn get_instruction_at(&self, addr: Address) -> Option<Instructions> {
+        if !self.xo_chip {
+            return Instructions::new(self.read_word(addr)).filter(|inst| !inst.is_xo_chip());
+        }
+        // Only needed by instructions that span two words
+        let next = self.read_word(addr.wrapping_add(2));
+        Instructions::new_long(self.read_word(addr), next)
+    }
+
+    /// Halts the CPU before it runs the instruction at the given address
+    pub fn add_breakpoint(&mut self, addr: Address) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Removes a breakpoint. Returns false if there was none at that address
+    pub fn remove_breakpoint(&mut self, addr: Address) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// Returns the addresses of the breakpoints, in ascending order
+    pub fn get_breakpoints(&self) -> Vec<Address> {
+        let mut breakpoints: Vec<Address> = self.breakpoints.iter().copied().collect();
+        breakpoints.sort_unstable();
+        breakpoints
+    }
+
+    /// Halts the CPU right after an instruction accesses the given range of memory
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.ram.add_watchpoint(watchpoint);
+    }
+
+    /// Removes the watchpoint with the given index. Returns false if there is none
+    pub fn remove_watchpoint(&mut self, index: usize) -> bool {
+        self.ram.remove_watchpoint(index)
+    }
+
+    /// Returns the watchpoints, in the order they were added
+    pub fn get_watchpoints(&self) -> &[Watchpoint] {
+        self.ram.get_watchpoints()
+    }
+
+    /// Halts the CPU right after an instruction changes a register so that the condition becomes true
+    pub fn add_condition(&mut self, condition: Condition) {
+        self.cpu.add_condition(condition);
+    }
+
+    /// Removes the condition with the given index. Returns false if there is none
+    pub fn remove_condition(&mut self, index: usize) -> bool {
+        self.cpu.remove_condition(index)
+    }
+
+    /// Returns the conditions, in the order they were added
+    pub fn get_conditions(&self) -> &[Condition] {
+        self.cpu.get_conditions()
+    }
+
+    /// Returns why the CPU is halted for the debugger, or None if it is running
+    pub fn get_break(&self) -> Option<BreakReason> {
+        self.break_reason
+    }
+
+    /// Halts the CPU before the next instruction
+    pub fn break_now(&mut self) {
+        self.break_reason = Some(BreakReason::Pause);
+    }
+
+    /// Resumes the CPU after it was halted for the debugger
+    pub fn resume(&mut self) {
+        if self.break_reason.take().is_some() {
+            self.resume_pc = Some(self.cpu.get_pc());
+        }
+    }
+
+    /// Resumes the CPU for a single instruction
+    pub fn step(&mut self) {
+        self.stepping = true;
+        self.resume();
+    }
+
+    /// Like `step()`, except that a subroutine call runs until the subroutine returns
+    pub fn step_over(&mut self) {
+        let pc = self.cpu.get_pc();
+        match self.get_instruction_at(pc) {
+            Some(Instructions::Call(_)) => {
+                self.step_over = Some((pc.wrapping_add(2), self.cpu.get_sp()));
+                self.resume();
+            }
+            _ => self.step(),
+        }
+    }
+
+    /// Halts the CPU if it reached a breakpoint or the end of a step over. Returns true if it did
+    fn hit_breakpoint(&mut self) -> bool {
+        let pc = self.cpu.get_pc();
+        // The instruction the CPU resumed on was already stopped at
+        if self.resume_pc.take() == Some(pc) {
+            return false;
+        }
+
+        if let Some((addr, sp)) = self.step_over {
+            // Recursive calls reach the same address deeper in the stack
+            if addr == pc && self.cpu.get_sp() <= sp {
+                self.step_over = None;
+                self.break_reason = Some(BreakReason::Step);
+                return true;
+            }
+        }
+        if self.breakpoints.contains(&pc) {
+            self.break_reason = Some(BreakReason::Breakpoint(pc));
+            return true;
+        }
+        false
+    }
+
+    /// Halts the CPU after the instruction at `pc` if it hit a watchpoint or a condition, or if it was run by `step()`
+    fn end_instruction(&mut self, pc: Address) {
+        self.check_watches(pc);
+        if self.stepping {
+            self.stepping = false;
+            if self.break_reason.is_none() {
+                self.break_reason = Some(BreakReason::Step);
+            }
+        }
+    }
+
+    /// Halts the CPU if the instruction at `pc` accessed a watched address or made a condition true
+    fn check_watches(&mut self, pc: Address) {
+        let watch_hit = self.ram.take_watch_hit();
+        let condition_hit = self.cpu.take_condition_hit();
+        let inst = self.get_instruction_at(pc);
+
+        if let Some((addr, access)) = watch_hit {
+            self.break_reason = Some(BreakReason::Watchpoint {
+                addr,
+                access,
+                pc,
+                inst,
+            });
+        } else if let Some(condition) = condition_hit {
+            self.break_reason = Some(BreakReason::Condition {
+                condition,
+                pc,
+                inst,
+            });
+        }
+    }
+
+    /// Sets the value of the Vx register. Changes made from outside do not trigger the debugger conditions
+    pub fn set_vx(&mut self, x: Register, value: u8) {
+        self.cpu.set_vx(x, value);
+        self.cpu.take_condition_hit();
+    }
+
+    /// Sets the value of the I register. Changes made from outside do not trigger the debugger conditions
+    pub fn set_i(&mut self, addr: Address) {
+        self.cpu.set_i(addr);
+        self.cpu.take_condition_hit();
+    }
+
+    /// Sets the value of Program Counter (PC)
+    pub fn set_pc(&mut self, addr: Address) {
+        self.cpu.set_pc(addr);
+    }
+
+    /// Sets the value of the Stack Pointer (SP). It is kept within the stack
+    pub fn set_sp(&mut self, sp: usize) {
+        self.cpu.set_sp(sp);
+    }
+
+    /// Sets the value of the Delay Timer (DT)
+    pub fn set_dt(&mut self, value: u8) {
+        self.cpu.set_delay_timer(value);
+    }
+
+    /// Sets the value of the Sound Timer (ST)
+    pub fn set_st(&mut self, value: u8) {
+        self.cpu.set_sound_timer(value);
+    }
+
+    /// Writes bytes to memory starting at the given address. The watchpoints are not triggered
+    pub fn write_memory(&mut self, addr: Address, bytes: &[u8]) {
+        for (j, byte) in bytes.iter().enumerate() {
+            self.ram.poke_byte(addr.wrapping_add(j as Address), *byte);
+        }
+    }
+
+    /// Returns a snapshot of the whole machine in the save state format.
+    /// The ROM hash and the quirks are included, so the state can only be loaded back with the same ROM
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+
+        state.write_sized_bytes(self.rom_hash.as_bytes());
+        state.write_u8(self.quirks.to_bits());
+        state.write_bool(self.xo_chip);
+        self.cpu.save_state(&mut state);
+        self.ram.save_state(&mut state);
+        self.display.save_state(&mut state);
+        state.write_bytes(&self.flags);
+        state.write_bool(self.exited);
+        match &self.audio_pattern {
+            Some(pattern) => {
+                state.write_bool(true);
+                state.write_bytes(pattern);
+            }
+            None => state.write_bool(false),
+        }
+        state.write_u8(self.pitch);
+        self.random.save_state(&mut state);
+        match self.waiting_key {
+            Some(reg) => {
+                state.write_bool(true);
+                state.write_u8(reg);
+            }
+            None => state.write_bool(false),
+        }
+        state.write_u16(self.wait_pressed);
+
+        state.into_inner()
+    }
+
+    /// Restores a snapshot produced by `save_state()`. Fails if it is invalid or if it was taken with a different ROM.
+    /// Nothing changes if it fails
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new(data)?;
+
+        let rom_hash = String::from_utf8_lossy(state.read_sized_bytes()?).to_string();
+        if !self.rom_hash.is_empty() && rom_hash != self.rom_hash {
+            return Err("Save state was taken with a different ROM".to_string());
+        }
+
+        // The snapshot is restored on a new machine first, so a broken one leaves this machine untouched
+        let mut machine = Chip8::new();
+        machine.rom_hash = rom_hash;
+        machine.quirks = Quirks::from_bits(state.read_u8()?);
+        machine.xo_chip = state.read_bool()?;
+        machine.cpu.load_state(&mut state)?;
+        machine.ram.load_state(&mut state)?;
+        machine.display.load_state(&mut state)?;
+        machine.flags.copy_from_slice(state.read_bytes(FLAG_COUNT)?);
+        machine.exited = state.read_bool()?;
+        machine.audio_pattern = if state.read_bool()? {
+            let mut pattern = [0; AUDIO_PATTERN_SIZE];
+            pattern.copy_from_slice(state.read_bytes(AUDIO_PATTERN_SIZE)?);
+            Some(pattern)
+        } else {
+            None
+        };
+        machine.pitch = state.read_u8()?;
+        machine.random.load_state(&mut state)?;
+        machine.waiting_key = if state.read_bool()? {
+            Some(state.read_u8()? & 0xF)
+        } else {
+            None
+        };
+        machine.wait_pressed = state.read_u16()?;
+
+        // Input and the debugger settings are not part of the snapshot
+        machine.keypad = self.keypad;
+        machine.breakpoints = std::mem::take(&mut self.breakpoints);
+        for watchpoint in self.ram.get_watchpoints() {
+            machine.ram.add_watchpoint(*watchpoint);
+        }
+        for condition in self.cpu.get_conditions() {
+            machine.cpu.add_condition(*condition);
+        }
+        *self = machine;
+
+        Ok(())
+    }
+
+    /// Loads ROM to RAM memory. Fails if it does not fit in the memory of the current mode: 4 KiB, or 64 KiB in
+    /// XO-CHIP mode, so XO-CHIP must be enabled first
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), String> {
+        let memory_size = if self.xo_chip {
+            MEMORY_SIZE
+        } else {
+            ORIGINAL_MEMORY_SIZE
+        };
+        let max_size = memory_size - PROGRAM_START as usize;
+        if rom.len() > max_size {
+            return Err(format!(
+                "The ROM is {} bytes, but at most {} fit in memory",
+                rom.len(),
+                max_size
+            ));
+        }
+
         for (i, byte) in rom.iter().enumerate() {
-            self.ram.write_byte(PROGRAM_START + i as Address, *byte);
+            self.ram.poke_byte(PROGRAM_START + i as Address, *byte);
         }
+        self.rom_hash = rom_hash(rom);
+        Ok(())
     }
 
     /// Returns the next instruction to be executed
-    fn get_next_instruction(&mut self) -> Option<Instructions> {
-        let curr_pc = self.cpu.get_pc();
+    fn get_next_instruction(&self) -> Option<Instructions> {
+        self.get_instruction_at(self.cpu.get_pc())
+    }
+
+    /// Reads the word (two bytes, most significant first) at the given address.
+    /// Used to fetch instructions, which does not trigger the watchpoints
+    fn read_word(&self, addr: Address) -> u16 {
         // Get MSB
-        let ms_byte = self.ram.read_byte(curr_pc);
+        let ms_byte = self.ram.peek_byte(self.wrap_address(addr));
         // Get LSB
-        let ls_byte = self.ram.read_byte(curr_pc + 1);
-        let opcode: u16 = ((ms_byte as u16) << 8) + ls_byte as u16;
+        let ls_byte = self.ram.peek_byte(self.wrap_address(addr.wrapping_add(1)));
+        ((ms_byte as u16) << 8) + ls_byte as u16
+    }
 
-        Instructions::new(opcode)
+    /// Returns the address the program actually reaches. Outside XO-CHIP mode there are only 4 KiB of memory, and
+    /// addresses past its end wrap around to the start
+    fn wrap_address(&self, addr: Address) -> Address {
+        if self.xo_chip {
+            addr
+        } else {
+            addr % ORIGINAL_MEMORY_SIZE as Address
+        }
+    }
+
+    /// Skips the instruction that follows the current one if the condition holds, and returns whether it did.
+    /// In XO-CHIP mode, F000 NNNN is skipped as a whole
+    fn skip_next_instruction_if(&mut self, condition: bool) -> bool {
+        if condition {
+            let next = self.cpu.get_pc().wrapping_add(2);
+            if self.xo_chip && self.read_word(next) == 0xF000 {
+                self.cpu.skip_instruction();
+            }
+            self.cpu.skip_instruction();
+        }
+        condition
     }
 
-    /// Runs the next instruction
-    pub fn run_next_instruction(&mut self) {
+    /// Runs the next instruction and returns it. Returns None if the opcode could not be decoded, if the program has exited,
+    /// if it waits for the vertical blank or for a key or if the CPU is halted for the debugger
+    pub fn run_next_instruction(&mut self) -> Option<Instructions> {
+        if self.exited
+            || self.waiting_vblank
+            || self.waiting_key.is_some()
+            || self.break_reason.is_some()
+        {
+            return None;
+        }
+        if self.hit_breakpoint() {
+            return None;
+        }
+
+        let pc = self.cpu.get_pc();
         let next_inst = self.get_next_instruction();
 
         if let Some(inst) = next_inst {
             self.run_instruction(inst);
         }
+        self.end_instruction(pc);
+
+        next_inst
     }
 
-    /// This function receives a single instruction and properly executes it (according to CHIP8 techinical reference). Used by `run_next_instruction()`
-    fn run_instruction(&mut self, inst: Instructions) {
+    /// This function receives a single instruction and properly executes it (according to CHIP8 techinical reference). Used by `run_next_instruction()`.
+    /// Returns true if it was a skip instruction that skipped the next one
+    fn run_instruction(&mut self, inst: Instructions) -> bool {
+        let mut skipped = false;
         match inst {
+            Instructions::ScrollDown(n) => self.display.scroll_down(n as usize),
+            Instructions::ScrollUp(n) => self.display.scroll_up(n as usize),
             Instructions::ClearDisplay => self.display.clear(),
             Instructions::Return => self.cpu.subroutine_return(),
+            Instructions::ScrollRight => self.display.scroll_right(4),
+            Instructions::ScrollLeft => self.display.scroll_left(4),
+            Instructions::Exit => {
+                self.exited = true;
+                // PC is left pointing at the exit instruction
+                return false;
+            }
+            Instructions::LowRes => self.display.set_hires(false),
+            Instructions::HighRes => self.display.set_hires(true),
             Instructions::Jump(addr) => self.cpu.jump(addr),
             Instructions::Call(addr) => self.cpu.call(addr),
             Instructions::SkipIfEqualsByte(reg, byte) => {
-                if self.cpu.get_vx(reg) == byte {
-                    self.cpu.skip_instruction();
-                }
+                skipped = self.skip_next_instruction_if(self.cpu.get_vx(reg) == byte)
             }
             Instructions::SkipIfNotEqualsByte(reg, byte) => {
-                if self.cpu.get_vx(reg) != byte {
-                    self.cpu.skip_instruction();
-                }
+                skipped = self.skip_next_instruction_if(self.cpu.get_vx(reg) != byte)
             }
             Instructions::SkipIfEquals(reg1, reg2) => {
-                if self.cpu.get_vx(reg1) == self.cpu.get_vx(reg2) {
-                    self.cpu.skip_instruction();
+                skipped =
+                    self.skip_next_instruction_if(self.cpu.get_vx(reg1) == self.cpu.get_vx(reg2))
+            }
+            Instructions::CopyRangeMemory(reg1, reg2) => {
+                let curr_i = self.cpu.get_i();
+                for (j, reg) in register_range(reg1, reg2).enumerate() {
+                    self.ram.write_byte(
+                        self.wrap_address(curr_i.wrapping_add(j as u16)),
+                        self.cpu.get_vx(reg),
+                    );
+                }
+            }
+            Instructions::SetRangeMemory(reg1, reg2) => {
+                let curr_i = self.cpu.get_i();
+                for (j, reg) in register_range(reg1, reg2).enumerate() {
+                    let byte = self
+                        .ram
+                        .read_byte(self.wrap_address(curr_i.wrapping_add(j as u16)));
+                    self.cpu.set_vx(reg, byte);
                 }
             }
             Instructions::SetRegisterByte(reg, byte) => self.cpu.set_vx(reg, byte),
             Instructions::AddByte(reg, byte) => self.cpu.add_vx(reg, byte),
             Instructions::SetRegister(reg1, reg2) => self.cpu.set_vx(reg1, self.cpu.get_vx(reg2)),
-            Instructions::And(reg1, reg2) => self
-                .cpu
-                .set_vx(reg1, self.cpu.get_vx(reg1) & self.cpu.get_vx(reg2)),
-            Instructions::Or(reg1, reg2) => self
-                .cpu
-                .set_vx(reg1, self.cpu.get_vx(reg1) | self.cpu.get_vx(reg2)),
-            Instructions::Xor(reg1, reg2) => self
-                .cpu
-                .set_vx(reg1, self.cpu.get_vx(reg1) ^ self.cpu.get_vx(reg2)),
+            Instructions::And(reg1, reg2) => {
+                self.cpu
+                    .set_vx(reg1, self.cpu.get_vx(reg1) & self.cpu.get_vx(reg2));
+                self.reset_vf();
+            }
+            Instructions::Or(reg1, reg2) => {
+                self.cpu
+                    .set_vx(reg1, self.cpu.get_vx(reg1) | self.cpu.get_vx(reg2));
+                self.reset_vf();
+            }
+            Instructions::Xor(reg1, reg2) => {
+                self.cpu
+                    .set_vx(reg1, self.cpu.get_vx(reg1) ^ self.cpu.get_vx(reg2));
+                self.reset_vf();
+            }
             Instructions::Add(reg1, reg2) => self.cpu.add(reg1, reg2),
             Instructions::Sub(reg1, reg2) => self.cpu.sub(reg1, reg2),
-            Instructions::ShiftRight(reg) => self.cpu.shift_right(reg),
+            Instructions::ShiftRight(reg1, reg2) => {
+                let src = if self.quirks.shift_uses_vy {
+                    reg2
+                } else {
+                    reg1
+                };
+                self.cpu.shift_right(reg1, src);
+            }
             Instructions::ReverseSub(reg1, reg2) => self.cpu.sub(reg2, reg1),
-            Instructions::ShiftLeft(reg) => self.cpu.shift_left(reg),
+            Instructions::ShiftLeft(reg1, reg2) => {
+                let src = if self.quirks.shift_uses_vy {
+                    reg2
+                } else {
+                    reg1
+                };
+                self.cpu.shift_left(reg1, src);
+            }
             Instructions::SkipIfNotEquals(reg1, reg2) => {
-                if self.cpu.get_vx(reg1) != self.cpu.get_vx(reg2) {
-                    self.cpu.skip_instruction();
-                }
+                skipped =
+                    self.skip_next_instruction_if(self.cpu.get_vx(reg1) != self.cpu.get_vx(reg2))
             }
             Instructions::SetI(addr) => self.cpu.set_i(addr),
-            Instructions::JumpPlusV0(addr) => self.cpu.jump(addr + self.cpu.get_vx(0x0) as u16),
-            Instructions::SetRandAnd(reg, byte) => self.cpu.set_vx(reg, byte & random::<u8>()),
+            Instructions::SetLongI(addr) => {
+                self.cpu.set_i(addr);
+                // The operand word is skipped here, the instruction itself below
+                self.cpu.skip_instruction();
+            }
+            Instructions::JumpPlusV0(addr) => {
+                // With the quirk, the register is given by the most significant digit of the address
+                let reg = if self.quirks.jump_uses_vx {
+                    (addr >> 8) as Register
+                } else {
+                    0x0
+                };
+                self.cpu.jump(addr + self.cpu.get_vx(reg) as u16);
+            }
+            Instructions::SetRandAnd(reg, byte) => {
+                let random = self.random.next_byte(&self.ram);
+                self.cpu.set_vx(reg, byte & random)
+            }
             Instructions::Draw(reg1, reg2, n) => {
-                let curr_i = self.cpu.get_i();
                 let x = self.cpu.get_vx(reg1) as usize;
                 let y = self.cpu.get_vx(reg2) as usize;
 
-                self.cpu.set_vx(0xF, 0);
-                for j in 0..n {
-                    let byte: u8 = self.ram.read_byte(curr_i + j as u16);
-                    for k in 0..8 {
-                        let idx =
-                            (ORIGINAL_WIDTH * (y + j as usize) + x + k as usize) % COORD_LENGTH;
-                        let bit_before = self.display.coord_at(idx);
-                        let bit_after = bit_before ^ (byte >> (7 - k) & 0x01);
-                        self.display.set_coord(idx, bit_after);
-
-                        if (bit_after != bit_before) && bit_before == 1 {
-                            self.cpu.set_vx(0xF, 1);
-                        }
-                    }
+                // A height of 0 means a 16x16 sprite
+                let collisions = if n == 0 {
+                    self.draw_sprite(x, y, 16, 2)
+                } else {
+                    self.draw_sprite(x, y, n as usize, 1)
+                };
+
+                // In hires mode, SUPER-CHIP reports the amount of rows that collided instead of a flag
+                if self.display.is_hires() {
+                    self.cpu.set_vx(0xF, collisions);
+                } else {
+                    self.cpu.set_vx(0xF, (collisions > 0) as u8);
                 }
-                self.display.map_pixels();
+
+                // The original interpreter draws during the vertical blank, so the program waits for the next one
+                self.waiting_vblank = self.quirks.display_wait;
             }
             Instructions::SkipIfKeyPressed(reg) => {
-                if self.display.get_key_pressed() == Some(self.cpu.get_vx(reg)) {
-                    self.cpu.skip_instruction();
-                }
+                skipped =
+                    self.skip_next_instruction_if(self.keypad.is_pressed(self.cpu.get_vx(reg)))
             }
             Instructions::SkipIfKeyNotPressed(reg) => {
-                if self.display.get_key_pressed() != Some(self.cpu.get_vx(reg)) {
-                    self.cpu.skip_instruction();
+                skipped =
+                    self.skip_next_instruction_if(!self.keypad.is_pressed(self.cpu.get_vx(reg)))
+            }
+            Instructions::SelectPlanes(n) => self.display.set_planes(n),
+            Instructions::LoadAudioPattern => {
+                let curr_i = self.cpu.get_i();
+                let mut pattern = [0; AUDIO_PATTERN_SIZE];
+                for (j, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self
+                        .ram
+                        .read_byte(self.wrap_address(curr_i.wrapping_add(j as u16)));
                 }
+                self.audio_pattern = Some(pattern);
             }
+            Instructions::SetPitch(reg) => self.pitch = self.cpu.get_vx(reg),
             Instructions::SetToDelayTimer(reg) => self.cpu.set_vx(reg, self.cpu.get_dt()),
             Instructions::WaitKeyPress(reg) => {
-                while {
-                    self.display.update();
-                    let key = self.display.get_key_pressed();
-                    if let Some(pressed) = key {
-                        self.cpu.set_vx(reg, pressed);
-                    }
-
-                    // Loop exit condition
-                    key == None
-                } {
-                    sleep(Duration::from_millis(1));
-                }
+                // The CPU halts until a key is pressed, or released with the wait key release quirk.
+                // Timers and the display keep running meanwhile
+                self.waiting_key = Some(reg);
+                self.wait_pressed = 0;
             }
             Instructions::SetDelayTimer(reg) => self.cpu.set_dt(reg),
             Instructions::SetSoundTimer(reg) => self.cpu.set_st(reg),
-            Instructions::AddRegisterI(reg) => self
-                .cpu
-                .set_i((self.cpu.get_i() + self.cpu.get_vx(reg) as u16) % 0x1000),
+            Instructions::AddRegisterI(reg) => {
+                let sum = self.cpu.get_i().wrapping_add(self.cpu.get_vx(reg) as u16);
+                // XO-CHIP addresses 64 KiB, everything else 4 KiB
+                self.cpu.set_i(self.wrap_address(sum));
+            }
             Instructions::SetSpriteI(reg) => self.cpu.set_sprite_i(self.cpu.get_vx(reg)),
+            Instructions::SetBigSpriteI(reg) => self.cpu.set_big_sprite_i(self.cpu.get_vx(reg)),
             Instructions::BCDRepresentation(reg) => {
                 let curr_i = self.cpu.get_i();
                 let value = self.cpu.get_vx(reg);
@@ -259,24 +931,468 @@ impl Chip8 {
                 let second_digit = (value % 100) / 10;
                 let third_digit = value % 10;
 
-                self.ram.write_byte(curr_i, first_digit);
-                self.ram.write_byte(curr_i + 1, second_digit);
-                self.ram.write_byte(curr_i + 2, third_digit);
+                self.ram.write_byte(self.wrap_address(curr_i), first_digit);
+                self.ram
+                    .write_byte(self.wrap_address(curr_i.wrapping_add(1)), second_digit);
+                self.ram
+                    .write_byte(self.wrap_address(curr_i.wrapping_add(2)), third_digit);
             }
             Instructions::CopyRegistersMemory(reg) => {
                 let curr_i = self.cpu.get_i();
                 for j in 0..=reg {
-                    self.ram.write_byte(curr_i + j as u16, self.cpu.get_vx(j));
+                    self.ram.write_byte(
+                        self.wrap_address(curr_i.wrapping_add(j as u16)),
+                        self.cpu.get_vx(j),
+                    );
+                }
+                if self.quirks.load_store_increments_i {
+                    self.cpu.set_i(curr_i.wrapping_add(reg as u16 + 1));
+                } else if self.quirks.load_store_increments_i_by_x {
+                    self.cpu.set_i(curr_i.wrapping_add(reg as u16));
                 }
             }
             Instructions::SetRegistersMemory(reg) => {
                 let curr_i = self.cpu.get_i();
                 for j in 0..=reg {
-                    self.cpu.set_vx(j, self.ram.read_byte(curr_i + j as u16));
+                    self.cpu.set_vx(
+                        j,
+                        self.ram
+                            .read_byte(self.wrap_address(curr_i.wrapping_add(j as u16))),
+                    );
+                }
+                if self.quirks.load_store_increments_i {
+                    self.cpu.set_i(curr_i.wrapping_add(reg as u16 + 1));
+                } else if self.quirks.load_store_increments_i_by_x {
+                    self.cpu.set_i(curr_i.wrapping_add(reg as u16));
+                }
+            }
+            Instructions::SaveFlags(reg) => {
+                // Registers past the last flag are ignored
+                let last = (reg as usize).min(self.flag_count() - 1) as Register;
+                for j in 0..=last {
+                    self.flags[j as usize] = self.cpu.get_vx(j);
+                }
+                self.flags_changed = true;
+            }
+            Instructions::LoadFlags(reg) => {
+                let last = (reg as usize).min(self.flag_count() - 1) as Register;
+                for j in 0..=last {
+                    self.cpu.set_vx(j, self.flags[j as usize]);
                 }
             }
         }
         // Next instruction
         self.cpu.skip_instruction();
+        skipped
+    }
+
+    /// Draws a sprite from memory starting at the address of i, with the given height in rows and width in bytes,
+    /// at coordinate (x, y). Returns the amount of rows where a pixel was turned off (plus, in hires mode, the rows clipped at the bottom)
+    /// When both planes are selected (XO-CHIP), the sprite for the second plane follows the one for the first plane in memory
+    fn draw_sprite(&mut self, x: usize, y: usize, height: usize, bytes_per_row: usize) -> u8 {
+        let mut addr = self.cpu.get_i();
+        let width = self.display.get_width();
+        let screen_height = self.display.get_height();
+        // The starting position always wraps around
+        let x = x % width;
+        let y = y % screen_height;
+
+        let mut collided_rows = vec![false; height];
+        let mut clipped_rows = 0;
+        for plane in 0..PLANE_COUNT {
+            let plane_bit = 1 << plane;
+            if self.display.get_planes() & plane_bit == 0 {
+                continue;
+            }
+
+            for (j, collided) in collided_rows.iter_mut().enumerate() {
+                let row = y + j;
+                let row_addr = addr;
+                addr = addr.wrapping_add(bytes_per_row as Address);
+
+                // Rows past the bottom edge are either clipped or wrapped to the top
+                if row >= screen_height && self.quirks.clip_sprites {
+                    if plane == 0 || self.display.get_planes() == plane_bit {
+                        clipped_rows += 1;
+                    }
+                    continue;
+                }
+
+                for b in 0..bytes_per_row {
+                    let byte: u8 = self
+                        .ram
+                        .read_byte(self.wrap_address(row_addr.wrapping_add(b as Address)));
+                    for k in 0..8 {
+                        // Same goes for columns past the right edge
+                        let col = x + b * 8 + k;
+                        if col >= width && self.quirks.clip_sprites {
+                            break;
+                        }
+
+                        if byte >> (7 - k) & 0x01 == 0 {
+                            continue;
+                        }
+
+                        let idx = width * (row % screen_height) + col % width;
+                        let before = self.display.coord_at(idx);
+                        self.display.set_coord(idx, before ^ plane_bit);
+
+                        if before & plane_bit != 0 {
+                            *collided = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        let collisions = collided_rows.iter().filter(|collided| **collided).count();
+        // SUPER-CHIP counts clipped rows as collisions in hires mode
+        if self.display.is_hires() {
+            (collisions + clipped_rows) as u8
+        } else {
+            collisions as u8
+        }
+    }
+
+    /// Sets vf to 0 if the vf reset quirk is enabled. Used by the logical instructions
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.cpu.set_vx(0xF, 0);
+        }
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Chip8 {
+        Chip8::new()
+    }
+}
+
+/// Returns the registers from reg1 to reg2 (inclusive), in descending order if reg1 is greater than reg2
+fn register_range(reg1: Register, reg2: Register) -> Box<dyn Iterator<Item = Register>> {
+    if reg1 <= reg2 {
+        Box::new(reg1..=reg2)
+    } else {
+        Box::new((reg2..=reg1).rev())
+    }
+}
+
+/// Returns the SHA-1 of a ROM as a hex string, as used to look it up in the ROM database
+pub fn rom_hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::STATE_VERSION;
+
+    #[test]
+    fn load_rom_checks_size() {
+        let mut chip8 = Chip8::new();
+        let max = ORIGINAL_MEMORY_SIZE - PROGRAM_START as usize;
+        assert!(chip8.load_rom(&vec![0; max]).is_ok());
+        assert!(chip8.load_rom(&vec![0; max + 1]).is_err());
+
+        chip8.set_xo_chip(true);
+        let max = MEMORY_SIZE - PROGRAM_START as usize;
+        assert!(chip8.load_rom(&vec![0; max]).is_ok());
+        assert!(chip8.load_rom(&vec![0; max + 1]).is_err());
+    }
+
+    /// Returns a machine that ran the first `count` instructions of the given program
+    fn ran(xo_chip: bool, rom: &[u8], count: usize) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.set_xo_chip(xo_chip);
+        chip8.load_rom(rom).unwrap();
+        for _ in 0..count {
+            chip8.run_next_instruction();
+        }
+        chip8
+    }
+
+    #[test]
+    fn memory_wraps_outside_xo_chip() {
+        // v0 := 1, v1 := 2, v2 := 3, v3 := 4, i := 0xFFE, save v3
+        let rom = [
+            0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x63, 0x04, 0xAF, 0xFE, 0xF3, 0x55,
+        ];
+        let chip8 = ran(false, &rom, 6);
+        assert_eq!(chip8.read_memory(0xFFE, 2), [1, 2]);
+        assert_eq!(chip8.read_memory(0x000, 2), [3, 4]);
+        assert_eq!(chip8.read_memory(0x1000, 2), [3, 4]);
+        assert_eq!(chip8.read_memory(0, 0x10000).len(), 0x1000);
+
+        let chip8 = ran(true, &rom, 6);
+        assert_eq!(chip8.read_memory(0xFFE, 4), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn xo_chip_instructions_need_xo_chip() {
+        // scroll-up 1, plane 3, audio, pitch := v0, save v0 - v1, load v0 - v1
+        for opcode in [
+            [0x00, 0xD1],
+            [0xF3, 0x01],
+            [0xF0, 0x02],
+            [0xF0, 0x3A],
+            [0x50, 0x12],
+            [0x50, 0x13],
+        ]
+        .iter()
+        {
+            let mut chip8 = Chip8::new();
+            chip8.load_rom(opcode).unwrap();
+            assert_eq!(
+                chip8.get_instruction_at(PROGRAM_START),
+                None,
+                "{:02X?}",
+                opcode
+            );
+            chip8.set_xo_chip(true);
+            assert!(
+                chip8.get_instruction_at(PROGRAM_START).is_some(),
+                "{:02X?}",
+                opcode
+            );
+        }
+    }
+
+    #[test]
+    fn planes() {
+        // plane 2, i := 0x214, sprite v0 v0 1, plane 3, i := 0x215, sprite v0 v0 1, plane 1, clear
+        let mut rom = vec![
+            0xF2, 0x01, 0xA2, 0x14, 0xD0, 0x01, 0xF3, 0x01, 0xA2, 0x15, 0xD0, 0x01, 0xF1, 0x01,
+            0x00, 0xE0,
+        ];
+        rom.resize(0x14, 0);
+        // Sprite of the second plane, then sprite of both planes: one row for the first, one for the second
+        rom.extend_from_slice(&[0xC0, 0xA0, 0xF0]);
+
+        let mut chip8 = ran(true, &rom, 3);
+        assert_eq!(chip8.get_framebuffer()[..4], [2, 2, 0, 0]);
+        for _ in 0..3 {
+            chip8.run_next_instruction();
+        }
+        assert_eq!(chip8.get_framebuffer()[..4], [1, 0, 3, 2]);
+        assert_eq!(chip8.get_vx(0xF), 1);
+
+        // Clearing only affects the selected planes
+        for _ in 0..2 {
+            chip8.run_next_instruction();
+        }
+        assert_eq!(chip8.get_framebuffer()[..4], [0, 0, 2, 2]);
+    }
+
+    #[test]
+    fn audio_pattern() {
+        // i := 0x208, audio, v0 := 112, pitch := v0
+        let mut rom = vec![0xA2, 0x08, 0xF0, 0x02, 0x60, 0x70, 0xF0, 0x3A];
+        let pattern: Vec<u8> = (0..AUDIO_PATTERN_SIZE as u8).collect();
+        rom.extend_from_slice(&pattern);
+
+        let chip8 = ran(true, &rom, 1);
+        assert_eq!(chip8.get_audio_pattern(), None);
+        let chip8 = ran(true, &rom, 2);
+        let (loaded, rate) = chip8.get_audio_pattern().unwrap();
+        assert_eq!(loaded[..], pattern[..]);
+        assert_eq!(rate, 4000.0);
+        // 48 steps of pitch double the rate
+        let chip8 = ran(true, &rom, 4);
+        assert_eq!(chip8.get_audio_pattern().unwrap().1, 8000.0);
+    }
+
+    #[test]
+    fn long_i_needs_xo_chip() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&[0xF0, 0x00, 0x12, 0x34]).unwrap();
+        assert_eq!(chip8.get_instruction_at(PROGRAM_START), None);
+
+        chip8.set_xo_chip(true);
+        assert_eq!(
+            chip8.get_instruction_at(PROGRAM_START),
+            Some(Instructions::SetLongI(0x1234))
+        );
+    }
+
+    /// Stores registers, timers, a sprite and memory, then clears two registers and loads them back from memory
+    const STATE_ROM: [u8; 26] = [
+        0x6A, 0x12, 0x6B, 0x34, 0xFA, 0x15, 0xFB, 0x18, 0xA2, 0x00, 0xD0, 0x14, 0xA3, 0x00, 0xFB,
+        0x55, 0x6A, 0x00, 0x6B, 0x00, 0xA3, 0x00, 0xFB, 0x65, 0x12, 0x18,
+    ];
+
+    /// Returns a machine that ran the first half of STATE_ROM, up to the point where it stored its registers
+    fn saved_machine() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&STATE_ROM).unwrap();
+        for _ in 0..8 {
+            chip8.run_next_instruction();
+        }
+        chip8
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let chip8 = saved_machine();
+        let state = chip8.save_state();
+
+        let mut restored = Chip8::new();
+        restored.load_rom(&STATE_ROM).unwrap();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!((restored.get_vx(0xA), restored.get_vx(0xB)), (0x12, 0x34));
+        assert_eq!(restored.get_pc(), 0x210);
+        assert_eq!(restored.get_i(), chip8.get_i());
+        assert_eq!((restored.get_dt(), restored.get_st()), (0x12, 0x34));
+        assert_eq!(restored.get_framebuffer(), chip8.get_framebuffer());
+        assert!(restored.get_framebuffer().contains(&1));
+
+        // The registers come back from the memory stored before the snapshot
+        for _ in 0..4 {
+            restored.run_next_instruction();
+        }
+        assert_eq!((restored.get_vx(0xA), restored.get_vx(0xB)), (0x12, 0x34));
+    }
+
+    #[test]
+    fn broken_states_are_rejected() {
+        let state = saved_machine().save_state();
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&STATE_ROM).unwrap();
+        let before = chip8.save_state();
+
+        let mut wrong_magic = state.clone();
+        wrong_magic[0] = b'X';
+        assert_eq!(
+            chip8.load_state(&wrong_magic),
+            Err("Not a save state".to_string())
+        );
+
+        let mut wrong_version = state.clone();
+        wrong_version[4..6].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        assert!(chip8.load_state(&wrong_version).is_err());
+
+        for len in [0, 3, 5, 6, 20, 100, state.len() / 2, state.len() - 1].iter() {
+            assert_eq!(
+                chip8.load_state(&state[..*len]).map_err(|_| ()),
+                Err(()),
+                "{} bytes",
+                len
+            );
+        }
+
+        let mut other = Chip8::new();
+        other.load_rom(&[0x00, 0xE0]).unwrap();
+        assert_eq!(
+            other.load_state(&state),
+            Err("Save state was taken with a different ROM".to_string())
+        );
+
+        // Failed loads leave the machine untouched
+        assert_eq!(chip8.save_state(), before);
+    }
+
+    /// Returns a machine that draws a sprite, then counts in v1 how many times it got past the draw.
+    /// The display wait quirk is enabled if `display_wait` is set
+    fn drawing_machine(display_wait: bool) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        let mut quirks = Quirks::modern();
+        quirks.display_wait = display_wait;
+        chip8.set_quirks(quirks);
+        chip8
+            .load_rom(&[0xA0, 0x00, 0xD0, 0x05, 0x71, 0x01, 0x12, 0x02])
+            .unwrap();
+        chip8
+    }
+
+    #[test]
+    fn display_wait_ends_frames() {
+        let mut chip8 = drawing_machine(true);
+        chip8.run_frame(100);
+        assert_eq!(chip8.get_vx(1), 0);
+        assert_eq!(chip8.get_pc(), 0x204);
+        for _ in 0..3 {
+            chip8.run_frame(100);
+        }
+        assert_eq!(chip8.get_vx(1), 3);
+
+        // A single instruction waits for the vertical blank too
+        chip8.run_next_instruction();
+        chip8.run_next_instruction();
+        chip8.run_next_instruction();
+        assert!(chip8.is_waiting_vblank());
+        assert_eq!(chip8.run_next_instruction(), None);
+        chip8.tick_timers();
+        assert!(!chip8.is_waiting_vblank());
+
+        let mut chip8 = drawing_machine(false);
+        chip8.run_frame(100);
+        assert_eq!(chip8.get_vx(1), 33);
+    }
+
+    #[test]
+    fn display_wait_ends_vip_frames() {
+        let mut chip8 = drawing_machine(true);
+        for _ in 0..4 {
+            chip8.run_vip_frame();
+        }
+        assert_eq!(chip8.get_vx(1), 3);
+
+        // Drawing the sprite takes 296 cycles, so several fit in a frame without the quirk
+        let mut chip8 = drawing_machine(false);
+        chip8.run_vip_frame();
+        assert!(chip8.get_vx(1) > 3);
+    }
+
+    /// Returns a machine that ran `v5 := key` (Fx0A) with the given wait key release quirk
+    fn waiting_machine(wait_key_release: bool, held: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        let mut quirks = Quirks::modern();
+        quirks.wait_key_release = wait_key_release;
+        chip8.set_quirks(quirks);
+        chip8.load_rom(&[0xF5, 0x0A, 0x12, 0x02]).unwrap();
+        for key in held {
+            chip8.press_key(*key);
+        }
+        chip8.run_frame(10);
+        assert!(chip8.is_waiting_key());
+        chip8
+    }
+
+    #[test]
+    fn key_wait_resumes_on_release() {
+        let mut chip8 = waiting_machine(true, &[]);
+        chip8.press_key(0x7);
+        chip8.run_frame(10);
+        assert!(chip8.is_waiting_key());
+        assert_eq!(chip8.get_pc(), 0x202);
+
+        chip8.press_key(0x3);
+        chip8.release_key(0x3);
+        assert!(!chip8.is_waiting_key());
+        assert_eq!(chip8.get_vx(5), 0x3);
+        chip8.run_frame(10);
+        assert_eq!(chip8.get_pc(), 0x202);
+    }
+
+    #[test]
+    fn key_wait_ignores_keys_held_before() {
+        let mut chip8 = waiting_machine(true, &[0x9]);
+        chip8.release_key(0x9);
+        assert!(chip8.is_waiting_key());
+
+        chip8.press_key(0x9);
+        chip8.run_frame(10);
+        assert!(chip8.is_waiting_key());
+        chip8.release_key(0x9);
+        assert!(!chip8.is_waiting_key());
+        assert_eq!(chip8.get_vx(5), 0x9);
+
+        // Without the quirk, only a new press resumes it
+        let mut chip8 = waiting_machine(false, &[0x9]);
+        chip8.run_frame(10);
+        assert!(chip8.is_waiting_key());
+        chip8.press_key(0xC);
+        assert!(!chip8.is_waiting_key());
+        assert_eq!(chip8.get_vx(5), 0xC);
     }
 }
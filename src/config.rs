@@ -0,0 +1,263 @@
+use crate::audio::{DEFAULT_VOLUME, TONE_FREQUENCY};
+use crate::chip8::INSTRUCTIONS_PER_FRAME;
+use crate::paths::config_dir;
+use crate::quirks::PROFILES;
+use crate::video::{PALETTE, WINDOW_SCALE};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml::Value;
+
+/// Names of the settings, as used in the configuration file and by `Config::set()`
+pub const SETTINGS: [&str; 8] = [
+    "scale",
+    "foreground",
+    "background",
+    "ipf",
+    "quirks",
+    "tone-frequency",
+    "volume",
+    "keymap",
+];
+
+/// User settings of the emulator. They are read from a TOML file, and the command line arguments override them:
+///
+/// ```toml
+/// scale = 10
+/// foreground = "#33FF66"
+/// background = "#101010"
+/// ipf = 30
+/// quirks = "cosmac-vip"
+/// tone-frequency = 440
+/// volume = 0.25
+/// keymap = "/home/user/keymap.toml"
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Window scale. The window is this many times bigger than the CHIP-8 screen
+    pub scale: usize,
+    /// Color of the pixels that are on, as 0xRRGGBB
+    pub foreground: u32,
+    /// Color of the pixels that are off, as 0xRRGGBB
+    pub background: u32,
+    /// Instructions run per frame
+    pub instructions_per_frame: u32,
+    /// Name of the quirks profile. See `quirks::PROFILES`
+    pub quirks: String,
+    /// Frequency of the beep (Hz)
+    pub tone_frequency: u32,
+    /// Volume of the sound, from 0.0 to 1.0
+    pub volume: f32,
+    /// Keymap file, if any. See `Keymap`
+    pub keymap: Option<PathBuf>,
+}
+
+impl Config {
+    /// Creates a configuration with the default settings
+    pub fn new() -> Config {
+        Config {
+            scale: WINDOW_SCALE,
+            foreground: PALETTE[1],
+            background: PALETTE[0],
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            quirks: "modern".to_string(),
+            tone_frequency: TONE_FREQUENCY,
+            volume: DEFAULT_VOLUME,
+            keymap: None,
+        }
+    }
+
+    /// Returns the path of the configuration file used when none is given: `config.toml` in the configuration directory
+    pub fn default_path() -> Option<PathBuf> {
+        Some(config_dir()?.join("config.toml"))
+    }
+
+    /// Reads a configuration file. See `parse()`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, String> {
+        let text = fs::read_to_string(path.as_ref())
+            .map_err(|err| format!("Could not read {}: {}", path.as_ref().display(), err))?;
+        Config::parse(&text)
+    }
+
+    /// Parses a configuration in TOML. Settings that are left out keep their default value
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let value: Value = text
+            .parse()
+            .map_err(|err| format!("Invalid configuration: {}", err))?;
+        let table = value
+            .as_table()
+            .ok_or_else(|| "Invalid configuration: expected a table".to_string())?;
+
+        let mut config = Config::new();
+        for (name, value) in table {
+            let value = match value {
+                Value::String(value) => value.clone(),
+                Value::Integer(value) => value.to_string(),
+                Value::Float(value) => value.to_string(),
+                _ => {
+                    return Err(format!(
+                        "Invalid configuration: {} has an unsupported type",
+                        name
+                    ))
+                }
+            };
+            config.set(name, &value)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Changes the setting with the given name. See SETTINGS
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let invalid = || format!("Invalid value for {}: {}", name, value);
+
+        match name {
+            "scale" => {
+                self.scale = value.parse().map_err(|_| invalid())?;
+                if self.scale == 0 {
+                    return Err(invalid());
+                }
+            }
+            "foreground" => self.foreground = parse_color(value).ok_or_else(invalid)?,
+            "background" => self.background = parse_color(value).ok_or_else(invalid)?,
+            "ipf" => {
+                self.instructions_per_frame = value.parse().map_err(|_| invalid())?;
+                if self.instructions_per_frame == 0 {
+                    return Err(invalid());
+                }
+            }
+            "quirks" => {
+                if !PROFILES.contains(&value) {
+                    return Err(invalid());
+                }
+                self.quirks = value.to_string();
+            }
+            "tone-frequency" => self.tone_frequency = value.parse().map_err(|_| invalid())?,
+            "volume" => {
+                self.volume = value.parse().map_err(|_| invalid())?;
+                if !(0.0..=1.0).contains(&self.volume) {
+                    return Err(invalid());
+                }
+            }
+            "keymap" => self.keymap = Some(PathBuf::from(value)),
+            _ => return Err(format!("Unknown setting: {}", name)),
+        }
+        Ok(())
+    }
+
+    /// Changes each of the given settings, in order. The command line arguments are applied this way on top of the file
+    pub fn set_all<'a, I>(&mut self, settings: I) -> Result<(), String>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        for (name, value) in settings {
+            self.set(name, value)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the palette of the screen: the background, the foreground and the two XO-CHIP plane colors
+    pub fn get_palette(&self) -> [u32; 4] {
+        [self.background, self.foreground, PALETTE[2], PALETTE[3]]
+    }
+
+    /// Returns the settings in the configuration file format
+    pub fn to_toml(&self) -> String {
+        let mut text = format!(
+            "scale = {}\nforeground = \"#{:06X}\"\nbackground = \"#{:06X}\"\nipf = {}\nquirks = \"{}\"\n\
+             tone-frequency = {}\nvolume = {}\n",
+            self.scale,
+            self.foreground,
+            self.background,
+            self.instructions_per_frame,
+            self.quirks,
+            self.tone_frequency,
+            self.volume,
+        );
+        if let Some(keymap) = &self.keymap {
+            text += &format!("keymap = {:?}\n", keymap.display().to_string());
+        }
+        text
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config::new()
+    }
+}
+
+/// Parses a color written as `#RRGGBB`, `RRGGBB` or `0xRRGGBB`
+pub(crate) fn parse_color(value: &str) -> Option<u32> {
+    let hex = value
+        .trim_start_matches('#')
+        .trim_start_matches("0x")
+        .trim_start_matches("0X");
+    if hex.len() != 6 {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str =
+        "scale = 4\nforeground = \"#33FF66\"\nipf = 30\nquirks = \"cosmac-vip\"\nvolume = 0.5\n";
+
+    #[test]
+    fn parses_settings() {
+        let config = Config::parse(FILE).unwrap();
+        assert_eq!(config.scale, 4);
+        assert_eq!(config.foreground, 0x33FF66);
+        assert_eq!(config.instructions_per_frame, 30);
+        assert_eq!(config.quirks, "cosmac-vip");
+        assert_eq!(config.volume, 0.5);
+        // Left out settings keep their default value
+        assert_eq!(config.background, Config::new().background);
+        assert_eq!(config.keymap, None);
+
+        assert_eq!(Config::parse("").unwrap(), Config::new());
+        assert_eq!(Config::parse(&config.to_toml()).unwrap(), config);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        for text in [
+            "scale = 0",
+            "ipf = 0",
+            "foreground = \"#12345\"",
+            "quirks = \"unknown\"",
+            "volume = 1.5",
+            "unknown = 1",
+            "scale = true",
+            "scale = ",
+        ] {
+            assert!(Config::parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn arguments_override_the_file() {
+        let mut config = Config::parse(FILE).unwrap();
+        config
+            .set_all(vec![
+                ("ipf", "100"),
+                ("background", "0x000080"),
+                ("foreground", "FFFFFF"),
+            ])
+            .unwrap();
+        assert_eq!(config.instructions_per_frame, 100);
+        assert_eq!(config.background, 0x000080);
+        assert_eq!(config.foreground, 0xFFFFFF);
+        // Settings that are not given keep the value of the file
+        assert_eq!(config.scale, 4);
+        assert_eq!(config.quirks, "cosmac-vip");
+
+        assert!(config
+            .set_all(vec![("scale", "2"), ("volume", "loud")])
+            .is_err());
+    }
+}
@@ -1,5 +1,8 @@
 use crate::chip8::PROGRAM_START;
 use crate::chip8::{Address, Register};
+use crate::debugger::{Condition, Target};
+use crate::memory::BIG_FONT_START;
+use crate::state::{StateReader, StateWriter};
 
 /// This struct holds all CPU registers
 struct Registers {
@@ -29,6 +32,10 @@ pub struct CPU {
     reg: Registers,
     /// The stack is used to store the address that the interpreter should return when done with a subroutine
     stack: Vec<Address>,
+    /// Conditions on the registers watched by the debugger
+    conditions: Vec<Condition>,
+    /// First condition that became true since the last call to `take_condition_hit()`
+    condition_hit: Option<Condition>,
 }
 
 impl CPU {
@@ -46,7 +53,12 @@ impl CPU {
         // 16 bytes means up to 16 levels of nested routines. This is the original value so I will be using it
         let stack: Vec<Address> = vec![0; 0x10];
 
-        CPU { reg, stack }
+        CPU {
+            reg,
+            stack,
+            conditions: vec![],
+            condition_hit: None,
+        }
     }
 
     /// Returns the value of the Vx register
@@ -56,12 +68,14 @@ impl CPU {
 
     /// Sets the value of the Vx register
     pub fn set_vx(&mut self, x: Register, byte: u8) {
+        let before = self.reg.vx[x as usize];
         self.reg.vx[x as usize] = byte;
+        self.watch(Target::V(x), before as u16, byte as u16);
     }
 
     /// Returns the value of the Vx register
     pub fn add_vx(&mut self, x: Register, byte: u8) {
-        self.reg.vx[x as usize] = self.reg.vx[x as usize].wrapping_add(byte);
+        self.set_vx(x, self.get_vx(x).wrapping_add(byte));
     }
 
     /// Returns the value of Program Counter (PC)
@@ -69,6 +83,11 @@ impl CPU {
         self.reg.pc
     }
 
+    /// Sets the value of PC
+    pub fn set_pc(&mut self, addr: Address) {
+        self.reg.pc = addr;
+    }
+
     /// Returns the value of the Delay Timer (DT)
     pub fn get_dt(&self) -> u8 {
         self.reg.dt
@@ -94,9 +113,75 @@ impl CPU {
         self.reg.st = self.get_vx(reg);
     }
 
+    /// Sets the value of DT to the given value instead of the value of a register
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.reg.dt = value;
+    }
+
+    /// Sets the value of ST to the given value instead of the value of a register
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.reg.st = value;
+    }
+
     /// Sets the value of I
     pub fn set_i(&mut self, addr: Address) {
+        let before = self.reg.i;
         self.reg.i = addr;
+        self.watch(Target::I, before, addr);
+    }
+
+    /// Watches a condition on a register. The first time it becomes true is kept until `take_condition_hit()` is called
+    pub fn add_condition(&mut self, condition: Condition) {
+        self.conditions.push(condition);
+    }
+
+    /// Removes the condition with the given index. Returns false if there is none
+    pub fn remove_condition(&mut self, index: usize) -> bool {
+        if index >= self.conditions.len() {
+            return false;
+        }
+        self.conditions.remove(index);
+        true
+    }
+
+    /// Returns the watched conditions, in the order they were added
+    pub fn get_conditions(&self) -> &[Condition] {
+        &self.conditions
+    }
+
+    /// Returns the first condition that became true since the last call, if any
+    pub fn take_condition_hit(&mut self) -> Option<Condition> {
+        self.condition_hit.take()
+    }
+
+    /// Records the first condition on a register that a change of its value made true
+    fn watch(&mut self, target: Target, before: u16, after: u16) {
+        if self.condition_hit.is_some() {
+            return;
+        }
+        self.condition_hit = self
+            .conditions
+            .iter()
+            .find(|condition| {
+                condition.target == target && !condition.holds(before) && condition.holds(after)
+            })
+            .copied();
+    }
+
+    /// Returns the value of the stack pointer, which is also the amount of addresses on the stack
+    pub fn get_sp(&self) -> usize {
+        self.reg.sp
+    }
+
+    /// Sets the value of the stack pointer. It is kept within the stack
+    pub fn set_sp(&mut self, sp: usize) {
+        self.reg.sp = sp.min(self.stack.len() - 1);
+    }
+
+    /// Returns the return addresses on the stack, from the bottom to the top
+    pub fn get_stack(&self) -> &[Address] {
+        // The first entry is never used, since sp is increased before pushing
+        &self.stack[1..=self.reg.sp]
     }
 
     /// Returns from a subroutine, updating the value of PC and SP
@@ -109,7 +194,7 @@ impl CPU {
     pub fn jump(&mut self, addr: Address) {
         // After the instruction is dealed with, pc will be incremented by 2
         // Therefore, it will land right at addr with the current value that pc is attributed
-        self.reg.pc = addr - 2;
+        self.reg.pc = addr.wrapping_sub(2);
     }
 
     /// Calls a subroutine at a given memory address
@@ -121,7 +206,7 @@ impl CPU {
 
     /// Updates the value of PC to skip current instruction
     pub fn skip_instruction(&mut self) {
-        self.reg.pc += 2;
+        self.reg.pc = self.reg.pc.wrapping_add(2);
     }
 
     /// Adds the value of two registers. If the result is greater than 255 VF is set to 1, otherwise 0
@@ -140,18 +225,20 @@ impl CPU {
         self.set_vx(reg1, ans);
     }
 
-    /// Shifts the value of reg to right
-    pub fn shift_right(&mut self, reg: Register) {
+    /// Shifts the value of src to right and stores it in reg
+    pub fn shift_right(&mut self, reg: Register, src: Register) {
+        let value = self.get_vx(src);
+        self.set_vx(reg, value >> 1);
         // Store lsb prior to shift
-        self.set_vx(0xF, self.get_vx(reg) & 0x01);
-        self.set_vx(reg, self.get_vx(reg) >> 1);
+        self.set_vx(0xF, value & 0x01);
     }
 
-    /// Shifts the value of reg to left
-    pub fn shift_left(&mut self, reg: Register) {
+    /// Shifts the value of src to left and stores it in reg
+    pub fn shift_left(&mut self, reg: Register, src: Register) {
+        let value = self.get_vx(src);
+        self.set_vx(reg, value << 1);
         // Store msb prior to shift
-        self.set_vx(0xF, self.get_vx(reg) >> 7);
-        self.set_vx(reg, self.get_vx(reg) << 1);
+        self.set_vx(0xF, value >> 7);
     }
 
     /// Sets the value of I to the address of the byte sprite.
@@ -160,6 +247,42 @@ impl CPU {
         self.set_i((byte * 5) as u16);
     }
 
+    /// Sets the value of I to the address of the big (SUPER-CHIP) byte sprite.
+    pub fn set_big_sprite_i(&mut self, byte: u8) {
+        // Each big sprite occupies 10 bytes of memory, placed in order right after the default ones
+        self.set_i(BIG_FONT_START + (byte & 0xF) as u16 * 10);
+    }
+
+    /// Writes the registers and the stack to a save state
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.reg.vx);
+        state.write_u16(self.reg.i);
+        state.write_u8(self.reg.dt);
+        state.write_u8(self.reg.st);
+        state.write_u16(self.reg.pc);
+        state.write_u8(self.reg.sp as u8);
+        for addr in &self.stack {
+            state.write_u16(*addr);
+        }
+    }
+
+    /// Restores the registers and the stack from a save state
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.reg.vx.copy_from_slice(state.read_bytes(0x10)?);
+        self.reg.i = state.read_u16()?;
+        self.reg.dt = state.read_u8()?;
+        self.reg.st = state.read_u8()?;
+        self.reg.pc = state.read_u16()?;
+        self.reg.sp = state.read_u8()? as usize;
+        if self.reg.sp >= self.stack.len() {
+            return Err("Invalid stack pointer in save state".to_string());
+        }
+        for addr in self.stack.iter_mut() {
+            *addr = state.read_u16()?;
+        }
+        Ok(())
+    }
+
     /// Updates the value of DT and ST
     pub fn tick_timers(&mut self) {
         if self.reg.dt != 0 {
@@ -170,3 +293,9 @@ impl CPU {
         }
     }
 }
+
+impl Default for CPU {
+    fn default() -> CPU {
+        CPU::new()
+    }
+}
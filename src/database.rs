@@ -0,0 +1,237 @@
+use crate::config::parse_color;
+use crate::paths::config_dir;
+use crate::quirks::Quirks;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+/// Database compiled into the emulator. See `database/programs.json`
+const BUNDLED: &str = include_str!("../database/programs.json");
+
+/// What the database knows about a ROM
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomInfo {
+    /// Title of the program
+    pub title: String,
+    /// Authors of the program
+    pub authors: Vec<String>,
+    /// Quirks profile matching the platform the ROM was written for, if it is one we support
+    pub profile: Option<String>,
+    /// Quirks of that platform, including the changes the ROM needs on top of it
+    pub quirks: Option<Quirks>,
+    /// Set if the ROM was written for XO-CHIP
+    pub xo_chip: bool,
+    /// Instructions per frame the ROM is meant to run at
+    pub tickrate: Option<u32>,
+    /// Colors of the pixels, as 0xRRGGBB and indexed by their value. Usually only background and foreground
+    pub colors: Vec<u32>,
+}
+
+/// Settings of known ROMs, keyed by their SHA-1. The format is the `programs.json` file of the community
+/// CHIP-8 database: a list of programs, each with its ROMs under `roms`
+pub struct Database {
+    /// Every program
+    programs: Vec<Value>,
+}
+
+impl Database {
+    /// Returns the database compiled into the emulator
+    pub fn bundled() -> Database {
+        Database::parse(BUNDLED).expect("Invalid bundled ROM database")
+    }
+
+    /// Returns the path of the user database, which is looked up before the bundled one:
+    /// `programs.json` in the configuration directory
+    pub fn user_path() -> Option<PathBuf> {
+        Some(config_dir()?.join("programs.json"))
+    }
+
+    /// Reads a database file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Database, String> {
+        let text = fs::read_to_string(path.as_ref())
+            .map_err(|err| format!("Could not read {}: {}", path.as_ref().display(), err))?;
+        Database::parse(&text)
+    }
+
+    /// Parses a database in the `programs.json` format
+    pub fn parse(text: &str) -> Result<Database, String> {
+        let value: Value =
+            serde_json::from_str(text).map_err(|err| format!("Invalid ROM database: {}", err))?;
+        match value {
+            Value::Array(programs) => Ok(Database { programs }),
+            _ => Err("Invalid ROM database: expected a list of programs".to_string()),
+        }
+    }
+
+    /// Returns what the database knows about the ROM with the given SHA-1, if anything
+    pub fn lookup(&self, rom_hash: &str) -> Option<RomInfo> {
+        self.programs.iter().find_map(|program| {
+            let rom = program.get("roms")?.get(rom_hash)?;
+            Some(rom_info(program, rom))
+        })
+    }
+}
+
+/// Gathers the settings of a ROM entry and of the program it belongs to
+fn rom_info(program: &Value, rom: &Value) -> RomInfo {
+    let title = program
+        .get("title")
+        .and_then(Value::as_str)
+        .unwrap_or("Unknown")
+        .to_string();
+    let authors = program
+        .get("authors")
+        .and_then(Value::as_array)
+        .map(|authors| {
+            authors
+                .iter()
+                .filter_map(Value::as_str)
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    // The first platform we support wins. The list is ordered by preference
+    let platform = rom
+        .get("platforms")
+        .and_then(Value::as_array)
+        .and_then(|platforms| {
+            platforms
+                .iter()
+                .filter_map(Value::as_str)
+                .find(|platform| platform_profile(platform).is_some())
+        });
+
+    let mut info = RomInfo {
+        title,
+        authors,
+        profile: None,
+        quirks: None,
+        xo_chip: platform == Some("xochip"),
+        tickrate: rom
+            .get("tickrate")
+            .and_then(Value::as_u64)
+            .map(|rate| rate as u32),
+        colors: rom
+            .get("colors")
+            .and_then(|colors| colors.get("pixels"))
+            .and_then(Value::as_array)
+            .map(|pixels| {
+                pixels
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .filter_map(parse_color)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    };
+
+    if let Some(platform) = platform {
+        let profile = platform_profile(platform).unwrap();
+        let mut quirks = Quirks::profile(profile).unwrap();
+        let changes = rom
+            .get("quirkyPlatforms")
+            .and_then(|quirky| quirky.get(platform));
+        if let Some(changes) = changes.and_then(Value::as_object) {
+            for (name, value) in changes {
+                if let Some(value) = value.as_bool() {
+                    apply_quirk(&mut quirks, name, value);
+                }
+            }
+        }
+        info.profile = Some(profile.to_string());
+        info.quirks = Some(quirks);
+    }
+
+    info
+}
+
+/// Returns the quirks profile matching a platform of the database, if it is one we support
+fn platform_profile(platform: &str) -> Option<&'static str> {
+    match platform {
+        "originalChip8" | "hybridVIP" => Some("cosmac-vip"),
+        "modernChip8" | "xochip" => Some("modern"),
+        "chip48" => Some("chip-48"),
+        "superchip1" | "superchip" => Some("super-chip"),
+        _ => None,
+    }
+}
+
+/// Applies a quirk of the database. Quirks we do not emulate are ignored
+fn apply_quirk(quirks: &mut Quirks, name: &str, value: bool) {
+    match name {
+        "shift" => quirks.shift_uses_vy = !value,
+        "memoryLeaveIUnchanged" => quirks.load_store_increments_i = !value,
+        "memoryIncrementByX" => quirks.load_store_increments_i_by_x = value,
+        "wrap" => quirks.clip_sprites = !value,
+        "jump" => quirks.jump_uses_vx = value,
+        "vblank" => quirks.display_wait = value,
+        "logic" => quirks.vf_reset = value,
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+
+    /// Entry in the format of the community database, for the ROM `00E0 1202`
+    const PROGRAMS: &str = r##"[
+        {
+            "title": "Clear Loop",
+            "authors": ["Someone"],
+            "roms": {
+                "HASH": {
+                    "platforms": ["megachip8", "superchip", "originalChip8"],
+                    "tickrate": 30,
+                    "quirkyPlatforms": { "superchip": { "wrap": true, "memoryIncrementByX": true } },
+                    "colors": { "pixels": ["#112233", "#445566"] }
+                }
+            }
+        },
+        {
+            "title": "XO Program",
+            "roms": { "0123456789abcdef0123456789abcdef01234567": { "platforms": ["xochip"] } }
+        }
+    ]"##;
+
+    #[test]
+    fn bundled_database_parses() {
+        Database::bundled();
+    }
+
+    #[test]
+    fn lookup_by_sha1() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&[0x00, 0xE0, 0x12, 0x02]).unwrap();
+        let hash = chip8.get_rom_hash().to_string();
+        let database = Database::parse(&PROGRAMS.replace("HASH", &hash)).unwrap();
+
+        let info = database.lookup(&hash).unwrap();
+        assert_eq!(info.title, "Clear Loop");
+        assert_eq!(info.authors, vec!["Someone".to_string()]);
+        // megachip8 is not supported, so superchip is the platform
+        assert_eq!(info.profile.as_deref(), Some("super-chip"));
+        let mut quirks = Quirks::super_chip();
+        quirks.clip_sprites = false;
+        quirks.load_store_increments_i_by_x = true;
+        assert_eq!(info.quirks, Some(quirks));
+        assert!(!info.xo_chip);
+        assert_eq!(info.tickrate, Some(30));
+        assert_eq!(info.colors, vec![0x112233, 0x445566]);
+
+        let info = database
+            .lookup("0123456789abcdef0123456789abcdef01234567")
+            .unwrap();
+        assert_eq!(info.profile.as_deref(), Some("modern"));
+        assert!(info.xo_chip);
+
+        assert_eq!(
+            database.lookup("ffffffffffffffffffffffffffffffffffffffff"),
+            None
+        );
+    }
+}
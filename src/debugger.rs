@@ -0,0 +1,756 @@
+use crate::chip8::{Address, Chip8, Register};
+use crate::disassembler::{self, Syntax};
+use crate::instructions::Instructions;
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+/// Bytes shown by the memory dump command when no length is given
+const DUMP_LENGTH: usize = 0x40;
+/// Bytes shown on each line of a memory dump
+const DUMP_ROW: usize = 0x10;
+
+/// Commands of the debugger, as shown by `help`
+const HELP: &str = "\
+break <addr>      b   Halt before running the instruction at addr
+delete <addr>     d   Remove the breakpoint at addr
+watch <addr>[-<end>] [r|w|rw]
+                  w   Halt after an instruction reads or writes (both by default) memory from addr to end
+unwatch <n>           Remove the watchpoint with the given number
+cond <reg> <op> <value>
+                      Halt after an instruction makes the condition true, such as V3 == 10 or I > E00
+uncond <n>            Remove the condition with the given number
+breakpoints       bl  List the breakpoints, the watchpoints and the conditions
+step              s   Run one instruction
+next              n   Run one instruction, running subroutine calls until they return
+continue          c   Resume the program
+registers         r   Show V0 to VF, I, PC, SP, the timers and the stack
+x <addr> [len]        Dump len bytes of memory starting at addr
+quit              q   Stop the emulator
+help              h   Show this help
+An empty line repeats the last command. Addresses and lengths are in hex, and labels of the symbol file can be
+given instead of addresses";
+
+/// Why the CPU was halted for the debugger
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakReason {
+    /// The CPU reached a breakpoint at the given address
+    Breakpoint(Address),
+    /// A step or a step over is done
+    Step,
+    /// The debugger asked the CPU to halt, such as when the emulator starts in debug mode
+    Pause,
+    /// The instruction at `pc` accessed a watched address. The CPU halts right after it
+    Watchpoint {
+        addr: Address,
+        access: Access,
+        pc: Address,
+        inst: Option<Instructions>,
+    },
+    /// The instruction at `pc` changed a register so that the condition became true. The CPU halts right after it
+    Condition {
+        condition: Condition,
+        pc: Address,
+        inst: Option<Instructions>,
+    },
+}
+
+/// Kinds of memory accesses a watchpoint halts on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    /// Returns true if a watchpoint of this kind halts on the given access, which is either Read or Write
+    pub fn matches(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::ReadWrite => write!(f, "access"),
+        }
+    }
+}
+
+/// Range of memory that halts the CPU when it is accessed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Watchpoint {
+    /// First address of the range
+    pub start: Address,
+    /// Last address of the range (inclusive)
+    pub end: Address,
+    /// Accesses that halt the CPU
+    pub access: Access,
+}
+
+impl Watchpoint {
+    /// Returns true if the watchpoint halts on the given access to the given address
+    pub fn matches(&self, addr: Address, access: Access) -> bool {
+        (self.start..=self.end).contains(&addr) && self.access.matches(access)
+    }
+}
+
+/// Register a condition looks at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    V(Register),
+    I,
+}
+
+/// Comparison operators of conditions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// Operators of conditions, in the order they are looked for when parsing
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Equal),
+    ("!=", Comparison::NotEqual),
+    ("<=", Comparison::LessOrEqual),
+    (">=", Comparison::GreaterOrEqual),
+    ("<", Comparison::Less),
+    (">", Comparison::Greater),
+];
+
+/// Condition on the value of a register, such as `V3 == 0x10` or `I > 0xE00`.
+/// The CPU halts when an instruction changes the register so that the condition becomes true
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    /// Register to look at
+    pub target: Target,
+    /// How its value is compared
+    pub comparison: Comparison,
+    /// Value it is compared to
+    pub value: u16,
+}
+
+impl Condition {
+    /// Parses a condition written as `<register> <operator> <value>`. The register is V0 to VF or I,
+    /// the operator one of `==`, `!=`, `<`, `<=`, `>` and `>=` and the value is in hex
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        let invalid = || format!("Invalid condition: {}", text);
+
+        let (position, symbol, comparison) = COMPARISONS
+            .iter()
+            .find_map(|(symbol, comparison)| {
+                text.find(symbol)
+                    .map(|position| (position, symbol, *comparison))
+            })
+            .ok_or_else(invalid)?;
+        let register = text[..position].trim().to_uppercase();
+        let value = text[position + symbol.len()..].trim();
+
+        let target = match register.as_str() {
+            "I" => Target::I,
+            _ => {
+                let x = register.strip_prefix('V').ok_or_else(invalid)?;
+                if x.len() != 1 {
+                    return Err(invalid());
+                }
+                Target::V(Register::from_str_radix(x, 16).map_err(|_| invalid())?)
+            }
+        };
+        let value =
+            u16::from_str_radix(value.trim_start_matches("0x"), 16).map_err(|_| invalid())?;
+
+        Ok(Condition {
+            target,
+            comparison,
+            value,
+        })
+    }
+
+    /// Returns true if the given value of the register satisfies the condition
+    pub fn holds(&self, value: u16) -> bool {
+        match self.comparison {
+            Comparison::Equal => value == self.value,
+            Comparison::NotEqual => value != self.value,
+            Comparison::Less => value < self.value,
+            Comparison::LessOrEqual => value <= self.value,
+            Comparison::Greater => value > self.value,
+            Comparison::GreaterOrEqual => value >= self.value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.target {
+            Target::V(x) => write!(f, "V{:X}", x)?,
+            Target::I => write!(f, "I")?,
+        }
+        let symbol = COMPARISONS
+            .iter()
+            .find(|(_, comparison)| *comparison == self.comparison)
+            .map(|(symbol, _)| *symbol)
+            .unwrap();
+        write!(f, " {} {:#X}", symbol, self.value)
+    }
+}
+
+/// Anything that takes over the machine when the CPU halts, such as the command line debugger or a GDB client
+pub trait DebugSession {
+    /// Called once per frame while the program runs. Can halt the CPU with `Chip8::break_now()`
+    fn poll(&mut self, _chip8: &mut Chip8) {}
+    /// Called when the CPU halts. Blocks until the program should resume, and returns false to stop the emulator
+    fn halted(&mut self, chip8: &mut Chip8) -> bool;
+}
+
+/// What the emulator should do after a debugger command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Keep reading commands. The CPU is still halted
+    Stay,
+    /// Run the program until the CPU halts again
+    Resume,
+    /// Stop the emulator
+    Quit,
+}
+
+/// Labels of a program, read from the symbol file written by `chip8 assemble` or `chip8 octo build`: one label per
+/// line, after its address in hex
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Symbols {
+    labels: Vec<(String, Address)>,
+}
+
+impl Symbols {
+    /// Creates an empty set of labels
+    pub fn new() -> Symbols {
+        Symbols { labels: vec![] }
+    }
+
+    /// Parses a symbol file. Fails on the first line that is not an address followed by a label
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        let mut labels = vec![];
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let addr = words
+                .next()
+                .and_then(|addr| Address::from_str_radix(addr, 16).ok());
+            match (addr, words.next(), words.next()) {
+                (Some(addr), Some(name), None) => labels.push((name.to_string(), addr)),
+                _ => return Err(format!("Invalid symbol on line {}: {}", index + 1, line)),
+            }
+        }
+        Ok(Symbols { labels })
+    }
+
+    /// Returns the address of a label
+    pub fn get_address(&self, name: &str) -> Option<Address> {
+        self.labels
+            .iter()
+            .find(|(label, _)| label == name)
+            .map(|(_, addr)| *addr)
+    }
+
+    /// Returns the first label at an address
+    pub fn get_name(&self, addr: Address) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(_, label_addr)| *label_addr == addr)
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// Command line debugger. Commands are read from stdin whenever the CPU halts, and act on the machine through the
+/// breakpoint and stepping hooks of `Chip8`, so any frontend can drive it
+pub struct Debugger {
+    /// Last command run. An empty line runs it again
+    last_command: String,
+    /// Labels that can stand for addresses in commands
+    symbols: Symbols,
+}
+
+impl Debugger {
+    /// Creates a new debugger
+    pub fn new() -> Debugger {
+        Debugger {
+            last_command: String::new(),
+            symbols: Symbols::new(),
+        }
+    }
+
+    /// Sets the labels that can be given instead of addresses, and that are shown next to them
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Tells why the CPU halted, then reads commands from stdin until one resumes the program.
+    /// Returns false if the user quit or closed stdin
+    pub fn prompt(&mut self, chip8: &mut Chip8) -> bool {
+        match chip8.get_break() {
+            Some(BreakReason::Breakpoint(addr)) => println!("Breakpoint at {:#05X}", addr),
+            Some(BreakReason::Watchpoint {
+                addr,
+                access,
+                pc,
+                inst,
+            }) => println!(
+                "Watchpoint: {} of {:#05X} by {}",
+                access,
+                addr,
+                describe(chip8, &self.symbols, pc, inst)
+            ),
+            Some(BreakReason::Condition {
+                condition,
+                pc,
+                inst,
+            }) => {
+                println!(
+                    "Condition {} met by {}",
+                    condition,
+                    describe(chip8, &self.symbols, pc, inst)
+                )
+            }
+            _ => (),
+        }
+        println!(
+            "{}",
+            describe_instruction(chip8, &self.symbols, chip8.get_pc())
+        );
+
+        let stdin = io::stdin();
+        loop {
+            print!("(chip8) ");
+            io::stdout().flush().expect("Error writing to stdout");
+
+            let mut line = String::new();
+            if stdin
+                .lock()
+                .read_line(&mut line)
+                .expect("Error reading from stdin")
+                == 0
+            {
+                return false;
+            }
+            match self.run_command(chip8, &line) {
+                Ok(Action::Stay) => (),
+                Ok(Action::Resume) => return true,
+                Ok(Action::Quit) => return false,
+                Err(err) => println!("{}", err),
+            }
+        }
+    }
+
+    /// Runs a single command, printing its output. Fails if the command is invalid
+    pub fn run_command(&mut self, chip8: &mut Chip8, line: &str) -> Result<Action, String> {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => line.to_string(),
+        };
+        self.last_command = line.clone();
+
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(Action::Stay),
+        };
+        let args: Vec<&str> = words.collect();
+
+        match command {
+            "break" | "b" => {
+                let addr = parse_address(args.first(), &self.symbols)?;
+                chip8.add_breakpoint(addr);
+                println!("Breakpoint set at {:#05X}", addr);
+            }
+            "delete" | "d" => {
+                let addr = parse_address(args.first(), &self.symbols)?;
+                if !chip8.remove_breakpoint(addr) {
+                    return Err(format!("No breakpoint at {:#05X}", addr));
+                }
+            }
+            "watch" | "w" => {
+                let range = args.first().ok_or_else(|| "Missing address".to_string())?;
+                let (start, end) = match range.split_once('-') {
+                    Some((start, end)) => (
+                        parse_address(Some(&start), &self.symbols)?,
+                        parse_address(Some(&end), &self.symbols)?,
+                    ),
+                    None => {
+                        let addr = parse_address(Some(range), &self.symbols)?;
+                        (addr, addr)
+                    }
+                };
+                if end < start {
+                    return Err(format!("Invalid range: {}", range));
+                }
+                let access = match args.get(1).copied() {
+                    Some("r") => Access::Read,
+                    Some("w") => Access::Write,
+                    Some("rw") | None => Access::ReadWrite,
+                    Some(access) => {
+                        return Err(format!("Invalid access: {}. Expected r, w or rw", access))
+                    }
+                };
+                chip8.add_watchpoint(Watchpoint { start, end, access });
+                println!("Watchpoint {} set", chip8.get_watchpoints().len() - 1);
+            }
+            "unwatch" => {
+                let index = parse_index(args.first())?;
+                if !chip8.remove_watchpoint(index) {
+                    return Err(format!("No watchpoint {}", index));
+                }
+            }
+            "cond" => {
+                chip8.add_condition(Condition::parse(&args.join(" "))?);
+                println!("Condition {} set", chip8.get_conditions().len() - 1);
+            }
+            "uncond" => {
+                let index = parse_index(args.first())?;
+                if !chip8.remove_condition(index) {
+                    return Err(format!("No condition {}", index));
+                }
+            }
+            "breakpoints" | "bl" => {
+                for addr in chip8.get_breakpoints() {
+                    println!("{}", describe_instruction(chip8, &self.symbols, addr));
+                }
+                for (index, watchpoint) in chip8.get_watchpoints().iter().enumerate() {
+                    println!(
+                        "Watchpoint {}: {} of {:#05X}-{:#05X}",
+                        index, watchpoint.access, watchpoint.start, watchpoint.end
+                    );
+                }
+                for (index, condition) in chip8.get_conditions().iter().enumerate() {
+                    println!("Condition {}: {}", index, condition);
+                }
+            }
+            "step" | "s" => {
+                chip8.step();
+                return Ok(Action::Resume);
+            }
+            "next" | "n" => {
+                chip8.step_over();
+                return Ok(Action::Resume);
+            }
+            "continue" | "c" => {
+                chip8.resume();
+                return Ok(Action::Resume);
+            }
+            "registers" | "r" => print_registers(chip8),
+            "x" => {
+                let addr = parse_address(args.first(), &self.symbols)?;
+                let len = match args.get(1) {
+                    Some(len) => usize::from_str_radix(len.trim_start_matches("0x"), 16)
+                        .map_err(|_| format!("Invalid length: {}", len))?,
+                    None => DUMP_LENGTH,
+                };
+                print_memory(chip8, addr, len);
+            }
+            "quit" | "q" => return Ok(Action::Quit),
+            "help" | "h" => println!("{}", HELP),
+            _ => {
+                return Err(format!(
+                    "Unknown command: {}. Type help for a list of commands",
+                    command
+                ))
+            }
+        }
+
+        Ok(Action::Stay)
+    }
+}
+
+impl DebugSession for Debugger {
+    fn halted(&mut self, chip8: &mut Chip8) -> bool {
+        self.prompt(chip8)
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+/// Parses an address given as a label, or written in hex with or without a `0x` prefix
+fn parse_address(arg: Option<&&str>, symbols: &Symbols) -> Result<Address, String> {
+    let arg = arg.ok_or_else(|| "Missing address".to_string())?;
+    if let Some(addr) = symbols.get_address(arg) {
+        return Ok(addr);
+    }
+    Address::from_str_radix(arg.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("Invalid address: {}", arg))
+}
+
+/// Parses the number of a watchpoint or of a condition
+fn parse_index(arg: Option<&&str>) -> Result<usize, String> {
+    let arg = arg.ok_or_else(|| "Missing number".to_string())?;
+    arg.parse().map_err(|_| format!("Invalid number: {}", arg))
+}
+
+/// Returns the address and its label, the opcode and the decoded instruction at the given address, on one line
+fn describe_instruction(chip8: &Chip8, symbols: &Symbols, addr: Address) -> String {
+    describe(chip8, symbols, addr, chip8.get_instruction_at(addr))
+}
+
+/// Returns the address and its label, the opcode and the given instruction decoded from that address, on one line
+fn describe(chip8: &Chip8, symbols: &Symbols, addr: Address, inst: Option<Instructions>) -> String {
+    let bytes = chip8.read_memory(addr, 2);
+    let inst = match inst {
+        Some(inst) => disassembler::mnemonic(&inst, Syntax::Octo),
+        None => "unknown".to_string(),
+    };
+    let label = match symbols.get_name(addr) {
+        Some(name) => format!(" <{}>", name),
+        None => String::new(),
+    };
+    format!(
+        "{:#05X}{}: {:02X}{:02X}  {}",
+        addr, label, bytes[0], bytes[1], inst
+    )
+}
+
+/// Prints every register, the timers and the stack
+fn print_registers(chip8: &Chip8) {
+    for row in 0..2 {
+        let registers: Vec<String> = (row * 8..row * 8 + 8)
+            .map(|x| format!("V{:X}={:02X}", x, chip8.get_vx(x)))
+            .collect();
+        println!("{}", registers.join(" "));
+    }
+    println!(
+        "I={:04X} PC={:04X} SP={:X} DT={:02X} ST={:02X}",
+        chip8.get_i(),
+        chip8.get_pc(),
+        chip8.get_sp(),
+        chip8.get_dt(),
+        chip8.get_st()
+    );
+    let stack: Vec<String> = chip8
+        .get_stack()
+        .iter()
+        .map(|addr| format!("{:04X}", addr))
+        .collect();
+    println!("Stack: [{}]", stack.join(" "));
+}
+
+/// Prints memory in hex, DUMP_ROW bytes per line
+fn print_memory(chip8: &Chip8, addr: Address, len: usize) {
+    let bytes = chip8.read_memory(addr, len);
+    for (row, chunk) in bytes.chunks(DUMP_ROW).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+        println!(
+            "{:04X}: {}",
+            addr.wrapping_add((row * DUMP_ROW) as Address),
+            hex.join(" ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Calls a subroutine, then loops forever
+    const ROM: [u8; 12] = [
+        0x22, 0x08, // 200: call 208
+        0x60, 0x01, // 202: v0 := 1
+        0x70, 0x01, // 204: v0 += 1
+        0x12, 0x04, // 206: jump 204
+        0x61, 0x05, // 208: v1 := 5
+        0x00, 0xEE, // 20A: return
+    ];
+
+    /// Returns a machine running ROM and a debugger knowing its subroutine as `sub`
+    fn debug() -> (Chip8, Debugger) {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&ROM).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.set_symbols(Symbols::parse("208 sub\n").unwrap());
+        (chip8, debugger)
+    }
+
+    #[test]
+    fn breakpoints_halt_before_the_instruction() {
+        let (mut chip8, mut debugger) = debug();
+        assert_eq!(debugger.run_command(&mut chip8, "b 204"), Ok(Action::Stay));
+        assert_eq!(
+            debugger.run_command(&mut chip8, "break sub"),
+            Ok(Action::Stay)
+        );
+        assert_eq!(chip8.get_breakpoints(), vec![0x204, 0x208]);
+
+        chip8.run_frame(100);
+        assert_eq!(chip8.get_break(), Some(BreakReason::Breakpoint(0x208)));
+        assert_eq!(chip8.get_vx(1), 0);
+
+        assert_eq!(debugger.run_command(&mut chip8, "c"), Ok(Action::Resume));
+        chip8.run_frame(100);
+        assert_eq!(chip8.get_break(), Some(BreakReason::Breakpoint(0x204)));
+        assert_eq!((chip8.get_vx(0), chip8.get_vx(1)), (1, 5));
+
+        // The loop comes back to the breakpoint
+        debugger.run_command(&mut chip8, "c").unwrap();
+        chip8.run_frame(100);
+        assert_eq!(chip8.get_break(), Some(BreakReason::Breakpoint(0x204)));
+        assert_eq!(chip8.get_vx(0), 2);
+
+        debugger.run_command(&mut chip8, "d 204").unwrap();
+        assert!(debugger.run_command(&mut chip8, "d 204").is_err());
+        debugger.run_command(&mut chip8, "c").unwrap();
+        chip8.run_frame(100);
+        assert_eq!(chip8.get_break(), None);
+    }
+
+    #[test]
+    fn step_runs_one_instruction() {
+        let (mut chip8, mut debugger) = debug();
+        chip8.break_now();
+        chip8.run_frame(100);
+        assert_eq!(chip8.get_pc(), 0x200);
+
+        // Steps into the subroutine
+        assert_eq!(debugger.run_command(&mut chip8, "s"), Ok(Action::Resume));
+        chip8.run_frame(100);
+        assert_eq!(chip8.get_break(), Some(BreakReason::Step));
+        assert_eq!((chip8.get_pc(), chip8.get_sp()), (0x208, 1));
+
+        // An empty line steps again
+        assert_eq!(debugger.run_command(&mut chip8, "\n"), Ok(Action::Resume));
+        chip8.run_frame(100);
+        assert_eq!((chip8.get_pc(), chip8.get_vx(1)), (0x20A, 5));
+    }
+
+    #[test]
+    fn step_over_runs_calls() {
+        let (mut chip8, mut debugger) = debug();
+        chip8.break_now();
+        chip8.run_frame(100);
+
+        assert_eq!(debugger.run_command(&mut chip8, "n"), Ok(Action::Resume));
+        chip8.run_frame(100);
+        assert_eq!(chip8.get_break(), Some(BreakReason::Step));
+        assert_eq!((chip8.get_pc(), chip8.get_sp()), (0x202, 0));
+        assert_eq!(chip8.get_vx(1), 5);
+
+        // Other instructions are stepped
+        debugger.run_command(&mut chip8, "n").unwrap();
+        chip8.run_frame(100);
+        assert_eq!(chip8.get_break(), Some(BreakReason::Step));
+        assert_eq!((chip8.get_pc(), chip8.get_vx(0)), (0x204, 1));
+    }
+
+    #[test]
+    fn parses_commands() {
+        let (mut chip8, mut debugger) = debug();
+        for command in [
+            "b",
+            "b zz",
+            "b nowhere",
+            "w 210-200",
+            "w 200 x",
+            "unwatch 0",
+            "uncond x",
+            "cond V0",
+            "foo",
+        ] {
+            assert!(
+                debugger.run_command(&mut chip8, command).is_err(),
+                "{}",
+                command
+            );
+        }
+        assert_eq!(
+            debugger.run_command(&mut chip8, "w 0x300-0x30F r"),
+            Ok(Action::Stay)
+        );
+        assert_eq!(
+            debugger.run_command(&mut chip8, "watch sub"),
+            Ok(Action::Stay)
+        );
+        assert_eq!(
+            chip8.get_watchpoints(),
+            [
+                Watchpoint {
+                    start: 0x300,
+                    end: 0x30F,
+                    access: Access::Read
+                },
+                Watchpoint {
+                    start: 0x208,
+                    end: 0x208,
+                    access: Access::ReadWrite
+                },
+            ]
+        );
+        assert_eq!(
+            debugger.run_command(&mut chip8, "unwatch 0"),
+            Ok(Action::Stay)
+        );
+        assert_eq!(chip8.get_watchpoints().len(), 1);
+
+        assert_eq!(
+            debugger.run_command(&mut chip8, "cond v3 >= 10"),
+            Ok(Action::Stay)
+        );
+        assert_eq!(chip8.get_conditions()[0].to_string(), "V3 >= 0x10");
+        // Dumps past the end of memory stop there
+        assert_eq!(
+            debugger.run_command(&mut chip8, "x FFF FFFFFF"),
+            Ok(Action::Stay)
+        );
+        assert!(debugger.run_command(&mut chip8, "x 200 zz").is_err());
+        assert_eq!(debugger.run_command(&mut chip8, "q"), Ok(Action::Quit));
+        // A blank line repeats the last command
+        assert_eq!(debugger.run_command(&mut chip8, "   "), Ok(Action::Quit));
+    }
+
+    #[test]
+    fn parses_conditions() {
+        let condition = Condition::parse("VA != 0x1F").unwrap();
+        assert_eq!(condition.target, Target::V(0xA));
+        assert_eq!(condition.comparison, Comparison::NotEqual);
+        assert_eq!(condition.value, 0x1F);
+        assert!(condition.holds(0));
+        assert!(!condition.holds(0x1F));
+
+        let condition = Condition::parse("i<=E00").unwrap();
+        assert_eq!(
+            (condition.target, condition.comparison),
+            (Target::I, Comparison::LessOrEqual)
+        );
+        assert!(condition.holds(0xE00));
+        assert!(!condition.holds(0xE01));
+
+        for text in [
+            "V10 == 1",
+            "VG == 1",
+            "X == 1",
+            "V1 == ",
+            "V1 = 1",
+            "V1 == 10000",
+        ] {
+            assert!(Condition::parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parses_symbols() {
+        let symbols = Symbols::parse("200 main\n\n20A loop\n").unwrap();
+        assert_eq!(symbols.get_address("loop"), Some(0x20A));
+        assert_eq!(symbols.get_name(0x200), Some("main"));
+        assert_eq!(symbols.get_name(0x202), None);
+        assert!(Symbols::parse("200\n").is_err());
+        assert!(Symbols::parse("main 200\n").is_err());
+        assert!(Symbols::parse("200 main extra\n").is_err());
+    }
+}
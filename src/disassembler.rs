@@ -0,0 +1,393 @@
+use crate::chip8::{Address, PROGRAM_START};
+use crate::instructions::Instructions;
+use crate::instructions::Instructions::*;
+
+use std::collections::BTreeSet;
+
+/// Names of the syntaxes, as accepted by `Syntax::from_name()`
+pub const SYNTAXES: [&str; 2] = ["octo", "cowgod"];
+/// Most bytes shown on a single `.byte` line
+const BYTES_PER_LINE: usize = 8;
+
+/// Syntaxes instructions can be written in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    /// Octo's assembly language, such as `v0 := 0x12` or `sprite v0 v1 5`
+    Octo,
+    /// Mnemonics of Cowgod's Chip-8 Technical Reference, such as `LD V0, 0x12` or `DRW V0, V1, 5`
+    Cowgod,
+}
+
+impl Syntax {
+    /// Returns the syntax with the given name, if there is one. See SYNTAXES
+    pub fn from_name(name: &str) -> Option<Syntax> {
+        match name {
+            "octo" => Some(Syntax::Octo),
+            "cowgod" => Some(Syntax::Cowgod),
+            _ => None,
+        }
+    }
+}
+
+/// A line of disassembly: an instruction or some bytes that are not one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    /// Address of the first byte
+    pub addr: Address,
+    /// Bytes the line stands for
+    pub bytes: Vec<u8>,
+    /// Instruction or `.byte` directive
+    pub text: String,
+}
+
+/// Writes an instruction in the given syntax
+pub fn mnemonic(inst: &Instructions, syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Octo => octo(inst),
+        Syntax::Cowgod => cowgod(inst),
+    }
+}
+
+/// Writes an instruction in Octo's syntax
+fn octo(inst: &Instructions) -> String {
+    match *inst {
+        ScrollDown(n) => format!("scroll-down {}", n),
+        ScrollUp(n) => format!("scroll-up {}", n),
+        ClearDisplay => "clear".to_string(),
+        Return => "return".to_string(),
+        ScrollRight => "scroll-right".to_string(),
+        ScrollLeft => "scroll-left".to_string(),
+        Exit => "exit".to_string(),
+        LowRes => "lores".to_string(),
+        HighRes => "hires".to_string(),
+        Jump(addr) => format!("jump {:#05X}", addr),
+        Call(addr) => format!(":call {:#05X}", addr),
+        // Octo writes skips as the condition under which the next instruction runs
+        SkipIfEqualsByte(x, byte) => format!("if v{:x} != {:#04X} then", x, byte),
+        SkipIfNotEqualsByte(x, byte) => format!("if v{:x} == {:#04X} then", x, byte),
+        SkipIfEquals(x, y) => format!("if v{:x} != v{:x} then", x, y),
+        CopyRangeMemory(x, y) => format!("save v{:x} - v{:x}", x, y),
+        SetRangeMemory(x, y) => format!("load v{:x} - v{:x}", x, y),
+        SetRegisterByte(x, byte) => format!("v{:x} := {:#04X}", x, byte),
+        AddByte(x, byte) => format!("v{:x} += {:#04X}", x, byte),
+        SetRegister(x, y) => format!("v{:x} := v{:x}", x, y),
+        Or(x, y) => format!("v{:x} |= v{:x}", x, y),
+        And(x, y) => format!("v{:x} &= v{:x}", x, y),
+        Xor(x, y) => format!("v{:x} ^= v{:x}", x, y),
+        Add(x, y) => format!("v{:x} += v{:x}", x, y),
+        Sub(x, y) => format!("v{:x} -= v{:x}", x, y),
+        ShiftRight(x, y) => format!("v{:x} >>= v{:x}", x, y),
+        ReverseSub(x, y) => format!("v{:x} =- v{:x}", x, y),
+        ShiftLeft(x, y) => format!("v{:x} <<= v{:x}", x, y),
+        SkipIfNotEquals(x, y) => format!("if v{:x} == v{:x} then", x, y),
+        SetI(addr) => format!("i := {:#05X}", addr),
+        SetLongI(addr) => format!("i := long {:#06X}", addr),
+        JumpPlusV0(addr) => format!("jump0 {:#05X}", addr),
+        SetRandAnd(x, byte) => format!("v{:x} := random {:#04X}", x, byte),
+        Draw(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+        SkipIfKeyPressed(x) => format!("if v{:x} -key then", x),
+        SkipIfKeyNotPressed(x) => format!("if v{:x} key then", x),
+        SelectPlanes(n) => format!("plane {}", n),
+        LoadAudioPattern => "audio".to_string(),
+        SetPitch(x) => format!("pitch := v{:x}", x),
+        SetToDelayTimer(x) => format!("v{:x} := delay", x),
+        WaitKeyPress(x) => format!("v{:x} := key", x),
+        SetDelayTimer(x) => format!("delay := v{:x}", x),
+        SetSoundTimer(x) => format!("buzzer := v{:x}", x),
+        AddRegisterI(x) => format!("i += v{:x}", x),
+        SetSpriteI(x) => format!("i := hex v{:x}", x),
+        SetBigSpriteI(x) => format!("i := bighex v{:x}", x),
+        BCDRepresentation(x) => format!("bcd v{:x}", x),
+        CopyRegistersMemory(x) => format!("save v{:x}", x),
+        SetRegistersMemory(x) => format!("load v{:x}", x),
+        SaveFlags(x) => format!("saveflags v{:x}", x),
+        LoadFlags(x) => format!("loadflags v{:x}", x),
+    }
+}
+
+/// Writes an instruction with the mnemonics of Cowgod's technical reference. The extensions it does not cover
+/// get mnemonics in the same spirit
+fn cowgod(inst: &Instructions) -> String {
+    match *inst {
+        ScrollDown(n) => format!("SCD {}", n),
+        ScrollUp(n) => format!("SCU {}", n),
+        ClearDisplay => "CLS".to_string(),
+        Return => "RET".to_string(),
+        ScrollRight => "SCR".to_string(),
+        ScrollLeft => "SCL".to_string(),
+        Exit => "EXIT".to_string(),
+        LowRes => "LOW".to_string(),
+        HighRes => "HIGH".to_string(),
+        Jump(addr) => format!("JP {:#05X}", addr),
+        Call(addr) => format!("CALL {:#05X}", addr),
+        SkipIfEqualsByte(x, byte) => format!("SE V{:X}, {:#04X}", x, byte),
+        SkipIfNotEqualsByte(x, byte) => format!("SNE V{:X}, {:#04X}", x, byte),
+        SkipIfEquals(x, y) => format!("SE V{:X}, V{:X}", x, y),
+        CopyRangeMemory(x, y) => format!("LD [I], V{:X}-V{:X}", x, y),
+        SetRangeMemory(x, y) => format!("LD V{:X}-V{:X}, [I]", x, y),
+        SetRegisterByte(x, byte) => format!("LD V{:X}, {:#04X}", x, byte),
+        AddByte(x, byte) => format!("ADD V{:X}, {:#04X}", x, byte),
+        SetRegister(x, y) => format!("LD V{:X}, V{:X}", x, y),
+        Or(x, y) => format!("OR V{:X}, V{:X}", x, y),
+        And(x, y) => format!("AND V{:X}, V{:X}", x, y),
+        Xor(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+        Add(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+        Sub(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+        ShiftRight(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+        ReverseSub(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+        ShiftLeft(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+        SkipIfNotEquals(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+        SetI(addr) => format!("LD I, {:#05X}", addr),
+        SetLongI(addr) => format!("LD I, LONG {:#06X}", addr),
+        JumpPlusV0(addr) => format!("JP V0, {:#05X}", addr),
+        SetRandAnd(x, byte) => format!("RND V{:X}, {:#04X}", x, byte),
+        Draw(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        SkipIfKeyPressed(x) => format!("SKP V{:X}", x),
+        SkipIfKeyNotPressed(x) => format!("SKNP V{:X}", x),
+        SelectPlanes(n) => format!("PLANE {}", n),
+        LoadAudioPattern => "AUDIO".to_string(),
+        SetPitch(x) => format!("PITCH V{:X}", x),
+        SetToDelayTimer(x) => format!("LD V{:X}, DT", x),
+        WaitKeyPress(x) => format!("LD V{:X}, K", x),
+        SetDelayTimer(x) => format!("LD DT, V{:X}", x),
+        SetSoundTimer(x) => format!("LD ST, V{:X}", x),
+        AddRegisterI(x) => format!("ADD I, V{:X}", x),
+        SetSpriteI(x) => format!("LD F, V{:X}", x),
+        SetBigSpriteI(x) => format!("LD HF, V{:X}", x),
+        BCDRepresentation(x) => format!("LD B, V{:X}", x),
+        CopyRegistersMemory(x) => format!("LD [I], V{:X}", x),
+        SetRegistersMemory(x) => format!("LD V{:X}, [I]", x),
+        SaveFlags(x) => format!("LD R, V{:X}", x),
+        LoadFlags(x) => format!("LD V{:X}, R", x),
+    }
+}
+
+/// Decodes the instruction at the given offset of a ROM. Returns None if it is not one, or if the ROM ends before it does
+fn decode(rom: &[u8], offset: usize) -> Option<Instructions> {
+    let word = |offset: usize| Some((*rom.get(offset)? as u16) << 8 | *rom.get(offset + 1)? as u16);
+    let inst = Instructions::new_long(word(offset)?, word(offset + 2).unwrap_or(0))?;
+    if offset + inst.size() as usize > rom.len() {
+        return None;
+    }
+    Some(inst)
+}
+
+/// Returns a `.byte` line for the given bytes
+fn data_line(addr: Address, bytes: &[u8]) -> Line {
+    let values: Vec<String> = bytes.iter().map(|byte| format!("{:#04X}", byte)).collect();
+    Line {
+        addr,
+        bytes: bytes.to_vec(),
+        text: format!(".byte {}", values.join(" ")),
+    }
+}
+
+/// Disassembles a ROM loaded at PROGRAM_START word by word. Every word that decodes is taken for an instruction,
+/// even if it is sprite data, and the others become `.byte` lines
+pub fn disassemble(rom: &[u8], syntax: Syntax) -> Vec<Line> {
+    let mut lines = vec![];
+    let mut offset = 0;
+
+    while offset < rom.len() {
+        let addr = PROGRAM_START.wrapping_add(offset as Address);
+        match decode(rom, offset) {
+            Some(inst) => {
+                let size = inst.size() as usize;
+                lines.push(Line {
+                    addr,
+                    bytes: rom[offset..offset + size].to_vec(),
+                    text: mnemonic(&inst, syntax),
+                });
+                offset += size;
+            }
+            None => {
+                let end = (offset + 2).min(rom.len());
+                lines.push(data_line(addr, &rom[offset..end]));
+                offset = end;
+            }
+        }
+    }
+
+    lines
+}
+
+/// Disassembles a ROM loaded at PROGRAM_START by following the flow of the program from its first instruction:
+/// jumps, calls and both outcomes of skips. What is never reached, such as sprites, becomes `.byte` lines.
+/// Jumps through `jump0` cannot be followed, so the code they lead to is taken for data
+pub fn disassemble_recursive(rom: &[u8], syntax: Syntax) -> Vec<Line> {
+    // Offsets of the instructions found, and offsets left to look at
+    let mut code = BTreeSet::new();
+    let mut pending = vec![0];
+
+    while let Some(offset) = pending.pop() {
+        if code.contains(&offset) {
+            continue;
+        }
+        // The flow stops at anything that does not decode, including the end of the ROM
+        let inst = match decode(rom, offset) {
+            Some(inst) => inst,
+            None => continue,
+        };
+        code.insert(offset);
+        let next = offset + inst.size() as usize;
+        let target = |addr: Address| (addr as usize).checked_sub(PROGRAM_START as usize);
+
+        match inst {
+            Jump(addr) => pending.extend(target(addr)),
+            Call(addr) => {
+                pending.extend(target(addr));
+                pending.push(next);
+            }
+            SkipIfEqualsByte(..)
+            | SkipIfNotEqualsByte(..)
+            | SkipIfEquals(..)
+            | SkipIfNotEquals(..)
+            | SkipIfKeyPressed(_)
+            | SkipIfKeyNotPressed(_) => {
+                pending.push(next);
+                // The skipped instruction may be a long one
+                let skipped = decode(rom, next).map_or(2, |inst| inst.size() as usize);
+                pending.push(next + skipped);
+            }
+            Return | Exit | JumpPlusV0(_) => (),
+            _ => pending.push(next),
+        }
+    }
+
+    let mut lines = vec![];
+    let mut offset = 0;
+    while offset < rom.len() {
+        let addr = PROGRAM_START.wrapping_add(offset as Address);
+        if code.contains(&offset) {
+            let inst = decode(rom, offset).unwrap();
+            let size = inst.size() as usize;
+            lines.push(Line {
+                addr,
+                bytes: rom[offset..offset + size].to_vec(),
+                text: mnemonic(&inst, syntax),
+            });
+            offset += size;
+        } else {
+            // Data runs until the next instruction
+            let end = (offset..rom.len())
+                .take(BYTES_PER_LINE)
+                .find(|offset| code.contains(offset))
+                .unwrap_or_else(|| (offset + BYTES_PER_LINE).min(rom.len()));
+            lines.push(data_line(addr, &rom[offset..end]));
+            offset = end;
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(lines: &[Line]) -> Vec<(Address, &str)> {
+        lines
+            .iter()
+            .map(|line| (line.addr, line.text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn writes_both_syntaxes() {
+        let cases = [
+            (0x00E0, "clear", "CLS"),
+            (0x2345, ":call 0x345", "CALL 0x345"),
+            (0x3A12, "if va != 0x12 then", "SE VA, 0x12"),
+            (0x8AB6, "va >>= vb", "SHR VA, VB"),
+            (0xD125, "sprite v1 v2 5", "DRW V1, V2, 5"),
+            (0xF365, "load v3", "LD V3, [I]"),
+            (0x5123, "load v1 - v2", "LD V1-V2, [I]"),
+        ];
+        for (opcode, octo, cowgod) in cases.iter() {
+            let inst = Instructions::new(*opcode).unwrap();
+            assert_eq!(mnemonic(&inst, Syntax::Octo), *octo);
+            assert_eq!(mnemonic(&inst, Syntax::Cowgod), *cowgod);
+        }
+        assert_eq!(Syntax::from_name("cowgod"), Some(Syntax::Cowgod));
+        assert_eq!(Syntax::from_name("intel"), None);
+    }
+
+    #[test]
+    fn long_i_takes_four_bytes() {
+        // i := long 0x1234, clear
+        let rom = [0xF0, 0x00, 0x12, 0x34, 0x00, 0xE0];
+        for lines in [
+            disassemble(&rom, Syntax::Octo),
+            disassemble_recursive(&rom, Syntax::Octo),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                texts(lines),
+                [(0x200, "i := long 0x1234"), (0x204, "clear")]
+            );
+            assert_eq!(lines[0].bytes, [0xF0, 0x00, 0x12, 0x34]);
+        }
+        assert_eq!(
+            texts(&disassemble(&rom, Syntax::Cowgod))[0],
+            (0x200, "LD I, LONG 0x1234")
+        );
+
+        // Cut short by the end of the ROM
+        assert_eq!(
+            texts(&disassemble(&rom[..3], Syntax::Octo)),
+            [(0x200, ".byte 0xF0 0x00"), (0x202, ".byte 0x12")]
+        );
+    }
+
+    #[test]
+    fn follows_the_flow_around_data() {
+        let rom = [
+            0x22, 0x08, // 200: call 208
+            0x12, 0x0C, // 202: jump 20C
+            0x3C, 0x42, 0x42, 0x3C, // 204: sprite
+            0x60, 0x01, // 208: v0 := 1
+            0x00, 0xEE, // 20A: return
+            0x30, 0x01, // 20C: if v0 != 1 then
+            0x00, 0xE0, // 20E: clear
+            0x00, 0xFD, // 210: exit
+            0x60, 0x02, // 212: never reached
+        ];
+        assert_eq!(
+            texts(&disassemble_recursive(&rom, Syntax::Octo)),
+            [
+                (0x200, ":call 0x208"),
+                (0x202, "jump 0x20C"),
+                (0x204, ".byte 0x3C 0x42 0x42 0x3C"),
+                (0x208, "v0 := 0x01"),
+                (0x20A, "return"),
+                (0x20C, "if v0 != 0x01 then"),
+                (0x20E, "clear"),
+                (0x210, "exit"),
+                (0x212, ".byte 0x60 0x02"),
+            ]
+        );
+        // Word by word, the sprite is taken for code
+        assert_eq!(
+            disassemble(&rom, Syntax::Octo)[2].text,
+            "if vc != 0x42 then"
+        );
+    }
+
+    #[test]
+    fn ignores_targets_outside_the_rom() {
+        // jump 0x100, call 0x800, clear
+        let rom = [0x11, 0x00, 0x28, 0x00, 0x00, 0xE0];
+        assert_eq!(
+            texts(&disassemble_recursive(&rom, Syntax::Octo)),
+            [(0x200, "jump 0x100"), (0x202, ".byte 0x28 0x00 0x00 0xE0")]
+        );
+
+        // call 0x800, clear
+        let rom = [0x28, 0x00, 0x00, 0xE0];
+        assert_eq!(
+            texts(&disassemble_recursive(&rom, Syntax::Octo)),
+            [(0x200, ":call 0x800"), (0x202, "clear")]
+        );
+    }
+}
@@ -1,122 +1,300 @@
-extern crate minifb;
+use crate::chip8::{HIRES_HEIGHT, HIRES_WIDTH, ORIGINAL_HEIGHT, ORIGINAL_WIDTH};
+use crate::state::{StateReader, StateWriter};
 
-use crate::chip8::{ORIGINAL_HEIGHT, ORIGINAL_WIDTH, PIXEL_COLOR, WINDOW_SCALE};
-use minifb::{Window, WindowOptions};
+/// Number of bitplanes. XO-CHIP programs can draw on both, while every other program only uses the first one
+pub const PLANE_COUNT: usize = 2;
 
-/// Interface between user and the CHIP8
-/// It handles graphics and keyboard input
+/// The CHIP-8 screen. Holds the state of every pixel, either in the original 64x32 resolution (lores) or in the
+/// 128x64 resolution of SUPER-CHIP (hires)
+/// It does not draw anything on its own: frontends read the coordinates and present them however they see fit
 pub struct Display {
-    /// Buffer with pixel values of displayed window. The coordinates of coord are maped here according to WINDOW_SCALE
-    buffer: Vec<u32>,
-    /// Original pixel coordinates
+    /// Pixel coordinates, row by row. Bit 0 of each value is the pixel in the first plane and bit 1 the pixel in the
+    /// second plane, so values range from 0 (both off) to 3 (both on)
     coord: Vec<u8>,
-    /// Stack to keep track of changes made do the screen. This allows for much faster scaling of the original window size
-    changes_stack: Vec<usize>,
-    /// Window that displays the graphics and handles input (keyboard)
-    window: Window,
-    /// Window width. Product of ORIGINAL_WIDTH (64) and WINDOW_SCALE
-    window_width: usize,
-    /// Window height. Product of ORIGINAL_HEIGHT (32) and WINDOW_SCALE
-    window_height: usize,
+    /// Planes affected by drawing, clearing and scrolling. Bit 0 selects the first plane and bit 1 the second
+    planes: u8,
+    /// Current width in pixels
+    width: usize,
+    /// Current height in pixels
+    height: usize,
 }
 
 impl Display {
-
-    /// Creates a new display with size determined by the function parameters
-    pub fn new(width: usize, height: usize) -> Display {
-        let buffer = vec![0; width * height];
-        let coord = vec![0; 64 * 32];
-        let changes_stack = vec![];
-
-        let window = Window::new("CHIP-8 Emulator", width, height, WindowOptions::default())
-            .expect("Error creating window");
-
+    /// Creates a new lores display with all pixels turned off
+    pub fn new() -> Display {
         Display {
-            buffer,
-            coord,
-            changes_stack,
-            window,
-            window_width: width,
-            window_height: height,
+            coord: vec![0; ORIGINAL_WIDTH * ORIGINAL_HEIGHT],
+            planes: 0b01,
+            width: ORIGINAL_WIDTH,
+            height: ORIGINAL_HEIGHT,
         }
     }
 
+    /// Returns the planes affected by drawing, clearing and scrolling
+    pub fn get_planes(&self) -> u8 {
+        self.planes
+    }
+
+    /// Selects the planes affected by drawing, clearing and scrolling (XO-CHIP)
+    pub fn set_planes(&mut self, planes: u8) {
+        self.planes = planes & 0b11;
+    }
+
     /// Get coordinate at index
-    pub fn coord_at(&mut self, idx: usize) -> u8 {
+    pub fn coord_at(&self, idx: usize) -> u8 {
         self.coord[idx]
     }
 
     /// Set coordinate at index
     pub fn set_coord(&mut self, idx: usize, bit: u8) {
         self.coord[idx] = bit;
-        self.changes_stack.push(idx);
     }
 
-    /// Draws buffer to window
-    pub fn draw(&mut self) {
-        self.window
-            .update_with_buffer(&self.buffer, self.window_width, self.window_height)
-            .expect("Error drawing to window");
+    /// Returns all coordinates, row by row
+    pub fn get_coords(&self) -> &[u8] {
+        &self.coord
     }
 
-    /// Updates window
-    pub fn update(&mut self) {
-        self.window.update();
+    /// Returns the current width in pixels
+    pub fn get_width(&self) -> usize {
+        self.width
     }
 
-    /// Maps pixels to represent the original window in a higher scale
-    pub fn map_pixels(&mut self) {
-        while let Some(i) = self.changes_stack.pop() {
-            let x = (i % ORIGINAL_WIDTH) * WINDOW_SCALE;
-            let y = (i / ORIGINAL_WIDTH) * WINDOW_SCALE;
+    /// Returns the current height in pixels
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
 
-            // Update buffer to reflect on the changes made to the original virtual window that self.coord represents
-            for j in 0..WINDOW_SCALE {
-                for k in 0..WINDOW_SCALE {
-                    let idx = (ORIGINAL_WIDTH * WINDOW_SCALE * (y + j as usize) + x + k as usize)
-                        % (ORIGINAL_WIDTH * WINDOW_SCALE * ORIGINAL_HEIGHT * WINDOW_SCALE);
-                    self.buffer[idx] = if self.coord[i] == 1 { PIXEL_COLOR } else { 0x0 };
-                }
-            }
-        }
+    /// Returns true if the display is in hires mode
+    pub fn is_hires(&self) -> bool {
+        self.width == HIRES_WIDTH
     }
 
-    /// Returns true if window is open and if ESC is not pressed
-    pub fn is_window_open(&self) -> bool {
-        self.window.is_open() && !self.window.is_key_down(minifb::Key::Escape)
+    /// Switches between lores and hires mode. The display is cleared in the process
+    pub fn set_hires(&mut self, hires: bool) {
+        if hires {
+            self.width = HIRES_WIDTH;
+            self.height = HIRES_HEIGHT;
+        } else {
+            self.width = ORIGINAL_WIDTH;
+            self.height = ORIGINAL_HEIGHT;
+        }
+        self.coord = vec![0; self.width * self.height];
     }
 
-    /// Clears the window
+    /// Clears the selected planes
     pub fn clear(&mut self) {
-        self.coord = vec![0; self.coord.len()];
-        self.buffer = vec![0; self.buffer.len()];
-        self.draw()
-    }
-
-    /// Returns the key that was pressing. This is the function that maps the keyboard
-    pub fn get_key_pressed(&self) -> Option<u8> {
-        let keys = self.window.get_keys_pressed(minifb::KeyRepeat::Yes);
-        match keys {
-            Some(vec) => match vec.iter().next() {
-                Some(minifb::Key::Q) => Some(0x1),
-                Some(minifb::Key::W) => Some(0x2),
-                Some(minifb::Key::E) => Some(0x3),
-                Some(minifb::Key::R) => Some(0xC),
-                Some(minifb::Key::A) => Some(0x4),
-                Some(minifb::Key::S) => Some(0x5),
-                Some(minifb::Key::D) => Some(0x6),
-                Some(minifb::Key::F) => Some(0xD),
-                Some(minifb::Key::U) => Some(0x7),
-                Some(minifb::Key::I) => Some(0x8),
-                Some(minifb::Key::O) => Some(0x9),
-                Some(minifb::Key::P) => Some(0xE),
-                Some(minifb::Key::J) => Some(0x7),
-                Some(minifb::Key::K) => Some(0x8),
-                Some(minifb::Key::L) => Some(0x9),
-                Some(minifb::Key::Semicolon) => Some(0xF),
-                _ => None,
-            },
-            None => None,
+        for pixel in &mut self.coord {
+            *pixel &= !self.planes;
         }
     }
+
+    /// Scrolls the selected planes down by n pixels. Rows that enter from the top are blank
+    pub fn scroll_down(&mut self, n: usize) {
+        let n = n.min(self.height);
+        for idx in (0..self.coord.len()).rev() {
+            let src = if idx >= n * self.width {
+                self.coord[idx - n * self.width]
+            } else {
+                0
+            };
+            self.move_pixel(idx, src);
+        }
+    }
+
+    /// Scrolls the selected planes up by n pixels (XO-CHIP). Rows that enter from the bottom are blank
+    pub fn scroll_up(&mut self, n: usize) {
+        let n = n.min(self.height);
+        let len = self.coord.len();
+        for idx in 0..len {
+            let src = if idx + n * self.width < len {
+                self.coord[idx + n * self.width]
+            } else {
+                0
+            };
+            self.move_pixel(idx, src);
+        }
+    }
+
+    /// Scrolls the selected planes right by n pixels. Columns that enter from the left are blank
+    pub fn scroll_right(&mut self, n: usize) {
+        let n = n.min(self.width);
+        for idx in (0..self.coord.len()).rev() {
+            let src = if idx % self.width >= n {
+                self.coord[idx - n]
+            } else {
+                0
+            };
+            self.move_pixel(idx, src);
+        }
+    }
+
+    /// Scrolls the selected planes left by n pixels. Columns that enter from the right are blank
+    pub fn scroll_left(&mut self, n: usize) {
+        let n = n.min(self.width);
+        for idx in 0..self.coord.len() {
+            let src = if idx % self.width + n < self.width {
+                self.coord[idx + n]
+            } else {
+                0
+            };
+            self.move_pixel(idx, src);
+        }
+    }
+
+    /// Writes the resolution, the selected planes and every pixel to a save state
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.is_hires());
+        state.write_u8(self.planes);
+        state.write_bytes(&self.coord);
+    }
+
+    /// Restores the resolution, the selected planes and every pixel from a save state
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.set_hires(state.read_bool()?);
+        self.set_planes(state.read_u8()?);
+        let len = self.coord.len();
+        self.coord.copy_from_slice(state.read_bytes(len)?);
+        Ok(())
+    }
+
+    /// Replaces the selected planes of the pixel at idx with the ones from src. Used by scrolling
+    fn move_pixel(&mut self, idx: usize, src: u8) {
+        self.coord[idx] = (self.coord[idx] & !self.planes) | (src & self.planes);
+    }
+}
+
+impl Default for Display {
+    fn default() -> Display {
+        Display::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::quirks::Quirks;
+
+    /// Returns the pixels lit on the first plane, as (x, y)
+    fn lit(coords: &[u8], width: usize) -> Vec<(usize, usize)> {
+        (0..coords.len())
+            .filter(|idx| coords[*idx] & 1 != 0)
+            .map(|idx| (idx % width, idx / width))
+            .collect()
+    }
+
+    /// Returns a SUPER-CHIP machine with the given program loaded at 0x200 and sprite data at 0x220
+    fn machine(program: &[u8], sprite: &[u8]) -> Chip8 {
+        let mut rom = program.to_vec();
+        rom.resize(0x20, 0);
+        rom.extend_from_slice(sprite);
+        let mut chip8 = Chip8::new();
+        chip8.set_quirks(Quirks::super_chip());
+        chip8.load_rom(&rom).unwrap();
+        chip8
+    }
+
+    /// Runs the given amount of instructions
+    fn run(chip8: &mut Chip8, count: usize) {
+        for _ in 0..count {
+            chip8.run_next_instruction();
+        }
+    }
+
+    #[test]
+    fn big_sprites_in_hires() {
+        // A 16x16 sprite with a full top row and the corners of the bottom row
+        let mut sprite = vec![0; 32];
+        sprite[..2].copy_from_slice(&[0xFF, 0xFF]);
+        sprite[30..].copy_from_slice(&[0x80, 0x01]);
+        // hires, v0 := 16, v1 := 8, i := 0x220, sprite v0 v1 0 (twice), v1 := 60, sprite v0 v1 0
+        let program = [
+            0x00, 0xFF, 0x60, 0x10, 0x61, 0x08, 0xA2, 0x20, 0xD0, 0x10, 0xD0, 0x10, 0x61, 0x3C,
+            0xD0, 0x10,
+        ];
+        let mut chip8 = machine(&program, &sprite);
+
+        run(&mut chip8, 5);
+        assert_eq!(chip8.get_resolution(), (128, 64));
+        assert_eq!(chip8.get_framebuffer().len(), 128 * 64);
+        let mut expected: Vec<(usize, usize)> = (16..32).map(|x| (x, 8)).collect();
+        expected.extend_from_slice(&[(16, 23), (31, 23)]);
+        assert_eq!(lit(chip8.get_framebuffer(), 128), expected);
+        assert_eq!(chip8.get_vx(0xF), 0);
+
+        // Drawn again, it erases itself. In hires mode, vf is the amount of rows that collided
+        run(&mut chip8, 1);
+        assert!(lit(chip8.get_framebuffer(), 128).is_empty());
+        assert_eq!(chip8.get_vx(0xF), 2);
+
+        // Rows clipped at the bottom count as collisions too
+        run(&mut chip8, 2);
+        assert_eq!(
+            lit(chip8.get_framebuffer(), 128),
+            (16..32).map(|x| (x, 60)).collect::<Vec<_>>()
+        );
+        assert_eq!(chip8.get_vx(0xF), 12);
+    }
+
+    #[test]
+    fn lores_collisions_are_a_flag() {
+        // i := 0x220, sprite v0 v0 2, twice
+        let mut chip8 = machine(&[0xA2, 0x20, 0xD0, 0x02, 0xD0, 0x02], &[0x80, 0x80]);
+        run(&mut chip8, 2);
+        assert_eq!(chip8.get_resolution(), (64, 32));
+        assert_eq!(lit(chip8.get_framebuffer(), 64), [(0, 0), (0, 1)]);
+        assert_eq!(chip8.get_vx(0xF), 0);
+        run(&mut chip8, 1);
+        assert_eq!(chip8.get_vx(0xF), 1);
+    }
+
+    #[test]
+    fn scroll_opcodes() {
+        // hires, v0 := 10, v1 := 5, i := 0x220, sprite v0 v1 1, scroll-down 3, scroll-right, scroll-left,
+        // scroll-left, scroll-left, scroll-left
+        let program = [
+            0x00, 0xFF, 0x60, 0x0A, 0x61, 0x05, 0xA2, 0x20, 0xD0, 0x11, 0x00, 0xC3, 0x00, 0xFB,
+            0x00, 0xFC, 0x00, 0xFC, 0x00, 0xFC, 0x00, 0xFC,
+        ];
+        let mut chip8 = machine(&program, &[0x80]);
+        run(&mut chip8, 5);
+        assert_eq!(lit(chip8.get_framebuffer(), 128), [(10, 5)]);
+        run(&mut chip8, 1);
+        assert_eq!(lit(chip8.get_framebuffer(), 128), [(10, 8)]);
+        run(&mut chip8, 1);
+        assert_eq!(lit(chip8.get_framebuffer(), 128), [(14, 8)]);
+        run(&mut chip8, 2);
+        assert_eq!(lit(chip8.get_framebuffer(), 128), [(6, 8)]);
+        // Pixels scrolled past the edge are lost
+        run(&mut chip8, 2);
+        assert!(lit(chip8.get_framebuffer(), 128).is_empty());
+    }
+
+    #[test]
+    fn scrolls_fill_with_blank_pixels() {
+        let mut display = Display::new();
+        display.set_hires(true);
+        for x in 0..HIRES_WIDTH {
+            display.set_coord(x, 1);
+        }
+        display.scroll_down(2);
+        assert_eq!(
+            lit(display.get_coords(), HIRES_WIDTH),
+            (0..128).map(|x| (x, 2)).collect::<Vec<_>>()
+        );
+        display.scroll_right(4);
+        assert_eq!(
+            lit(display.get_coords(), HIRES_WIDTH),
+            (4..128).map(|x| (x, 2)).collect::<Vec<_>>()
+        );
+        display.scroll_down(HIRES_HEIGHT);
+        assert!(lit(display.get_coords(), HIRES_WIDTH).is_empty());
+
+        // Switching resolution clears the screen
+        display.set_coord(0, 1);
+        display.set_hires(false);
+        assert_eq!(display.get_coords().len(), ORIGINAL_WIDTH * ORIGINAL_HEIGHT);
+        assert!(lit(display.get_coords(), ORIGINAL_WIDTH).is_empty());
+    }
 }
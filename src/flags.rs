@@ -0,0 +1,46 @@
+use crate::paths::data_dir;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Persistent storage for the RPL user flags of a ROM. SUPER-CHIP games use the flags to save data such as high scores,
+/// so keeping them in a file lets that data survive restarts
+pub struct FlagStorage {
+    /// File where the flags are kept
+    path: PathBuf,
+}
+
+impl FlagStorage {
+    /// Creates a storage backed by the file at the given path
+    pub fn new<P: AsRef<Path>>(path: P) -> FlagStorage {
+        FlagStorage {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Creates a storage for the ROM with the given hash, under the user data directory.
+    /// Returns None if there is no user data directory
+    pub fn for_rom(rom_hash: &str) -> Option<FlagStorage> {
+        let dir = data_dir()?.join("flags");
+        Some(FlagStorage::new(dir.join(format!("{}.flags", rom_hash))))
+    }
+
+    /// Returns the path of the file where the flags are kept
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the flags from the file. Returns None if they were never saved
+    pub fn load(&self) -> Option<Vec<u8>> {
+        fs::read(&self.path).ok()
+    }
+
+    /// Writes the flags to the file, creating its directory if needed
+    pub fn save(&self, flags: &[u8]) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, flags)
+    }
+}
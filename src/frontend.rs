@@ -0,0 +1,311 @@
+use crate::audio::{AudioBackend, FRAME_RATE};
+use crate::chip8::{Chip8, AUDIO_PATTERN_SIZE, INSTRUCTIONS_PER_FRAME, MAX_INSTRUCTIONS_PER_FRAME};
+use crate::debugger::DebugSession;
+use crate::flags::FlagStorage;
+use crate::gamepad::{ButtonMap, GamepadSource};
+use crate::rewind::Rewind;
+use crate::state::StateSlots;
+use crate::video::{Event, VideoBackend};
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Most frames run in a row to catch up with the clock. Any more time lost than that is dropped
+const MAX_CATCH_UP_FRAMES: u32 = 5;
+
+/// Interface between user and the CHIP8
+/// It drives the emulator, presenting its screen and feeding it input through a video backend, and plays its sound through an audio backend
+pub struct Frontend {
+    /// Video backend. Handles graphics and keyboard input
+    video: Box<dyn VideoBackend>,
+    /// Audio backend. Plays the beeper
+    audio: Box<dyn AudioBackend>,
+    /// Where the RPL user flags are persisted, if anywhere
+    flag_storage: Option<FlagStorage>,
+    /// Where the numbered save states are kept, if anywhere
+    state_slots: Option<StateSlots>,
+    /// Gamepad feeding the keypad along with the video backend, and how its buttons map to the keypad, if any
+    gamepad: Option<(Box<dyn GamepadSource>, ButtonMap)>,
+    /// Snapshots of the last frames, if rewinding is enabled
+    rewind: Option<Rewind>,
+    /// Amount of instructions run in each frame
+    instructions_per_frame: u32,
+    /// Runs frames on the timing model of the COSMAC VIP instead of a fixed amount of instructions
+    vip_timing: bool,
+    /// Set while the rewind key is held. The machine is paused and steps back one frame at a time
+    rewinding: bool,
+    /// Last XO-CHIP audio pattern handed to the audio backend
+    audio_pattern: Option<([u8; AUDIO_PATTERN_SIZE], f32)>,
+    /// Debugger that takes over whenever the CPU halts, if any
+    debugger: Option<Box<dyn DebugSession>>,
+    /// Set when the user quit from the debugger
+    quit: bool,
+}
+
+impl Frontend {
+    /// Creates a new frontend that uses the given backends
+    pub fn new(video: Box<dyn VideoBackend>, audio: Box<dyn AudioBackend>) -> Frontend {
+        Frontend {
+            video,
+            audio,
+            flag_storage: None,
+            state_slots: None,
+            gamepad: None,
+            rewind: None,
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            vip_timing: false,
+            rewinding: false,
+            audio_pattern: None,
+            debugger: None,
+            quit: false,
+        }
+    }
+
+    /// Persists the RPL user flags to the given storage whenever the program writes to them
+    pub fn set_flag_storage(&mut self, storage: FlagStorage) {
+        self.flag_storage = Some(storage);
+    }
+
+    /// Enables the save state hotkeys, keeping the states in the given slots
+    pub fn set_state_slots(&mut self, slots: StateSlots) {
+        self.state_slots = Some(slots);
+    }
+
+    /// Feeds the keypad from a gamepad too, mapping its buttons with the given map
+    pub fn set_gamepad(&mut self, gamepad: Box<dyn GamepadSource>, buttons: ButtonMap) {
+        self.gamepad = Some((gamepad, buttons));
+    }
+
+    /// Enables the rewind hotkey, keeping the snapshots in the given buffer
+    pub fn set_rewind(&mut self, rewind: Rewind) {
+        self.rewind = Some(rewind);
+    }
+
+    /// Sets how many instructions run in each frame, from 1 to MAX_INSTRUCTIONS_PER_FRAME.
+    /// The emulated clock speed is this times FRAME_RATE
+    pub fn set_instructions_per_frame(&mut self, instructions: u32) {
+        self.instructions_per_frame = instructions.clamp(1, MAX_INSTRUCTIONS_PER_FRAME);
+    }
+
+    /// Returns how many instructions run in each frame
+    pub fn get_instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    /// Hands the machine to the given debugger whenever the CPU halts, such as on a breakpoint
+    pub fn set_debugger(&mut self, debugger: Box<dyn DebugSession>) {
+        self.debugger = Some(debugger);
+    }
+
+    /// Charges each instruction the time it took on the COSMAC VIP, instead of running a fixed amount per frame.
+    /// The instructions per frame setting is ignored while it is enabled
+    pub fn set_vip_timing(&mut self, vip_timing: bool) {
+        self.vip_timing = vip_timing;
+    }
+
+    /// Runs the emulator until the video backend is closed, the program exits or the user quits from the debugger.
+    /// Frames are paced against a monotonic clock: the time elapsed is accumulated and a frame is run for every
+    /// 1/FRAME_RATE seconds of it, so the emulated speed does not drift with the precision of `sleep()`
+    pub fn run(&mut self, chip8: &mut Chip8) {
+        let frame_period = Duration::from_nanos(1_000_000_000 / FRAME_RATE as u64);
+        let mut last_time = Instant::now();
+        // Time elapsed that frames were not run for yet
+        let mut lag = Duration::from_secs(0);
+
+        // This is the emulator's main loop. It ends when the backend is closed or when the program exits
+        while self.video.is_open() && !chip8.has_exited() && !self.quit {
+            let now = Instant::now();
+            lag += now - last_time;
+            last_time = now;
+
+            // After a long stall, such as the window being dragged, the lost time is dropped instead of being caught up
+            if lag > frame_period * MAX_CATCH_UP_FRAMES {
+                lag = frame_period;
+            }
+
+            while lag >= frame_period && self.video.is_open() && !chip8.has_exited() && !self.quit {
+                // Frames are not run while the debugger has the CPU halted
+                if chip8.get_break().is_some() && self.debugger.is_some() {
+                    break;
+                }
+                lag -= frame_period;
+                self.run_frame(chip8);
+            }
+
+            // The debugger blocks until the user resumes the program. The time spent there is not caught up
+            if chip8.get_break().is_some() {
+                if let Some(debugger) = &mut self.debugger {
+                    self.quit = !debugger.halted(chip8);
+                    last_time = Instant::now();
+                    lag = Duration::from_secs(0);
+                }
+            }
+
+            // Waits for the clock to catch up. The lag can still be over a period if the frames stopped early
+            sleep(frame_period.saturating_sub(lag));
+        }
+    }
+
+    /// Runs a single frame: the instructions of the frame, then the timers, the sound, the display and the input
+    fn run_frame(&mut self, chip8: &mut Chip8) {
+        // Runs the frame, or steps back one frame when rewinding
+        if self.rewinding {
+            if let Some(state) = self.rewind.as_mut().and_then(|rewind| rewind.pop()) {
+                chip8
+                    .load_state(&state)
+                    .expect("Error loading rewind snapshot");
+            }
+        } else {
+            if let Some(rewind) = &mut self.rewind {
+                rewind.push(chip8.save_state());
+            }
+            // With the display wait quirk, a sprite draw ends the frame early
+            if self.vip_timing {
+                chip8.run_vip_frame();
+            } else {
+                chip8.run_frame(self.instructions_per_frame);
+            }
+            self.save_flags(chip8);
+        }
+
+        // Hands a new audio pattern to the audio backend, if the program loaded one
+        if chip8.get_audio_pattern() != self.audio_pattern {
+            self.audio_pattern = chip8.get_audio_pattern();
+            if let Some((pattern, rate)) = &self.audio_pattern {
+                self.audio.set_pattern(pattern, *rate);
+            }
+        }
+
+        // If the buzzer is active, play beep sound. Else, stop it
+        if chip8.is_beeping() && !self.rewinding {
+            self.audio.start_tone();
+        } else {
+            self.audio.stop_tone();
+        }
+        self.audio.end_frame();
+
+        // Updates display
+        let (width, height) = chip8.get_resolution();
+        self.video.present(chip8.get_framebuffer(), width, height);
+
+        // Feeds the input collected during this frame to the emulator
+        self.rewinding = false;
+        let mut events = self.video.poll_events();
+        if let Some((gamepad, buttons)) = &mut self.gamepad {
+            events.extend(
+                gamepad
+                    .poll()
+                    .into_iter()
+                    .filter_map(|event| buttons.map_event(event)),
+            );
+        }
+        for event in events {
+            match event {
+                Event::KeyDown(key) => chip8.press_key(key),
+                Event::KeyUp(key) => chip8.release_key(key),
+                Event::SaveState(slot) => self.save_state(chip8, slot),
+                Event::LoadState(slot) => self.load_state(chip8, slot),
+                Event::Rewind => self.rewinding = self.rewind.is_some(),
+                Event::SpeedUp => self.change_speed(self.instructions_per_frame.saturating_mul(2)),
+                Event::SlowDown => self.change_speed(self.instructions_per_frame / 2),
+                Event::Break => {
+                    if self.debugger.is_some() {
+                        chip8.break_now();
+                    }
+                }
+            }
+        }
+        if let Some(debugger) = &mut self.debugger {
+            debugger.poll(chip8);
+        }
+    }
+
+    /// Changes how many instructions run in each frame and tells the user
+    fn change_speed(&mut self, instructions: u32) {
+        self.set_instructions_per_frame(instructions);
+        println!(
+            "Running {} instructions per frame ({} per second)",
+            self.instructions_per_frame,
+            self.instructions_per_frame as u64 * FRAME_RATE as u64
+        );
+    }
+
+    /// Writes the RPL user flags to the storage, if the program changed them
+    fn save_flags(&mut self, chip8: &mut Chip8) {
+        if !chip8.take_flags_changed() {
+            return;
+        }
+        if let Some(storage) = &self.flag_storage {
+            if let Err(err) = storage.save(chip8.get_flags()) {
+                println!(
+                    "Could not save flags to {}: {}",
+                    storage.get_path().display(),
+                    err
+                );
+            }
+        }
+    }
+    /// Saves the machine state to a numbered slot
+    fn save_state(&mut self, chip8: &Chip8, slot: u8) {
+        if let Some(slots) = &self.state_slots {
+            match slots.save(slot, &chip8.save_state()) {
+                Ok(()) => println!("Saved state to slot {}", slot),
+                Err(err) => println!("Could not save state to slot {}: {}", slot, err),
+            }
+        }
+    }
+
+    /// Loads the machine state from a numbered slot
+    fn load_state(&mut self, chip8: &mut Chip8, slot: u8) {
+        if let Some(slots) = &self.state_slots {
+            let result = slots
+                .load(slot)
+                .map_err(|err| err.to_string())
+                .and_then(|state| chip8.load_state(&state));
+            match result {
+                Ok(()) => {
+                    // The snapshots belong to the timeline that was just left
+                    if let Some(rewind) = &mut self.rewind {
+                        rewind.clear();
+                    }
+                    println!("Loaded state from slot {}", slot)
+                }
+                Err(err) => println!("Could not load state from slot {}: {}", slot, err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::NullAudio;
+    use crate::video::HeadlessVideo;
+
+    /// Runs a frame of a program that loops forever with the given events, and returns the speed it ends at
+    fn speed_after(events: &[Event]) -> u32 {
+        let mut video = HeadlessVideo::new(Some(1));
+        for event in events {
+            video.push_event(*event);
+        }
+        let mut frontend = Frontend::new(Box::new(video), Box::new(NullAudio));
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&[0x12, 0x00]).unwrap();
+
+        frontend.run(&mut chip8);
+        frontend.get_instructions_per_frame()
+    }
+
+    #[test]
+    fn speed_is_clamped() {
+        assert_eq!(
+            speed_after(&[Event::SpeedUp; 40]),
+            MAX_INSTRUCTIONS_PER_FRAME
+        );
+        assert_eq!(speed_after(&[Event::SlowDown; 40]), 1);
+        assert_eq!(
+            speed_after(&[Event::SpeedUp, Event::SpeedUp, Event::SlowDown]),
+            INSTRUCTIONS_PER_FRAME * 2
+        );
+    }
+}
@@ -0,0 +1,378 @@
+#[cfg(feature = "gilrs")]
+extern crate gilrs;
+
+use crate::keypad::KEY_COUNT;
+use crate::video::Event;
+
+use std::fs;
+use std::path::Path;
+
+use toml::Value;
+
+/// Names of the gamepad buttons, as used in the keymap file. Indexed by `Button`
+pub const BUTTON_NAMES: [&str; 16] = [
+    "south",
+    "east",
+    "north",
+    "west",
+    "left-trigger",
+    "left-trigger2",
+    "right-trigger",
+    "right-trigger2",
+    "select",
+    "start",
+    "left-thumb",
+    "right-thumb",
+    "dpad-up",
+    "dpad-down",
+    "dpad-left",
+    "dpad-right",
+];
+
+/// Buttons of a gamepad, named after their position as on most controllers (south is A on Xbox pads, cross on PlayStation ones)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    South,
+    East,
+    North,
+    West,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// Every button, in the order of BUTTON_NAMES
+const BUTTONS: [Button; 16] = [
+    Button::South,
+    Button::East,
+    Button::North,
+    Button::West,
+    Button::LeftTrigger,
+    Button::LeftTrigger2,
+    Button::RightTrigger,
+    Button::RightTrigger2,
+    Button::Select,
+    Button::Start,
+    Button::LeftThumb,
+    Button::RightThumb,
+    Button::DPadUp,
+    Button::DPadDown,
+    Button::DPadLeft,
+    Button::DPadRight,
+];
+
+impl Button {
+    /// Returns the button with the given name, if there is one. See BUTTON_NAMES
+    pub fn from_name(name: &str) -> Option<Button> {
+        let name = name.to_lowercase();
+        BUTTON_NAMES
+            .iter()
+            .position(|button| *button == name)
+            .map(|i| BUTTONS[i])
+    }
+
+    /// Returns the name of the button
+    pub fn name(self) -> &'static str {
+        BUTTON_NAMES[self as usize]
+    }
+}
+
+/// Events reported by a gamepad
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GamepadEvent {
+    /// A button went down
+    ButtonDown(Button),
+    /// A button went up
+    ButtonUp(Button),
+}
+
+/// Anything able to report gamepad buttons, such as a real controller or a virtual one
+pub trait GamepadSource {
+    /// Returns the events that happened since the last call
+    fn poll(&mut self) -> Vec<GamepadEvent>;
+}
+
+/// Maps gamepad buttons to the 16 keys of the keypad
+#[derive(Debug, Clone, PartialEq)]
+pub struct ButtonMap {
+    /// Keypad key of each button, indexed by `Button`
+    keys: [Option<u8>; 16],
+}
+
+impl ButtonMap {
+    /// Creates a button map with the default layout: the d-pad is 5, 8, 7 and 9 (up, down, left and right
+    /// in most games), south is 6, east is 4, west is C, north is D, select is E and start is F
+    pub fn new() -> ButtonMap {
+        let mut map = ButtonMap::empty();
+        map.set(Button::DPadUp, 0x5);
+        map.set(Button::DPadDown, 0x8);
+        map.set(Button::DPadLeft, 0x7);
+        map.set(Button::DPadRight, 0x9);
+        map.set(Button::South, 0x6);
+        map.set(Button::East, 0x4);
+        map.set(Button::West, 0xC);
+        map.set(Button::North, 0xD);
+        map.set(Button::Select, 0xE);
+        map.set(Button::Start, 0xF);
+        map
+    }
+
+    /// Creates a button map where no button is mapped
+    pub fn empty() -> ButtonMap {
+        ButtonMap { keys: [None; 16] }
+    }
+
+    /// Reads the button map from a keymap file for the ROM with the given hash. See `parse()`
+    pub fn load<P: AsRef<Path>>(path: P, rom_hash: &str) -> Result<ButtonMap, String> {
+        let text = fs::read_to_string(path.as_ref())
+            .map_err(|err| format!("Could not read {}: {}", path.as_ref().display(), err))?;
+        ButtonMap::parse(&text, rom_hash)
+    }
+
+    /// Parses the button map of a keymap in TOML for the ROM with the given hash:
+    ///
+    /// ```toml
+    /// [gamepad]
+    /// south = 5
+    /// dpad-left = "A"
+    ///
+    /// [roms.<ROM SHA-1>.gamepad]
+    /// start = 0
+    /// ```
+    ///
+    /// The `gamepad` table replaces the default layout. The table of the ROM, if any, is then applied on top of it
+    pub fn parse(text: &str, rom_hash: &str) -> Result<ButtonMap, String> {
+        let value: Value = text
+            .parse()
+            .map_err(|err| format!("Invalid keymap: {}", err))?;
+
+        let mut map = match value.get("gamepad") {
+            Some(buttons) => {
+                let mut map = ButtonMap::empty();
+                map.apply(buttons)?;
+                map
+            }
+            None => ButtonMap::new(),
+        };
+        let rom = value.get("roms").and_then(|roms| roms.get(rom_hash));
+        if let Some(buttons) = rom.and_then(|rom| rom.get("gamepad")) {
+            map.apply(buttons)?;
+        }
+
+        Ok(map)
+    }
+
+    /// Maps a button to a keypad key (0x0 to 0xF)
+    pub fn set(&mut self, button: Button, key: u8) {
+        self.keys[button as usize] = Some(key);
+    }
+
+    /// Returns the keypad key a button is mapped to, if any
+    pub fn get(&self, button: Button) -> Option<u8> {
+        self.keys[button as usize]
+    }
+
+    /// Turns a gamepad event into a keypad event. Events of unmapped buttons are dropped
+    pub fn map_event(&self, event: GamepadEvent) -> Option<Event> {
+        match event {
+            GamepadEvent::ButtonDown(button) => self.get(button).map(Event::KeyDown),
+            GamepadEvent::ButtonUp(button) => self.get(button).map(Event::KeyUp),
+        }
+    }
+
+    /// Applies every entry of a TOML table
+    fn apply(&mut self, table: &Value) -> Result<(), String> {
+        let table = table
+            .as_table()
+            .ok_or_else(|| "Invalid keymap: expected a table of buttons".to_string())?;
+
+        for (name, key) in table {
+            let button = Button::from_name(name)
+                .ok_or_else(|| format!("Invalid keymap: unknown gamepad button {}", name))?;
+            let key = match key {
+                Value::Integer(key) => *key,
+                Value::String(key) => i64::from_str_radix(key, 16).unwrap_or(-1),
+                _ => -1,
+            };
+            if key < 0 || key >= KEY_COUNT as i64 {
+                return Err(format!(
+                    "Invalid keymap: {} is not mapped to a key from 0 to F",
+                    name
+                ));
+            }
+            self.set(button, key as u8);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ButtonMap {
+    fn default() -> ButtonMap {
+        ButtonMap::new()
+    }
+}
+
+/// Virtual gamepad. Buttons are pressed and released from code, which makes it possible to drive the emulator
+/// and check button maps without any hardware
+#[derive(Debug, Default)]
+pub struct MockGamepad {
+    /// Events to be returned by the next call to `poll()`
+    events: Vec<GamepadEvent>,
+}
+
+impl MockGamepad {
+    /// Creates a virtual gamepad with no button down
+    pub fn new() -> MockGamepad {
+        MockGamepad { events: vec![] }
+    }
+
+    /// Puts a button down
+    pub fn press(&mut self, button: Button) {
+        self.events.push(GamepadEvent::ButtonDown(button));
+    }
+
+    /// Releases a button
+    pub fn release(&mut self, button: Button) {
+        self.events.push(GamepadEvent::ButtonUp(button));
+    }
+}
+
+impl GamepadSource for MockGamepad {
+    fn poll(&mut self) -> Vec<GamepadEvent> {
+        self.events.drain(..).collect()
+    }
+}
+
+/// Reads every gamepad connected to the machine through gilrs
+#[cfg(feature = "gilrs")]
+pub struct GilrsGamepad {
+    gilrs: gilrs::Gilrs,
+}
+
+#[cfg(feature = "gilrs")]
+impl GilrsGamepad {
+    /// Starts listening to the gamepads. Fails if the platform gamepad API is not available
+    pub fn new() -> Result<GilrsGamepad, String> {
+        let gilrs = gilrs::Gilrs::new().map_err(|err| err.to_string())?;
+        Ok(GilrsGamepad { gilrs })
+    }
+
+    /// Returns the button matching a gilrs button, if it is one we know
+    fn convert(button: gilrs::Button) -> Option<Button> {
+        match button {
+            gilrs::Button::South => Some(Button::South),
+            gilrs::Button::East => Some(Button::East),
+            gilrs::Button::North => Some(Button::North),
+            gilrs::Button::West => Some(Button::West),
+            gilrs::Button::LeftTrigger => Some(Button::LeftTrigger),
+            gilrs::Button::LeftTrigger2 => Some(Button::LeftTrigger2),
+            gilrs::Button::RightTrigger => Some(Button::RightTrigger),
+            gilrs::Button::RightTrigger2 => Some(Button::RightTrigger2),
+            gilrs::Button::Select => Some(Button::Select),
+            gilrs::Button::Start => Some(Button::Start),
+            gilrs::Button::LeftThumb => Some(Button::LeftThumb),
+            gilrs::Button::RightThumb => Some(Button::RightThumb),
+            gilrs::Button::DPadUp => Some(Button::DPadUp),
+            gilrs::Button::DPadDown => Some(Button::DPadDown),
+            gilrs::Button::DPadLeft => Some(Button::DPadLeft),
+            gilrs::Button::DPadRight => Some(Button::DPadRight),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "gilrs")]
+impl GamepadSource for GilrsGamepad {
+    fn poll(&mut self) -> Vec<GamepadEvent> {
+        let mut events = vec![];
+
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                gilrs::EventType::ButtonPressed(button, _) => {
+                    if let Some(button) = GilrsGamepad::convert(button) {
+                        events.push(GamepadEvent::ButtonDown(button));
+                    }
+                }
+                gilrs::EventType::ButtonReleased(button, _) => {
+                    if let Some(button) = GilrsGamepad::convert(button) {
+                        events.push(GamepadEvent::ButtonUp(button));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::NullAudio;
+    use crate::chip8::Chip8;
+    use crate::frontend::Frontend;
+    use crate::keypad::Keypad;
+    use crate::video::HeadlessVideo;
+
+    /// Runs a frame with the given gamepad and button map, and returns the keypad after it
+    fn keypad_after(gamepad: MockGamepad, buttons: ButtonMap) -> Keypad {
+        let mut frontend =
+            Frontend::new(Box::new(HeadlessVideo::new(Some(1))), Box::new(NullAudio));
+        frontend.set_gamepad(Box::new(gamepad), buttons);
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&[0x12, 0x00]).unwrap();
+        frontend.run(&mut chip8);
+        *chip8.get_keypad()
+    }
+
+    /// Returns a keypad with the given keys down
+    fn keypad(keys: &[u8]) -> Keypad {
+        let mut keypad = Keypad::new();
+        for key in keys {
+            keypad.press(*key);
+        }
+        keypad
+    }
+
+    #[test]
+    fn default_map() {
+        let mut gamepad = MockGamepad::new();
+        gamepad.press(Button::DPadUp);
+        gamepad.press(Button::South);
+        gamepad.press(Button::Start);
+        gamepad.release(Button::South);
+        // Not mapped by default
+        gamepad.press(Button::LeftThumb);
+
+        assert_eq!(keypad_after(gamepad, ButtonMap::new()), keypad(&[0x5, 0xF]));
+    }
+
+    #[test]
+    fn rom_override() {
+        let text = "[gamepad]\nsouth = 5\ndpad-left = \"A\"\n\n[roms.abcd.gamepad]\nsouth = 0\nstart = 1\n";
+        let global = ButtonMap::parse(text, "1234").unwrap();
+        let rom = ButtonMap::parse(text, "abcd").unwrap();
+
+        let press = || {
+            let mut gamepad = MockGamepad::new();
+            gamepad.press(Button::South);
+            gamepad.press(Button::DPadLeft);
+            gamepad.press(Button::Start);
+            gamepad
+        };
+        // The gamepad table replaces the default layout, so start is not mapped
+        assert_eq!(keypad_after(press(), global), keypad(&[0x5, 0xA]));
+        assert_eq!(keypad_after(press(), rom), keypad(&[0x0, 0xA, 0x1]));
+    }
+}
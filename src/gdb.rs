@@ -0,0 +1,561 @@
+use crate::chip8::{Address, Chip8};
+use crate::debugger::{Access, BreakReason, DebugSession, Watchpoint};
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+/// Amount of registers in the register file: V0 to VF, I, PC, SP, DT and ST
+pub const REGISTER_COUNT: usize = 21;
+/// Number of the I register in the register file. V0 to VF come first
+const REG_I: usize = 0x10;
+/// Number of the PC register in the register file
+const REG_PC: usize = 0x11;
+/// Number of the SP register in the register file
+const REG_SP: usize = 0x12;
+/// Number of the DT register in the register file
+const REG_DT: usize = 0x13;
+/// Number of the ST register in the register file
+const REG_ST: usize = 0x14;
+/// Largest packet the client may send, as announced by qSupported
+const PACKET_SIZE: usize = 0x4000;
+/// Byte sent by the client to interrupt the program
+const INTERRUPT: u8 = 0x03;
+
+/// Signal numbers reported in stop replies
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// GDB remote serial protocol stub. It serves a single client over TCP and maps the machine to a register file:
+/// V0 to VF (8 bits each), I (16 bits), PC (16 bits), SP, DT and ST (8 bits each), in that order.
+/// 16-bit registers are sent most significant byte first, as CHIP-8 is big-endian.
+/// Memory reads and writes, software breakpoints, watchpoints, single step and continue are supported
+pub struct GdbStub {
+    /// Connection to the client
+    stream: TcpStream,
+    /// Bytes received and not handled yet
+    received: Vec<u8>,
+    /// Set while the program runs on behalf of the client, which expects a stop reply when the CPU halts
+    running: bool,
+    /// Set once the client detached or hung up. The program then runs on its own
+    detached: bool,
+}
+
+impl GdbStub {
+    /// Waits for a client on the given port of the local machine
+    pub fn listen(port: u16) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let (stream, _) = listener.accept()?;
+        Ok(GdbStub::new(stream))
+    }
+
+    /// Serves the client on the other end of the given connection
+    pub fn new(stream: TcpStream) -> GdbStub {
+        GdbStub {
+            stream,
+            received: vec![],
+            running: false,
+            detached: false,
+        }
+    }
+
+    /// Receives the next packet, acknowledging it. Returns None if the client hung up
+    fn read_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            // Acknowledgments and interrupts can come before a packet. The CPU is halted already
+            let start = self.received.iter().position(|byte| *byte == b'$');
+            let end = start.and_then(|start| {
+                let hash = self.received[start..]
+                    .iter()
+                    .position(|byte| *byte == b'#')?;
+                Some(start + hash)
+            });
+
+            if let (Some(start), Some(end)) = (start, end) {
+                if self.received.len() >= end + 3 {
+                    let data = self.received[start + 1..end].to_vec();
+                    let checksum = std::str::from_utf8(&self.received[end + 1..end + 3])
+                        .ok()
+                        .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+                    self.received.drain(..end + 3);
+
+                    if checksum == Some(checksum_of(&data)) {
+                        self.stream.write_all(b"+")?;
+                        return Ok(Some(data));
+                    }
+                    // The client sends a packet again when it is not acknowledged
+                    self.stream.write_all(b"-")?;
+                    continue;
+                }
+            }
+
+            let mut buffer = [0; 1024];
+            let len = self.stream.read(&mut buffer)?;
+            if len == 0 {
+                return Ok(None);
+            }
+            self.received.extend_from_slice(&buffer[..len]);
+        }
+    }
+
+    /// Sends a packet. Acknowledgments from the client are not waited for
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+
+    /// Serves packets while the CPU is halted. Returns true once the program should resume, false to stop the emulator
+    fn serve(&mut self, chip8: &mut Chip8) -> io::Result<bool> {
+        loop {
+            let packet = match self.read_packet()? {
+                Some(packet) => String::from_utf8_lossy(&packet).to_string(),
+                None => {
+                    self.detach(chip8);
+                    return Ok(true);
+                }
+            };
+
+            let mut chars = packet.chars();
+            let command = chars.next().map(String::from).unwrap_or_default();
+            let args = chars.as_str();
+            let reply = match command.as_str() {
+                "?" => stop_reply(chip8.get_break()),
+                "g" => (0..REGISTER_COUNT)
+                    .map(|reg| read_register(chip8, reg))
+                    .collect(),
+                "G" => {
+                    let mut values = args;
+                    for reg in 0..REGISTER_COUNT {
+                        let len = register_size(reg) * 2;
+                        if values.len() < len {
+                            break;
+                        }
+                        let (value, rest) = values.split_at(len);
+                        write_register(chip8, reg, value);
+                        values = rest;
+                    }
+                    "OK".to_string()
+                }
+                "p" => match usize::from_str_radix(args, 16) {
+                    Ok(reg) if reg < REGISTER_COUNT => read_register(chip8, reg),
+                    _ => "E00".to_string(),
+                },
+                "P" => match args.split_once('=') {
+                    Some((reg, value)) => match usize::from_str_radix(reg, 16) {
+                        Ok(reg)
+                            if reg < REGISTER_COUNT && value.len() == register_size(reg) * 2 =>
+                        {
+                            write_register(chip8, reg, value);
+                            "OK".to_string()
+                        }
+                        _ => "E00".to_string(),
+                    },
+                    None => "E00".to_string(),
+                },
+                "m" => match parse_range(args) {
+                    // Each byte takes two characters of the reply
+                    Some((addr, len)) => chip8
+                        .read_memory(addr, len.min(PACKET_SIZE / 2))
+                        .iter()
+                        .map(|byte| format!("{:02x}", byte))
+                        .collect(),
+                    None => "E00".to_string(),
+                },
+                "M" => {
+                    let write = args
+                        .split_once(':')
+                        .and_then(|(range, data)| Some((parse_range(range)?, decode_hex(data)?)));
+                    match write {
+                        Some(((addr, len), bytes)) if bytes.len() == len => {
+                            chip8.write_memory(addr, &bytes);
+                            "OK".to_string()
+                        }
+                        _ => "E00".to_string(),
+                    }
+                }
+                "Z" | "z" => match set_breakpoint(chip8, args, command == "Z") {
+                    Some(true) => "OK".to_string(),
+                    Some(false) => "E00".to_string(),
+                    None => String::new(),
+                },
+                "c" | "s" => {
+                    if let Ok(addr) = Address::from_str_radix(args, 16) {
+                        chip8.set_pc(addr);
+                    }
+                    if command == "s" {
+                        chip8.step();
+                    } else {
+                        chip8.resume();
+                    }
+                    self.running = true;
+                    return Ok(true);
+                }
+                "D" => {
+                    self.write_packet("OK")?;
+                    self.detach(chip8);
+                    return Ok(true);
+                }
+                "k" => return Ok(false),
+                "H" => "OK".to_string(),
+                "q" => self.query(args),
+                // Anything else is not supported, which the client is told with an empty reply
+                _ => String::new(),
+            };
+            self.write_packet(&reply)?;
+        }
+    }
+
+    /// Answers a general query (q packets)
+    fn query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE)
+        } else if query == "Attached" {
+            "1".to_string()
+        } else if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            // The description is sent in chunks of the requested size. The last one starts with l instead of m
+            let xml = target_description();
+            let (offset, len) = match range.split_once(',') {
+                Some((offset, len)) => (
+                    usize::from_str_radix(offset, 16).unwrap_or(0),
+                    usize::from_str_radix(len, 16).unwrap_or(0),
+                ),
+                None => return "E00".to_string(),
+            };
+            let start = offset.min(xml.len());
+            let end = (start + len).min(xml.len());
+            let marker = if end == xml.len() { 'l' } else { 'm' };
+            format!("{}{}", marker, &xml[start..end])
+        } else {
+            String::new()
+        }
+    }
+
+    /// Tells the client why the CPU halted, if it is waiting for it
+    fn report_stop(&mut self, chip8: &Chip8) -> io::Result<()> {
+        if !self.running {
+            return Ok(());
+        }
+        self.running = false;
+        self.write_packet(&stop_reply(chip8.get_break()))
+    }
+
+    /// Removes what the client set and lets the program run on its own
+    fn detach(&mut self, chip8: &mut Chip8) {
+        for addr in chip8.get_breakpoints() {
+            chip8.remove_breakpoint(addr);
+        }
+        while chip8.remove_watchpoint(0) {}
+        chip8.resume();
+        self.detached = true;
+        self.running = false;
+    }
+}
+
+impl DebugSession for GdbStub {
+    fn poll(&mut self, chip8: &mut Chip8) {
+        if self.detached {
+            return;
+        }
+
+        // The client interrupts the program by sending a single byte outside of any packet
+        self.stream
+            .set_nonblocking(true)
+            .expect("Error polling GDB client");
+        let mut buffer = [0; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.detach(chip8);
+                    break;
+                }
+                Ok(len) => {
+                    if buffer[..len].contains(&INTERRUPT) {
+                        chip8.break_now();
+                    }
+                    self.received
+                        .extend(buffer[..len].iter().filter(|byte| **byte != INTERRUPT));
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    self.detach(chip8);
+                    break;
+                }
+            }
+        }
+        if !self.detached {
+            self.stream
+                .set_nonblocking(false)
+                .expect("Error polling GDB client");
+        }
+    }
+
+    fn halted(&mut self, chip8: &mut Chip8) -> bool {
+        if self.detached {
+            chip8.resume();
+            return true;
+        }
+
+        match self.report_stop(chip8).and_then(|()| self.serve(chip8)) {
+            Ok(resume) => resume,
+            Err(err) => {
+                println!("Lost the GDB client: {}", err);
+                self.detach(chip8);
+                true
+            }
+        }
+    }
+}
+
+/// Returns the checksum of a packet: the sum of its bytes, modulo 256
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, byte| sum.wrapping_add(*byte))
+}
+
+/// Returns the stop reply telling the client why the CPU halted
+fn stop_reply(reason: Option<BreakReason>) -> String {
+    match reason {
+        Some(BreakReason::Pause) => format!("S{:02x}", SIGINT),
+        Some(BreakReason::Watchpoint { addr, access, .. }) => {
+            let kind = match access {
+                Access::Write => "watch",
+                Access::Read => "rwatch",
+                Access::ReadWrite => "awatch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, kind, addr)
+        }
+        _ => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+/// Returns the size in bytes of a register of the register file
+fn register_size(reg: usize) -> usize {
+    match reg {
+        REG_I | REG_PC => 2,
+        _ => 1,
+    }
+}
+
+/// Returns the value of a register of the register file, in hex
+fn read_register(chip8: &Chip8, reg: usize) -> String {
+    match reg {
+        REG_I => format!("{:04x}", chip8.get_i()),
+        REG_PC => format!("{:04x}", chip8.get_pc()),
+        REG_SP => format!("{:02x}", chip8.get_sp()),
+        REG_DT => format!("{:02x}", chip8.get_dt()),
+        REG_ST => format!("{:02x}", chip8.get_st()),
+        x => format!("{:02x}", chip8.get_vx(x as u8)),
+    }
+}
+
+/// Sets a register of the register file from a value in hex. Invalid values are ignored
+fn write_register(chip8: &mut Chip8, reg: usize, value: &str) {
+    let value = match u16::from_str_radix(value, 16) {
+        Ok(value) => value,
+        Err(_) => return,
+    };
+    match reg {
+        REG_I => chip8.set_i(value),
+        REG_PC => chip8.set_pc(value),
+        REG_SP => chip8.set_sp(value as usize),
+        REG_DT => chip8.set_dt(value as u8),
+        REG_ST => chip8.set_st(value as u8),
+        x => chip8.set_vx(x as u8, value as u8),
+    }
+}
+
+/// Parses an address and a length written as `addr,length` in hex
+fn parse_range(range: &str) -> Option<(Address, usize)> {
+    let (addr, len) = range.split_once(',')?;
+    Some((
+        Address::from_str_radix(addr, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+/// Decodes bytes written in hex
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|j| u8::from_str_radix(hex.get(j..j + 2)?, 16).ok())
+        .collect()
+}
+
+/// Sets (Z) or removes (z) a breakpoint or a watchpoint from a packet written as `type,addr,kind`.
+/// Returns None if the type is not supported, and whether it worked otherwise
+fn set_breakpoint(chip8: &mut Chip8, args: &str, insert: bool) -> Option<bool> {
+    let mut fields = args.split(',');
+    let kind = fields.next()?;
+    let addr = Address::from_str_radix(fields.next()?, 16).ok()?;
+    // For watchpoints, this is the amount of bytes watched
+    let len = fields
+        .next()
+        .and_then(|len| Address::from_str_radix(len, 16).ok())
+        .unwrap_or(1)
+        .max(1);
+
+    let access = match kind {
+        // Software and hardware breakpoints are the same thing here
+        "0" | "1" => {
+            if insert {
+                chip8.add_breakpoint(addr);
+                return Some(true);
+            }
+            return Some(chip8.remove_breakpoint(addr));
+        }
+        "2" => Access::Write,
+        "3" => Access::Read,
+        "4" => Access::ReadWrite,
+        _ => return None,
+    };
+    let watchpoint = Watchpoint {
+        start: addr,
+        end: addr.wrapping_add(len - 1),
+        access,
+    };
+    if insert {
+        chip8.add_watchpoint(watchpoint);
+        return Some(true);
+    }
+    match chip8
+        .get_watchpoints()
+        .iter()
+        .position(|other| *other == watchpoint)
+    {
+        Some(index) => Some(chip8.remove_watchpoint(index)),
+        None => Some(false),
+    }
+}
+
+/// Returns the target description sent to the client, which names the registers of the register file
+fn target_description() -> String {
+    let mut registers: Vec<String> = (0..0x10)
+        .map(|x| format!("<reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\"/>", x))
+        .collect();
+    registers.push("<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>".to_string());
+    registers.push("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>".to_string());
+    for name in ["sp", "dt", "st"].iter() {
+        registers.push(format!(
+            "<reg name=\"{}\" bitsize=\"8\" type=\"uint8\"/>",
+            name
+        ));
+    }
+
+    format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><feature name=\"org.chip8.cpu\">{}</feature></target>",
+        registers.join("")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::NullAudio;
+    use crate::frontend::Frontend;
+    use crate::video::HeadlessVideo;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Scripted GDB client
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        /// Reads a single byte from the stub
+        fn read_byte(&mut self) -> u8 {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte).unwrap();
+            byte[0]
+        }
+
+        /// Sends a packet with the given checksum and returns the acknowledgment of the stub
+        fn send_raw(&mut self, data: &str, checksum: u8) -> u8 {
+            write!(self.stream, "${}#{:02x}", data, checksum).unwrap();
+            self.read_byte()
+        }
+
+        /// Receives a packet, checking its framing and its checksum, and acknowledges it
+        fn receive(&mut self) -> String {
+            assert_eq!(self.read_byte(), b'$');
+            let mut data = vec![];
+            loop {
+                match self.read_byte() {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            let checksum = [self.read_byte(), self.read_byte()];
+            let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap();
+            assert_eq!(checksum, checksum_of(&data));
+            self.stream.write_all(b"+").unwrap();
+            String::from_utf8(data).unwrap()
+        }
+
+        /// Sends a packet, checks that the stub acknowledged it and returns its reply
+        fn command(&mut self, data: &str) -> String {
+            assert_eq!(self.send_raw(data, checksum_of(data.as_bytes())), b'+');
+            self.receive()
+        }
+    }
+
+    #[test]
+    fn scripted_session() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let emulator = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut chip8 = Chip8::new();
+            // v0 := 5, then v0 += 1 forever
+            chip8
+                .load_rom(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x02])
+                .unwrap();
+            // Closes after 10 seconds if the session gets stuck
+            let mut frontend =
+                Frontend::new(Box::new(HeadlessVideo::new(Some(600))), Box::new(NullAudio));
+            frontend.set_debugger(Box::new(GdbStub::new(stream)));
+            chip8.break_now();
+            frontend.run(&mut chip8);
+            chip8.get_vx(0)
+        });
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let mut client = Client { stream };
+
+        // Packets with a wrong checksum are rejected
+        assert_eq!(client.send_raw("?", 0), b'-');
+
+        assert_eq!(client.command("?"), "S02");
+        assert_eq!(
+            client.command("g"),
+            format!("{}00000200000000", "00".repeat(16))
+        );
+        assert_eq!(client.command("m200,6"), "600570011202");
+        assert_eq!(client.command("M300,2:abcd"), "OK");
+        assert_eq!(client.command("m300,2"), "abcd");
+
+        assert_eq!(client.command("Z0,204,2"), "OK");
+        assert_eq!(client.command("s"), "S05");
+        assert_eq!(client.command("p11"), "0202");
+        assert_eq!(client.command("p0"), "05");
+        assert_eq!(client.command("c"), "S05");
+        assert_eq!(client.command("p11"), "0204");
+        assert_eq!(client.command("p0"), "06");
+
+        // Without the breakpoint, the loop runs until the next one is set
+        assert_eq!(client.command("z0,204,2"), "OK");
+        assert_eq!(client.command("z0,204,2"), "E00");
+        assert_eq!(client.command("Z0,202,2"), "OK");
+        assert_eq!(client.command("c"), "S05");
+        assert_eq!(client.command("p11"), "0202");
+
+        assert_eq!(client.send_raw("k", checksum_of(b"k")), b'+');
+        assert_eq!(emulator.join().unwrap(), 0x06);
+    }
+}
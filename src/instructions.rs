@@ -2,13 +2,28 @@ use crate::chip8::{Address, Register};
 
 use Instructions::*;
 
-/// All the Chip-8 instructions, with the exception of 0nnn, which is ignored by modern interpreters including this one
-#[derive(Debug)]
+/// All the Chip-8 instructions, with the exception of 0nnn, which is ignored by modern interpreters including this one.
+/// The SUPER-CHIP 1.1 and XO-CHIP extensions are included as well
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Instructions {
+    /// Scroll display n pixels down (SUPER-CHIP)
+    ScrollDown(u8),
+    /// Scroll display n pixels up (XO-CHIP)
+    ScrollUp(u8),
     /// Clear display
     ClearDisplay,
     /// Return from subroutine
     Return,
+    /// Scroll display 4 pixels right (SUPER-CHIP)
+    ScrollRight,
+    /// Scroll display 4 pixels left (SUPER-CHIP)
+    ScrollLeft,
+    /// Exit the interpreter (SUPER-CHIP)
+    Exit,
+    /// Switch to lores (64x32) mode (SUPER-CHIP)
+    LowRes,
+    /// Switch to hires (128x64) mode (SUPER-CHIP)
+    HighRes,
     /// Jump to Address
     Jump(Address),
     /// Call subroutine at Address
@@ -19,6 +34,10 @@ pub enum Instructions {
     SkipIfNotEqualsByte(Register, u8),
     /// Skip next instruction if value of vx equals value of vy
     SkipIfEquals(Register, Register),
+    /// Copies the values of the registers vx to vy(inclusive) in memory starting at the address of i (XO-CHIP)
+    CopyRangeMemory(Register, Register),
+    /// Sets the values of the registers vx to vy(inclusive) with the values from memory starting at the address of i (XO-CHIP)
+    SetRangeMemory(Register, Register),
     /// Sets vx to nn
     SetRegisterByte(Register, u8),
     /// Sets vx to (vx + nn)
@@ -35,26 +54,34 @@ pub enum Instructions {
     Add(Register, Register),
     /// Sets vx value to (vx - vy)
     Sub(Register, Register),
-    /// Shifts vx one bit to the right
-    ShiftRight(Register),
+    /// Shifts vx one bit to the right (or stores vy shifted one bit to the right in vx, depending on quirks)
+    ShiftRight(Register, Register),
     /// Sets vx to (vy - vx)
     ReverseSub(Register, Register),
-    /// Shifts vx one bit to the left
-    ShiftLeft(Register),
+    /// Shifts vx one bit to the left (or stores vy shifted one bit to the left in vx, depending on quirks)
+    ShiftLeft(Register, Register),
     /// Skip next instruction if value of vx is not equal to the value of vy
     SkipIfNotEquals(Register, Register),
     /// Sets the register I to Address
     SetI(Address),
-    /// Jumps to (Address + v0)
+    /// Sets the register I to the 16-bit Address stored in the word that follows the instruction (XO-CHIP)
+    SetLongI(Address),
+    /// Jumps to (Address + v0) (or to (Address + vx), where x is the most significant digit of Address, depending on quirks)
     JumpPlusV0(Address),
     /// Sets the result of (random_byte & nn) to vx
     SetRandAnd(Register, u8),
-    /// Draws a sprite of width 8 and height n at coordinate (x, y)
+    /// Draws a sprite of width 8 and height n at coordinate (x, y). If n is 0, draws a 16x16 sprite (SUPER-CHIP)
     Draw(Register, Register, u8),
     /// Skip next instruction if key pressed equals the value of vx
     SkipIfKeyPressed(Register),
     /// Skip next instruction if key pressed is not equal to the value of vx
     SkipIfKeyNotPressed(Register),
+    /// Selects the planes (given by n) affected by drawing, clearing and scrolling (XO-CHIP)
+    SelectPlanes(u8),
+    /// Loads 16 bytes starting at the address of i into the audio pattern buffer (XO-CHIP)
+    LoadAudioPattern,
+    /// Sets the pitch of the audio pattern playback to the value of vx (XO-CHIP)
+    SetPitch(Register),
     /// Sets vx to the value of the delay timer (dt)
     SetToDelayTimer(Register),
     /// Waits for a key press. (Blocking instruction)
@@ -67,16 +94,21 @@ pub enum Instructions {
     AddRegisterI(Register),
     /// Sets i register to the memory address of the sprite for the character in vx
     SetSpriteI(u8),
+    /// Sets i register to the memory address of the big sprite for the character in vx (SUPER-CHIP)
+    SetBigSpriteI(Register),
     /// Binary-coded decimal representation. Stores the most significant digit of vx to i, the middle digit to i+1 and the last digit to i+2
     BCDRepresentation(Register),
     /// Copies the values of the registers v0 to vx(inclusive) in memory starting at the address of i
     CopyRegistersMemory(Register),
     /// Sets the values of the register v0 to vx(inclusive) with the values from memory starting at the addres of i
     SetRegistersMemory(Register),
+    /// Saves the values of the registers v0 to vx(inclusive) to the RPL user flags (SUPER-CHIP)
+    SaveFlags(Register),
+    /// Sets the values of the registers v0 to vx(inclusive) with the values from the RPL user flags (SUPER-CHIP)
+    LoadFlags(Register),
 }
 
 impl Instructions {
-
     /// Transforms the raw data from the ROM to an Instruction (one instruction at a time)
     pub fn new(raw: u16) -> Option<Instructions> {
         let first_digit: u8 = ((raw >> 12) & 0xF) as u8;
@@ -94,8 +126,15 @@ impl Instructions {
 
         match first_digit {
             0x0 if x == 0x0 => match ls_byte {
+                0xC0..=0xCF => Some(ScrollDown(last_digit)),
+                0xD0..=0xDF => Some(ScrollUp(last_digit)),
                 0xE0 => Some(ClearDisplay),
                 0xEE => Some(Return),
+                0xFB => Some(ScrollRight),
+                0xFC => Some(ScrollLeft),
+                0xFD => Some(Exit),
+                0xFE => Some(LowRes),
+                0xFF => Some(HighRes),
                 _ => None,
             },
             0x1 => Some(Jump(nnn)),
@@ -104,6 +143,8 @@ impl Instructions {
             0x4 => Some(SkipIfNotEqualsByte(x, ls_byte)),
             0x5 => match last_digit {
                 0x0 => Some(SkipIfEquals(x, y)),
+                0x2 => Some(CopyRangeMemory(x, y)),
+                0x3 => Some(SetRangeMemory(x, y)),
                 _ => None,
             },
             0x6 => Some(SetRegisterByte(x, ls_byte)),
@@ -115,9 +156,9 @@ impl Instructions {
                 0x3 => Some(Xor(x, y)),
                 0x4 => Some(Add(x, y)),
                 0x5 => Some(Sub(x, y)),
-                0x6 => Some(ShiftRight(x)),
+                0x6 => Some(ShiftRight(x, y)),
                 0x7 => Some(ReverseSub(x, y)),
-                0xE => Some(ShiftLeft(x)),
+                0xE => Some(ShiftLeft(x, y)),
                 _ => None,
             },
             0x9 => match last_digit {
@@ -134,18 +175,118 @@ impl Instructions {
                 _ => None,
             },
             0xF => match ls_byte {
+                0x01 => Some(SelectPlanes(x)),
+                0x02 if x == 0x0 => Some(LoadAudioPattern),
                 0x07 => Some(SetToDelayTimer(x)),
                 0x0A => Some(WaitKeyPress(x)),
                 0x15 => Some(SetDelayTimer(x)),
                 0x18 => Some(SetSoundTimer(x)),
                 0x1E => Some(AddRegisterI(x)),
                 0x29 => Some(SetSpriteI(x)),
+                0x30 => Some(SetBigSpriteI(x)),
                 0x33 => Some(BCDRepresentation(x)),
+                0x3A => Some(SetPitch(x)),
                 0x55 => Some(CopyRegistersMemory(x)),
                 0x65 => Some(SetRegistersMemory(x)),
+                0x75 => Some(SaveFlags(x)),
+                0x85 => Some(LoadFlags(x)),
                 _ => None,
             },
             _ => None,
         }
     }
+
+    /// Same as `new()`, but also decodes instructions that span two words. `next` is the word that follows `raw`
+    pub fn new_long(raw: u16, next: u16) -> Option<Instructions> {
+        match raw {
+            0xF000 => Some(SetLongI(next)),
+            _ => Instructions::new(raw),
+        }
+    }
+
+    /// Returns the opcode of the instruction, most significant byte first. This is the inverse of `new_long()`, so
+    /// instructions that span two words return four bytes. Operands too big for their field are truncated
+    pub fn encode(&self) -> Vec<u8> {
+        let xy = |op: u16, x: Register, y: Register, n: u16| {
+            op | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | n
+        };
+        let xnn = |op: u16, x: Register, byte: u8| op | (x as u16 & 0xF) << 8 | byte as u16;
+        let x = |op: u16, x: Register| op | (x as u16 & 0xF) << 8;
+
+        let opcode = match *self {
+            ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+            ClearDisplay => 0x00E0,
+            Return => 0x00EE,
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            LowRes => 0x00FE,
+            HighRes => 0x00FF,
+            Jump(addr) => 0x1000 | (addr & 0xFFF),
+            Call(addr) => 0x2000 | (addr & 0xFFF),
+            SkipIfEqualsByte(reg, byte) => xnn(0x3000, reg, byte),
+            SkipIfNotEqualsByte(reg, byte) => xnn(0x4000, reg, byte),
+            SkipIfEquals(reg1, reg2) => xy(0x5000, reg1, reg2, 0x0),
+            CopyRangeMemory(reg1, reg2) => xy(0x5000, reg1, reg2, 0x2),
+            SetRangeMemory(reg1, reg2) => xy(0x5000, reg1, reg2, 0x3),
+            SetRegisterByte(reg, byte) => xnn(0x6000, reg, byte),
+            AddByte(reg, byte) => xnn(0x7000, reg, byte),
+            SetRegister(reg1, reg2) => xy(0x8000, reg1, reg2, 0x0),
+            Or(reg1, reg2) => xy(0x8000, reg1, reg2, 0x1),
+            And(reg1, reg2) => xy(0x8000, reg1, reg2, 0x2),
+            Xor(reg1, reg2) => xy(0x8000, reg1, reg2, 0x3),
+            Add(reg1, reg2) => xy(0x8000, reg1, reg2, 0x4),
+            Sub(reg1, reg2) => xy(0x8000, reg1, reg2, 0x5),
+            ShiftRight(reg1, reg2) => xy(0x8000, reg1, reg2, 0x6),
+            ReverseSub(reg1, reg2) => xy(0x8000, reg1, reg2, 0x7),
+            ShiftLeft(reg1, reg2) => xy(0x8000, reg1, reg2, 0xE),
+            SkipIfNotEquals(reg1, reg2) => xy(0x9000, reg1, reg2, 0x0),
+            SetI(addr) => 0xA000 | (addr & 0xFFF),
+            SetLongI(addr) => return vec![0xF0, 0x00, (addr >> 8) as u8, addr as u8],
+            JumpPlusV0(addr) => 0xB000 | (addr & 0xFFF),
+            SetRandAnd(reg, byte) => xnn(0xC000, reg, byte),
+            Draw(reg1, reg2, n) => xy(0xD000, reg1, reg2, n as u16 & 0xF),
+            SkipIfKeyPressed(reg) => x(0xE09E, reg),
+            SkipIfKeyNotPressed(reg) => x(0xE0A1, reg),
+            SelectPlanes(n) => x(0xF001, n),
+            LoadAudioPattern => 0xF002,
+            SetPitch(reg) => x(0xF03A, reg),
+            SetToDelayTimer(reg) => x(0xF007, reg),
+            WaitKeyPress(reg) => x(0xF00A, reg),
+            SetDelayTimer(reg) => x(0xF015, reg),
+            SetSoundTimer(reg) => x(0xF018, reg),
+            AddRegisterI(reg) => x(0xF01E, reg),
+            SetSpriteI(reg) => x(0xF029, reg),
+            SetBigSpriteI(reg) => x(0xF030, reg),
+            BCDRepresentation(reg) => x(0xF033, reg),
+            CopyRegistersMemory(reg) => x(0xF055, reg),
+            SetRegistersMemory(reg) => x(0xF065, reg),
+            SaveFlags(reg) => x(0xF075, reg),
+            LoadFlags(reg) => x(0xF085, reg),
+        };
+        opcode.to_be_bytes().to_vec()
+    }
+
+    /// Returns true if the instruction is an XO-CHIP extension, which other interpreters do not know
+    pub fn is_xo_chip(&self) -> bool {
+        matches!(
+            self,
+            ScrollUp(_)
+                | CopyRangeMemory(..)
+                | SetRangeMemory(..)
+                | SetLongI(_)
+                | SelectPlanes(_)
+                | LoadAudioPattern
+                | SetPitch(_)
+        )
+    }
+
+    /// Returns the size of the instruction in bytes
+    pub fn size(&self) -> u16 {
+        match self {
+            SetLongI(_) => 4,
+            _ => 2,
+        }
+    }
 }
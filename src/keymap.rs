@@ -0,0 +1,221 @@
+use crate::keypad::KEY_COUNT;
+use crate::paths::config_dir;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml::Value;
+
+/// Standard layout: the left side of a QWERTY keyboard stands in for the keypad
+///
+/// 1 2 3 4      1 2 3 C
+/// Q W E R  ->  4 5 6 D
+/// A S D F      7 8 9 E
+/// Z X C V      A 0 B F
+pub const DEFAULT_LAYOUT: [(&str, u8); 16] = [
+    ("1", 0x1),
+    ("2", 0x2),
+    ("3", 0x3),
+    ("4", 0xC),
+    ("q", 0x4),
+    ("w", 0x5),
+    ("e", 0x6),
+    ("r", 0xD),
+    ("a", 0x7),
+    ("s", 0x8),
+    ("d", 0x9),
+    ("f", 0xE),
+    ("z", 0xA),
+    ("x", 0x0),
+    ("c", 0xB),
+    ("v", 0xF),
+];
+
+/// Maps host keys to the 16 keys of the keypad. Host keys are known by name, such as `q`, `1` or `space`,
+/// so the map does not depend on any windowing library
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keymap {
+    /// Keypad key of each host key, by normalized name
+    keys: HashMap<String, u8>,
+}
+
+impl Keymap {
+    /// Creates a keymap with the standard layout. See DEFAULT_LAYOUT
+    pub fn new() -> Keymap {
+        let mut keymap = Keymap::empty();
+        for (name, key) in DEFAULT_LAYOUT.iter() {
+            keymap.set(name, *key);
+        }
+        keymap
+    }
+
+    /// Creates a keymap where no host key is mapped
+    pub fn empty() -> Keymap {
+        Keymap {
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Returns the path of the keymap file used when none is given: `keymap.toml` in the configuration directory
+    pub fn default_path() -> Option<PathBuf> {
+        Some(config_dir()?.join("keymap.toml"))
+    }
+
+    /// Reads a keymap file for the ROM with the given hash. See `parse()`
+    pub fn load<P: AsRef<Path>>(path: P, rom_hash: &str) -> Result<Keymap, String> {
+        let text = fs::read_to_string(path.as_ref())
+            .map_err(|err| format!("Could not read {}: {}", path.as_ref().display(), err))?;
+        Keymap::parse(&text, rom_hash)
+    }
+
+    /// Parses a keymap in TOML for the ROM with the given hash. Each entry maps a host key to a keypad key:
+    ///
+    /// ```toml
+    /// [keys]
+    /// up = 5
+    /// space = "A"
+    ///
+    /// [roms.<ROM SHA-1>]
+    /// enter = 6
+    /// ```
+    ///
+    /// The `keys` table replaces the standard layout. The table of the ROM, if any, is then applied on top of it
+    pub fn parse(text: &str, rom_hash: &str) -> Result<Keymap, String> {
+        let value: Value = text
+            .parse()
+            .map_err(|err| format!("Invalid keymap: {}", err))?;
+
+        let mut keymap = match value.get("keys") {
+            Some(keys) => {
+                let mut keymap = Keymap::empty();
+                keymap.apply(keys)?;
+                keymap
+            }
+            None => Keymap::new(),
+        };
+        if let Some(keys) = value.get("roms").and_then(|roms| roms.get(rom_hash)) {
+            keymap.apply(keys)?;
+        }
+
+        Ok(keymap)
+    }
+
+    /// Maps a host key to a keypad key (0x0 to 0xF)
+    pub fn set(&mut self, host_key: &str, key: u8) {
+        self.keys.insert(normalize(host_key), key);
+    }
+
+    /// Returns the keypad key a host key is mapped to, if any
+    pub fn get(&self, host_key: &str) -> Option<u8> {
+        self.keys.get(&normalize(host_key)).copied()
+    }
+
+    /// Applies every entry of a TOML table
+    fn apply(&mut self, table: &Value) -> Result<(), String> {
+        let table = table
+            .as_table()
+            .ok_or_else(|| "Invalid keymap: expected a table of keys".to_string())?;
+
+        for (host_key, key) in table {
+            // The gamepad buttons of a ROM live in its table too. See `ButtonMap`
+            if host_key == "gamepad" && key.is_table() {
+                continue;
+            }
+            let key = match key {
+                Value::Integer(key) => *key,
+                Value::String(key) => i64::from_str_radix(key, 16).unwrap_or(-1),
+                _ => -1,
+            };
+            if key < 0 || key >= KEY_COUNT as i64 {
+                return Err(format!(
+                    "Invalid keymap: {} is not mapped to a key from 0 to F",
+                    host_key
+                ));
+            }
+            self.set(host_key, key as u8);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::new()
+    }
+}
+
+/// Normalizes the name of a host key, so that `Q` and `q` or `Key1` and `1` are the same key
+fn normalize(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.strip_prefix("key") {
+        Some(digit) if digit.len() == 1 && digit.chars().all(|c| c.is_ascii_digit()) => {
+            digit.to_string()
+        }
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM_HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn default_layout() {
+        let keymap = Keymap::new();
+        assert_eq!(keymap.get("1"), Some(0x1));
+        assert_eq!(keymap.get("4"), Some(0xC));
+        assert_eq!(keymap.get("Q"), Some(0x4));
+        assert_eq!(keymap.get("Key1"), Some(0x1));
+        assert_eq!(keymap.get("x"), Some(0x0));
+        assert_eq!(keymap.get("v"), Some(0xF));
+        assert_eq!(keymap.get("space"), None);
+        assert_eq!(Keymap::parse("", ROM_HASH), Ok(Keymap::new()));
+    }
+
+    #[test]
+    fn parses_keys() {
+        let keymap = Keymap::parse("[keys]\nup = 5\nspace = \"A\"\nKey2 = 0\n", ROM_HASH).unwrap();
+        assert_eq!(keymap.get("Up"), Some(0x5));
+        assert_eq!(keymap.get("space"), Some(0xA));
+        assert_eq!(keymap.get("2"), Some(0x0));
+        // The keys table replaces the standard layout
+        assert_eq!(keymap.get("q"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_keymaps() {
+        assert!(Keymap::parse("[keys]\nup = 16\n", ROM_HASH).is_err());
+        assert!(Keymap::parse("[keys]\nup = -1\n", ROM_HASH).is_err());
+        assert!(Keymap::parse("[keys]\nup = \"G\"\n", ROM_HASH).is_err());
+        assert!(Keymap::parse("[keys]\nup = true\n", ROM_HASH).is_err());
+        assert!(Keymap::parse("keys = 5\n", ROM_HASH).is_err());
+        assert!(Keymap::parse("[keys\nup = 5\n", ROM_HASH).is_err());
+        assert!(Keymap::load("/nonexistent/keymap.toml", ROM_HASH).is_err());
+    }
+
+    #[test]
+    fn rom_keys_override_the_others() {
+        let text = format!(
+            "[keys]\nup = 5\ndown = 8\n\n[roms.{}]\nup = 2\nenter = 6\n\n[roms.{}.gamepad]\nsouth = 6\n",
+            ROM_HASH, ROM_HASH
+        );
+        let keymap = Keymap::parse(&text, ROM_HASH).unwrap();
+        assert_eq!(keymap.get("up"), Some(0x2));
+        assert_eq!(keymap.get("down"), Some(0x8));
+        assert_eq!(keymap.get("enter"), Some(0x6));
+
+        // Other ROMs only get the keys table
+        let keymap = Keymap::parse(&text, "ffffffffffffffffffffffffffffffffffffffff").unwrap();
+        assert_eq!(keymap.get("up"), Some(0x5));
+        assert_eq!(keymap.get("enter"), None);
+
+        // Without a keys table, the ROM keys are added to the standard layout
+        let keymap = Keymap::parse(&format!("[roms.{}]\nq = 9\n", ROM_HASH), ROM_HASH).unwrap();
+        assert_eq!(keymap.get("q"), Some(0x9));
+        assert_eq!(keymap.get("w"), Some(0x5));
+    }
+}
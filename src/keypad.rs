@@ -0,0 +1,105 @@
+/// Amount of keys on the CHIP-8 keypad
+pub const KEY_COUNT: u8 = 16;
+
+/// The hexadecimal keypad. Holds which of the 16 keys are down, one bit per key (bit n is key n).
+/// It knows nothing about the host keyboard: the frontend presses and releases keys as its backend reports them
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Keypad {
+    /// Keys currently held down
+    pressed: u16,
+}
+
+impl Keypad {
+    /// Creates a keypad with every key up
+    pub fn new() -> Keypad {
+        Keypad { pressed: 0 }
+    }
+
+    /// Puts a key (0x0 to 0xF) down. Other values are ignored
+    pub fn press(&mut self, key: u8) {
+        if key < KEY_COUNT {
+            self.pressed |= 1 << key;
+        }
+    }
+
+    /// Releases a key (0x0 to 0xF). Other values are ignored
+    pub fn release(&mut self, key: u8) {
+        if key < KEY_COUNT {
+            self.pressed &= !(1 << key);
+        }
+    }
+
+    /// Returns true if the given key is down. Only the low nibble is used, as on the original interpreter
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.pressed & 1 << (key & 0xF) != 0
+    }
+
+    /// Returns the state of every key, one bit per key
+    pub fn get_state(&self) -> u16 {
+        self.pressed
+    }
+
+    /// Sets the state of every key, one bit per key
+    pub fn set_state(&mut self, pressed: u16) {
+        self.pressed = pressed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+
+    #[test]
+    fn keys_are_bits() {
+        let mut keypad = Keypad::new();
+        keypad.press(0x0);
+        keypad.press(0x5);
+        keypad.press(0xF);
+        keypad.press(0x10);
+        assert_eq!(keypad.get_state(), 0b1000_0000_0010_0001);
+
+        keypad.release(0x5);
+        keypad.release(0x5);
+        assert_eq!(keypad.get_state(), 0b1000_0000_0000_0001);
+        assert!(keypad.is_pressed(0xF));
+        assert!(!keypad.is_pressed(0x5));
+        // Only the low nibble counts
+        assert!(keypad.is_pressed(0x10));
+    }
+
+    /// Runs a program that counts in v2 if key 5 is up (Ex9E) and in v3 if key A is down (ExA1), with the given keys
+    /// held, and returns both counts
+    fn skips_with(keys: &[u8]) -> (u8, u8) {
+        let mut chip8 = Chip8::new();
+        chip8
+            .load_rom(&[
+                0x60, 0x05, 0x61, 0x0A, 0xE0, 0x9E, 0x72, 0x01, 0xE1, 0xA1, 0x73, 0x01, 0x12, 0x0C,
+            ])
+            .unwrap();
+        for key in keys {
+            chip8.press_key(*key);
+        }
+        for _ in 0..6 {
+            chip8.run_next_instruction();
+        }
+        (chip8.get_vx(2), chip8.get_vx(3))
+    }
+
+    #[test]
+    fn skips_see_every_key_held() {
+        assert_eq!(skips_with(&[]), (1, 0));
+        assert_eq!(skips_with(&[0x5]), (0, 0));
+        assert_eq!(skips_with(&[0xA]), (1, 1));
+        assert_eq!(skips_with(&[0x3, 0x5, 0xA, 0xF]), (0, 1));
+    }
+
+    #[test]
+    fn released_keys_are_up() {
+        let mut chip8 = Chip8::new();
+        chip8.press_key(0x5);
+        chip8.press_key(0xA);
+        chip8.release_key(0x5);
+        assert_eq!(chip8.get_keypad().get_state(), 1 << 0xA);
+    }
+}
@@ -0,0 +1,34 @@
+//! A CHIP-8 emulator core.
+//!
+//! `Chip8` runs without any window or sound device. It is stepped one instruction at a time with
+//! `run_next_instruction()` or one frame at a time with `run_frame()`, and its framebuffer, registers
+//! and sound state can be queried in between. The `frontend` module drives it through a `VideoBackend`,
+//! such as a minifb window or the headless backend, and plays its sound through an `AudioBackend`.
+
+pub mod assembler;
+pub mod audio;
+pub mod chip8;
+pub mod config;
+pub mod cpu;
+pub mod database;
+pub mod debugger;
+pub mod disassembler;
+pub mod display;
+pub mod flags;
+pub mod frontend;
+pub mod gamepad;
+pub mod gdb;
+pub mod instructions;
+pub mod keymap;
+pub mod keypad;
+pub mod memory;
+pub mod octo;
+pub mod paths;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+pub mod state;
+pub mod timing;
+pub mod video;
+
+pub use crate::chip8::Chip8;
@@ -1,31 +1,312 @@
-mod chip8;
-mod cpu;
-mod display;
-mod instructions;
-mod memory;
-
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use std::fs::File;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use chip8::assembler::{self, AssembleError, Program};
+use chip8::audio::{AudioBackend, NullAudio, WavAudio};
+use chip8::chip8::rom_hash;
+use chip8::config::{self, Config};
+use chip8::database::{Database, RomInfo};
+use chip8::debugger::{Debugger, Symbols};
+use chip8::disassembler::{self, Syntax, SYNTAXES};
+use chip8::flags::FlagStorage;
+use chip8::frontend::Frontend;
+use chip8::gamepad::{ButtonMap, GamepadSource};
+use chip8::gdb::GdbStub;
+use chip8::keymap::Keymap;
+use chip8::octo;
+use chip8::quirks::{self, Quirks};
+use chip8::random::{RandomMode, RANDOM_MODES};
+use chip8::rewind::Rewind;
+use chip8::state::StateSlots;
+use chip8::video::{HeadlessVideo, VideoBackend};
 use chip8::Chip8;
 
 fn main() {
+    let quirk_help = format!(
+        "Overrides a single quirk of the profile. Flags: {}",
+        quirks::FLAGS.join(", ")
+    );
+
     // Info about the program and arguments
     let args = App::new(clap::crate_name!())
         .version(clap::crate_version!())
         .author(clap::crate_authors!())
         .about(clap::crate_description!())
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("rom")
                 .value_name("PATH_TO_ROM")
                 .help("Path to ROM file")
                 .takes_value(true)
-                .required(true),
+                .required_unless("print-config"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("PATH")
+                .help("Configuration file. Defaults to ~/.config/chip8-rs/config.toml, if it exists")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("print-config")
+                .long("print-config")
+                .help("Prints the configuration in use, after applying the arguments, and exits"),
+        )
+        .arg(
+            Arg::with_name("scale")
+                .long("scale")
+                .value_name("N")
+                .help("Window scale")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("foreground")
+                .long("foreground")
+                .value_name("#RRGGBB")
+                .help("Color of the pixels that are on")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("background")
+                .long("background")
+                .value_name("#RRGGBB")
+                .help("Color of the pixels that are off")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tone-frequency")
+                .long("tone-frequency")
+                .value_name("HZ")
+                .help("Frequency of the beep")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("volume")
+                .long("volume")
+                .value_name("VOLUME")
+                .help("Volume of the sound, from 0.0 to 1.0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("headless")
+                .long("headless")
+                .help("Runs without opening a window"),
+        )
+        .arg(
+            Arg::with_name("quirks")
+                .long("quirks")
+                .value_name("PROFILE")
+                .help("Interpreter whose behavior is emulated for ambiguous instructions")
+                .takes_value(true)
+                .possible_values(&quirks::PROFILES),
+        )
+        .arg(
+            Arg::with_name("quirk")
+                .long("quirk")
+                .value_name("FLAG=on|off")
+                .help(&quirk_help)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("xo-chip")
+                .long("xo-chip")
+                .help("Runs XO-CHIP programs: 64 KiB of memory, two bitplanes and audio patterns"),
+        )
+        .arg(
+            Arg::with_name("ipf")
+                .long("ipf")
+                .value_name("N")
+                .help("Instructions run per frame. There are 60 frames per second")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("vip-timing")
+                .long("vip-timing")
+                .help("Runs instructions at the speed of the COSMAC VIP, each taking its original time. Replaces --ipf"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("N")
+                .help("Seeds the random number generator, so that every run plays out the same way")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("random")
+                .long("random")
+                .value_name("GENERATOR")
+                .help("Random number generator. cosmac-vip is a weak one in the spirit of the original interpreter")
+                .takes_value(true)
+                .possible_values(&RANDOM_MODES)
+                .default_value("xorshift"),
+        )
+        .arg(
+            Arg::with_name("load-state")
+                .long("load-state")
+                .value_name("PATH")
+                .help("Starts from a save state instead of the beginning of the ROM")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rewind")
+                .long("rewind")
+                .value_name("SECONDS")
+                .help("How far back Backspace can rewind. 0 disables rewinding")
+                .takes_value(true)
+                .default_value("10"),
+        )
+        .arg(
+            Arg::with_name("keymap")
+                .long("keymap")
+                .value_name("PATH")
+                .help("Keymap file. Defaults to ~/.config/chip8-rs/keymap.toml, if it exists")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mute")
+                .long("mute")
+                .help("Runs without sound"),
+        )
+        .arg(
+            Arg::with_name("wav")
+                .long("wav")
+                .value_name("PATH")
+                .help("Renders the sound to a WAV file instead of playing it")
+                .takes_value(true)
+                .conflicts_with("mute"),
+        )
+        .arg(
+            Arg::with_name("frames")
+                .long("frames")
+                .value_name("N")
+                .help("Stops after N frames. Only used with --headless")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("debug")
+                .long("debug")
+                .help("Halts before the first instruction and reads debugger commands from the terminal"),
+        )
+        .arg(
+            Arg::with_name("gdb")
+                .long("gdb")
+                .value_name("PORT")
+                .help("Waits for a GDB client on the given local port, then halts before the first instruction")
+                .takes_value(true)
+                .conflicts_with("debug"),
+        )
+        .arg(
+            Arg::with_name("symbols")
+                .long("symbols")
+                .value_name("PATH")
+                .help("Reads debugger labels from a symbol file. Defaults to the ROM path with a .sym extension")
+                .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name("disasm")
+                .about("Prints the instructions of a ROM")
+                .arg(
+                    Arg::with_name("rom")
+                        .value_name("PATH_TO_ROM")
+                        .help("Path to ROM file")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("syntax")
+                        .long("syntax")
+                        .value_name("SYNTAX")
+                        .help("Mnemonics to use")
+                        .takes_value(true)
+                        .possible_values(&SYNTAXES)
+                        .default_value("octo"),
+                )
+                .arg(
+                    Arg::with_name("recursive")
+                        .long("recursive")
+                        .help("Follows jumps, calls and skips from the first instruction to tell code from data"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("assemble")
+                .about("Assembles a source file into a ROM and a symbol file")
+                .arg(
+                    Arg::with_name("source")
+                        .value_name("PATH_TO_SOURCE")
+                        .help("Path to source file")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("PATH_TO_ROM")
+                        .help("Where to write the ROM. Defaults to the source path with a .ch8 extension")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("octo")
+                .about("Works with Octo source code")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("build")
+                        .about("Compiles an Octo source file into a ROM and a symbol file")
+                        .arg(
+                            Arg::with_name("source")
+                                .value_name("PATH_TO_SOURCE")
+                                .help("Path to source file")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .short("o")
+                                .long("output")
+                                .value_name("PATH_TO_ROM")
+                                .help("Where to write the ROM. Defaults to the source path with a .ch8 extension")
+                                .takes_value(true),
+                        ),
+                ),
         )
         .get_matches();
 
+    if let Some(args) = args.subcommand_matches("disasm") {
+        disasm(args);
+        return;
+    }
+    if let Some(args) = args.subcommand_matches("assemble") {
+        build(args, assembler::assemble);
+        return;
+    }
+    if let Some(args) = args
+        .subcommand_matches("octo")
+        .and_then(|args| args.subcommand_matches("build"))
+    {
+        build(args, octo::compile);
+        return;
+    }
+
+    // Settings come from the configuration file, and the arguments override them
+    let config_path = match args.value_of("config") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => Config::default_path().filter(|path| path.exists()),
+    };
+    let mut config = match &config_path {
+        Some(path) => Config::load(path).expect("Could not load configuration"),
+        None => Config::new(),
+    };
+    let settings = config::SETTINGS
+        .iter()
+        .filter_map(|name| Some((*name, args.value_of(name)?)));
+    config.set_all(settings).expect("Invalid setting");
+    if args.is_present("print-config") {
+        print!("{}", config.to_toml());
+        return;
+    }
+
     // Get path to ROM from args
     let rom_path = Path::new(args.value_of("rom").unwrap());
 
@@ -41,9 +322,237 @@ fn main() {
     // Creates an instance of the emulator
     let mut chip8 = Chip8::new();
 
-    // Loads ROM to RAM
-    chip8.load_rom(&data);
+    // Settings of known ROMs replace the ones of the configuration file, but not the arguments
+    let info = lookup_rom(&rom_hash(&data));
+    if let Some(info) = &info {
+        if info.authors.is_empty() {
+            println!("{}", info.title);
+        } else {
+            println!("{} by {}", info.title, info.authors.join(", "));
+        }
+        if let (Some(tickrate), None) = (info.tickrate, args.value_of("ipf")) {
+            config.instructions_per_frame = tickrate;
+        }
+        if let (Some(color), None) = (info.colors.first(), args.value_of("background")) {
+            config.background = *color;
+        }
+        if let (Some(color), None) = (info.colors.get(1), args.value_of("foreground")) {
+            config.foreground = *color;
+        }
+    }
+
+    // Picks the interpreter behaviors, starting from the profile and then applying each override
+    let known_quirks = info.as_ref().and_then(|info| info.quirks);
+    let mut quirks = match (known_quirks, args.value_of("quirks")) {
+        (Some(quirks), None) => quirks,
+        _ => Quirks::profile(&config.quirks).unwrap(),
+    };
+    for setting in args.values_of("quirk").into_iter().flatten() {
+        quirks.apply_override(setting).expect("Invalid quirk");
+    }
+    chip8.set_quirks(quirks);
+    chip8.set_xo_chip(
+        args.is_present("xo-chip") || info.as_ref().map_or(false, |info| info.xo_chip),
+    );
+
+    // Loads ROM to RAM. It must fit in the memory of the mode picked above
+    chip8.load_rom(&data).expect("Could not load ROM");
+
+    // Random numbers are only reproducible when a seed is given
+    chip8.set_random_mode(RandomMode::from_name(args.value_of("random").unwrap()).unwrap());
+    if let Some(seed) = args.value_of("seed") {
+        chip8.set_seed(seed.parse().expect("Invalid seed"));
+    }
+
+    // Jumps straight to a save state. Its quirks and random number generator replace the ones picked above
+    if let Some(path) = args.value_of("load-state") {
+        let state = std::fs::read(path).expect("Could not open save state");
+        chip8.load_state(&state).expect("Could not load save state");
+    }
+
+    // Maps the keyboard and the gamepad buttons to the keypad. Without a keymap file, the standard layouts are used
+    let keymap_path = match &config.keymap {
+        Some(path) => Some(path.clone()),
+        None => Keymap::default_path().filter(|path| path.exists()),
+    };
+    let (keymap, buttons) = match &keymap_path {
+        Some(path) => (
+            Keymap::load(path, chip8.get_rom_hash()).expect("Could not load keymap"),
+            ButtonMap::load(path, chip8.get_rom_hash()).expect("Could not load keymap"),
+        ),
+        None => (Keymap::new(), ButtonMap::new()),
+    };
+
+    // Picks where the screen is shown
+    let video: Box<dyn VideoBackend> = if args.is_present("headless") {
+        let frames = args
+            .value_of("frames")
+            .map(|n| n.parse().expect("Invalid amount of frames"));
+        Box::new(HeadlessVideo::new(frames))
+    } else {
+        window(&chip8, &config, keymap)
+    };
+
+    // Picks where the sound goes
+    let mut audio: Box<dyn AudioBackend> = if let Some(path) = args.value_of("wav") {
+        Box::new(WavAudio::new(path).expect("Could not create WAV file"))
+    } else if args.is_present("mute") {
+        Box::new(NullAudio)
+    } else {
+        sound_device()
+    };
+    audio.set_tone(config.tone_frequency, config.volume);
+
+    let mut frontend = Frontend::new(video, audio);
+    if let Some(source) = gamepad() {
+        frontend.set_gamepad(source, buttons);
+    }
+    frontend.set_instructions_per_frame(config.instructions_per_frame);
+    frontend.set_vip_timing(args.is_present("vip-timing"));
+
+    // The debugger takes over before the first instruction, and then whenever the CPU halts
+    if args.is_present("debug") {
+        let mut debugger = Debugger::new();
+        let symbols_path = match args.value_of("symbols") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => Some(rom_path.with_extension("sym")).filter(|path| path.exists()),
+        };
+        if let Some(path) = symbols_path {
+            let text = std::fs::read_to_string(path).expect("Could not open symbol file");
+            debugger.set_symbols(Symbols::parse(&text).expect("Invalid symbol file"));
+        }
+        frontend.set_debugger(Box::new(debugger));
+        chip8.break_now();
+    }
+    if let Some(port) = args.value_of("gdb") {
+        let port = port.parse().expect("Invalid port");
+        println!("Waiting for a GDB client on port {}", port);
+        frontend.set_debugger(Box::new(
+            GdbStub::listen(port).expect("Could not accept GDB client"),
+        ));
+        chip8.break_now();
+    }
+
+    // Restores the RPL user flags saved by previous runs of this ROM
+    if let Some(storage) = FlagStorage::for_rom(chip8.get_rom_hash()) {
+        if let Some(flags) = storage.load() {
+            chip8.set_flags(&flags);
+        }
+        frontend.set_flag_storage(storage);
+    }
+
+    // Save states for the hotkeys are kept per ROM as well
+    if let Some(slots) = StateSlots::for_rom(chip8.get_rom_hash()) {
+        frontend.set_state_slots(slots);
+    }
+
+    // Keeps the last frames around so the user can step back in time
+    let seconds = args
+        .value_of("rewind")
+        .unwrap()
+        .parse()
+        .expect("Invalid amount of seconds");
+    if seconds > 0 {
+        frontend.set_rewind(Rewind::new(seconds));
+    }
 
     // Runs ROM
-    chip8.run();
+    frontend.run(&mut chip8);
+}
+
+/// Prints the disassembly of a ROM: the address, the bytes and the text of every line
+fn disasm(args: &ArgMatches) {
+    let rom = std::fs::read(args.value_of("rom").unwrap()).expect("Could not open ROM");
+    let syntax = Syntax::from_name(args.value_of("syntax").unwrap()).unwrap();
+
+    let lines = if args.is_present("recursive") {
+        disassembler::disassemble_recursive(&rom, syntax)
+    } else {
+        disassembler::disassemble(&rom, syntax)
+    };
+    for line in lines {
+        let bytes: String = line
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        println!("{:04X}  {:<16}  {}", line.addr, bytes, line.text);
+    }
+}
+
+/// Builds a ROM and its symbol file from a source file with the given compiler
+fn build(args: &ArgMatches, compile: fn(&str, &Path) -> Result<Program, AssembleError>) {
+    let source_path = Path::new(args.value_of("source").unwrap());
+    let source = std::fs::read_to_string(source_path).expect("Could not open source file");
+    let rom_path = match args.value_of("output") {
+        Some(path) => PathBuf::from(path),
+        None => source_path.with_extension("ch8"),
+    };
+
+    let program = match compile(&source, source_path) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    std::fs::write(&rom_path, &program.rom).expect("Could not write ROM");
+    std::fs::write(rom_path.with_extension("sym"), program.symbol_file())
+        .expect("Could not write symbol file");
+}
+
+/// Looks a ROM up in the user database first, then in the bundled one
+fn lookup_rom(rom_hash: &str) -> Option<RomInfo> {
+    let user = Database::user_path()
+        .filter(|path| path.exists())
+        .map(|path| Database::load(path).expect("Could not load ROM database"));
+
+    user.and_then(|database| database.lookup(rom_hash))
+        .or_else(|| Database::bundled().lookup(rom_hash))
+}
+
+/// Creates a window big enough for the emulator screen
+#[cfg(feature = "minifb")]
+fn window(chip8: &Chip8, config: &Config, keymap: Keymap) -> Box<dyn VideoBackend> {
+    let (width, height) = chip8.get_resolution();
+    let mut window = chip8::video::MinifbVideo::new(width, height, config.scale, keymap);
+    window.set_palette(config.get_palette());
+    Box::new(window)
+}
+
+/// Windows are not available without minifb
+#[cfg(not(feature = "minifb"))]
+fn window(_chip8: &Chip8, _config: &Config, _keymap: Keymap) -> Box<dyn VideoBackend> {
+    panic!("This build has no window support. Run it with --headless");
+}
+
+/// Plays sound on the default sound device
+#[cfg(feature = "rodio")]
+fn sound_device() -> Box<dyn AudioBackend> {
+    Box::new(chip8::audio::RodioAudio::new())
+}
+
+/// Sound devices are not available without rodio
+#[cfg(not(feature = "rodio"))]
+fn sound_device() -> Box<dyn AudioBackend> {
+    println!("This build has no sound support!");
+    Box::new(NullAudio)
+}
+
+/// Reads the gamepads connected to the machine
+#[cfg(feature = "gilrs")]
+fn gamepad() -> Option<Box<dyn GamepadSource>> {
+    match chip8::gamepad::GilrsGamepad::new() {
+        Ok(gamepad) => Some(Box::new(gamepad)),
+        Err(err) => {
+            println!("Gamepads are not available: {}", err);
+            None
+        }
+    }
+}
+
+/// Gamepads are not available without gilrs
+#[cfg(not(feature = "gilrs"))]
+fn gamepad() -> Option<Box<dyn GamepadSource>> {
+    None
 }
@@ -1,17 +1,35 @@
 use crate::chip8::Address;
+use crate::debugger::{Access, Watchpoint};
+use crate::state::{StateReader, StateWriter};
+
+/// Address of the first big font sprite
+pub const BIG_FONT_START: Address = 0x50;
+
+/// Size of the RAM. The original CHIP-8 only had 4 KiB (0x000 to 0xFFF), but XO-CHIP programs can address 64 KiB
+pub const MEMORY_SIZE: usize = 0x10000;
+/// Memory addressable without XO-CHIP
+pub const ORIGINAL_MEMORY_SIZE: usize = 0x1000;
 
 /// Struct that represents the RAM memory of the machine
 pub struct Memory {
-    ram: [u8; 0x1000],
+    ram: Vec<u8>,
+    /// Ranges of memory watched by the debugger
+    watchpoints: Vec<Watchpoint>,
+    /// First watched access since the last call to `take_watch_hit()`: the address and whether it was a read or a write
+    watch_hit: Option<(Address, Access)>,
 }
 
 impl Memory {
-
     /// Creates a new instance of Memory with the default interpreter values
     pub fn new() -> Memory {
-        let mut mem = Memory { ram: [0; 0x1000] };
+        let mut mem = Memory {
+            ram: vec![0; MEMORY_SIZE],
+            watchpoints: vec![],
+            watch_hit: None,
+        };
 
         mem.load_font_sprites();
+        mem.load_big_font_sprites();
 
         mem
     }
@@ -49,13 +67,232 @@ impl Memory {
         }
     }
 
+    /// Loads the SUPER-CHIP big font sprites (8x10) to memory, right after the default ones
+    /// The occupied memory address range from 0x50 to 0xF0
+    fn load_big_font_sprites(&mut self) {
+        // Big font sprites. SUPER-CHIP only had digits, the letters are the same as Octo's
+        let sprites: [[u8; 10]; 0x10] = [
+            [0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C], // 0
+            [0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C], // 1
+            [0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF], // 2
+            [0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C], // 3
+            [0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06], // 4
+            [0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C], // 5
+            [0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C], // 6
+            [0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18], // 7
+            [0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C], // 8
+            [0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C], // 9
+            [0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3], // A
+            [0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC], // B
+            [0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C], // C
+            [0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC], // D
+            [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF], // E
+            [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0], // F
+        ];
+
+        // Load them to memory
+        let mut i = BIG_FONT_START;
+        for sprite in sprites.iter() {
+            for byte in sprite.iter() {
+                self.write_byte(i, *byte);
+                i += 1;
+            }
+        }
+    }
+
     /// Writes byte to memory
     pub fn write_byte(&mut self, index: Address, byte: u8) {
+        self.watch(index, Access::Write);
         self.ram[index as usize] = byte;
     }
 
+    /// Writes byte to memory without triggering the watchpoints. Used to change the memory from outside
+    pub fn poke_byte(&mut self, index: Address, byte: u8) {
+        self.ram[index as usize] = byte;
+    }
+
+    /// Writes the whole memory to a save state
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+    }
+
+    /// Restores the whole memory from a save state
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.ram.copy_from_slice(state.read_bytes(MEMORY_SIZE)?);
+        Ok(())
+    }
+
     /// Reads byte from memory
     pub fn read_byte(&mut self, index: Address) -> u8 {
+        self.watch(index, Access::Read);
+        self.ram[index as usize]
+    }
+
+    /// Reads byte from memory without the program noticing. Used to inspect the memory from outside
+    pub fn peek_byte(&self, index: Address) -> u8 {
         self.ram[index as usize]
     }
+
+    /// Watches a range of memory. The first access to it is kept until `take_watch_hit()` is called
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Removes the watchpoint with the given index. Returns false if there is none
+    pub fn remove_watchpoint(&mut self, index: usize) -> bool {
+        if index >= self.watchpoints.len() {
+            return false;
+        }
+        self.watchpoints.remove(index);
+        true
+    }
+
+    /// Returns the watchpoints, in the order they were added
+    pub fn get_watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Returns the first watched access since the last call, if any: the address and whether it was a read or a write
+    pub fn take_watch_hit(&mut self) -> Option<(Address, Access)> {
+        self.watch_hit.take()
+    }
+
+    /// Records an access if a watchpoint covers it and none was recorded yet
+    fn watch(&mut self, index: Address, access: Access) {
+        if self.watch_hit.is_none()
+            && self
+                .watchpoints
+                .iter()
+                .any(|watchpoint| watchpoint.matches(index, access))
+        {
+            self.watch_hit = Some((index, access));
+        }
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::cpu::CPU;
+    use crate::debugger::{BreakReason, Condition};
+
+    fn watched(start: Address, end: Address, access: Access) -> Memory {
+        let mut memory = Memory::new();
+        memory.add_watchpoint(Watchpoint { start, end, access });
+        memory
+    }
+
+    #[test]
+    fn accesses_trigger_watchpoints() {
+        let mut memory = watched(0x300, 0x30F, Access::ReadWrite);
+        memory.write_byte(0x2FF, 1);
+        memory.read_byte(0x310);
+        assert_eq!(memory.take_watch_hit(), None);
+
+        memory.write_byte(0x300, 1);
+        // Only the first access is kept
+        memory.read_byte(0x30F);
+        assert_eq!(memory.take_watch_hit(), Some((0x300, Access::Write)));
+        assert_eq!(memory.take_watch_hit(), None);
+        memory.read_byte(0x30F);
+        assert_eq!(memory.take_watch_hit(), Some((0x30F, Access::Read)));
+
+        // Each kind of watchpoint only sees its own accesses
+        let mut memory = watched(0x300, 0x300, Access::Read);
+        memory.write_byte(0x300, 1);
+        assert_eq!(memory.take_watch_hit(), None);
+        memory.read_byte(0x300);
+        assert_eq!(memory.take_watch_hit(), Some((0x300, Access::Read)));
+
+        let mut memory = watched(0x300, 0x300, Access::Write);
+        memory.read_byte(0x300);
+        assert_eq!(memory.take_watch_hit(), None);
+        memory.write_byte(0x300, 1);
+        assert_eq!(memory.take_watch_hit(), Some((0x300, Access::Write)));
+    }
+
+    #[test]
+    fn peek_and_poke_do_not_trigger_watchpoints() {
+        let mut memory = watched(0x300, 0x300, Access::ReadWrite);
+        memory.poke_byte(0x300, 7);
+        assert_eq!(memory.peek_byte(0x300), 7);
+        assert_eq!(memory.take_watch_hit(), None);
+
+        assert!(memory.remove_watchpoint(0));
+        assert!(!memory.remove_watchpoint(0));
+        memory.read_byte(0x300);
+        assert_eq!(memory.take_watch_hit(), None);
+    }
+
+    #[test]
+    fn conditions_trigger_when_they_become_true() {
+        let mut cpu = CPU::new();
+        cpu.add_condition(Condition::parse("V3 >= 10").unwrap());
+        cpu.add_condition(Condition::parse("I == 300").unwrap());
+        cpu.set_vx(3, 0x0F);
+        cpu.set_vx(2, 0x10);
+        cpu.set_i(0x200);
+        assert_eq!(cpu.take_condition_hit(), None);
+
+        cpu.set_vx(3, 0x10);
+        assert_eq!(
+            cpu.take_condition_hit(),
+            Some(Condition::parse("V3 >= 10").unwrap())
+        );
+        // It already held
+        cpu.set_vx(3, 0x20);
+        assert_eq!(cpu.take_condition_hit(), None);
+
+        cpu.set_i(0x300);
+        assert_eq!(
+            cpu.take_condition_hit(),
+            Some(Condition::parse("I == 300").unwrap())
+        );
+    }
+
+    #[test]
+    fn instructions_halt_on_watches() {
+        // i := 0x300, v0 := 5, save v0, v1 := 9, v2 := 1
+        let rom = [0xA3, 0x00, 0x60, 0x05, 0xF0, 0x55, 0x61, 0x09, 0x62, 0x01];
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&rom).unwrap();
+        chip8.add_watchpoint(Watchpoint {
+            start: 0x300,
+            end: 0x300,
+            access: Access::Write,
+        });
+        chip8.add_condition(Condition::parse("V1 == 9").unwrap());
+
+        // The CPU halts right after the instruction
+        chip8.run_frame(10);
+        assert_eq!(chip8.get_pc(), 0x206);
+        match chip8.get_break() {
+            Some(BreakReason::Watchpoint {
+                addr, access, pc, ..
+            }) => assert_eq!((addr, access, pc), (0x300, Access::Write, 0x204)),
+            reason => panic!("{:?}", reason),
+        }
+
+        chip8.resume();
+        chip8.run_frame(10);
+        match chip8.get_break() {
+            Some(BreakReason::Condition { pc, .. }) => assert_eq!(pc, 0x206),
+            reason => panic!("{:?}", reason),
+        }
+
+        // Changes made from outside are not watched
+        chip8.resume();
+        chip8.set_vx(1, 0);
+        chip8.set_vx(1, 9);
+        chip8.run_frame(1);
+        assert_eq!(chip8.get_break(), None);
+        assert_eq!(chip8.get_pc(), 0x20A);
+    }
 }
@@ -0,0 +1,977 @@
+use crate::assembler::{AssembleError, Program};
+use crate::chip8::{Address, Register, PROGRAM_START};
+use crate::instructions::Instructions;
+use crate::instructions::Instructions::*;
+use crate::memory::MEMORY_SIZE;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Most macro expansions in a program. Macros that expand themselves stop there
+const MAX_EXPANSIONS: usize = 0x10000;
+/// Binary operators of `:calc`. There is no precedence: expressions are evaluated from right to left
+const OPERATORS: [&str; 19] = [
+    "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "min", "max", "<", ">", "<=", ">=", "==",
+    "!=", "pow",
+];
+
+/// Word of the source code, and where it is
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+/// Macro defined with `:macro`
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// Address of an instruction that refers to a label defined further down
+struct Fixup {
+    addr: usize,
+    label: Token,
+    /// The instruction is `i := long`, whose address is the whole second word
+    long: bool,
+}
+
+/// Block of code waiting for the word that closes it
+enum Block {
+    /// `loop`, closed by `again`, with the jumps of its `while`s, which go after `again`
+    Loop {
+        start: Address,
+        exits: Vec<usize>,
+        token: Token,
+    },
+    /// `if ... begin` or `else`, closed by `else` or `end`, with the jump that skips the block
+    If {
+        jump: usize,
+        token: Token,
+        has_else: bool,
+    },
+}
+
+/// State of the compiler. The source is compiled in one pass, and addresses of labels that are not defined yet are
+/// filled in at the end
+struct Compiler {
+    file: String,
+    /// Tokens left to compile, the next one last
+    tokens: Vec<Token>,
+    /// Memory from PROGRAM_START
+    rom: Vec<u8>,
+    /// Address of the next byte
+    here: usize,
+    labels: HashMap<String, Address>,
+    /// Labels in the order they were defined
+    symbols: Vec<(String, Address)>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, Register>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    expansions: usize,
+}
+
+/// Compiles Octo source code read from the given path. The program starts with a jump to the `main` label.
+///
+/// ```text
+/// :alias x v0
+/// :const speed 2
+///
+/// : main
+///     x := 0
+///     loop
+///         x += speed
+///         if x == 64 then x := 0
+///     again
+/// ```
+///
+/// Supported are the instructions of `Instructions`, `:`, `:alias`, `:const`, `:macro`, `:calc`, `:byte`, `:org`,
+/// `:call`, `loop` / `while` / `again` and `if` / `then`, or `if` / `begin` / `else` / `end` with `==`, `!=`, `key`
+/// and `-key`. Numbers on their own are inserted as bytes.
+pub fn compile(source: &str, path: &Path) -> Result<Program, AssembleError> {
+    let mut tokens = tokenize(source);
+    tokens.reverse();
+    let mut compiler = Compiler {
+        file: path.display().to_string(),
+        tokens,
+        // Room for the jump to main
+        rom: vec![0; 2],
+        here: PROGRAM_START as usize + 2,
+        labels: HashMap::new(),
+        symbols: vec![],
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: vec![],
+        blocks: vec![],
+        expansions: 0,
+    };
+
+    while let Some(token) = compiler.tokens.pop() {
+        compiler.statement(token)?;
+    }
+    match compiler.blocks.last() {
+        Some(Block::Loop { token, .. }) => return Err(compiler.error(token, "loop without again")),
+        Some(Block::If { token, .. }) => return Err(compiler.error(token, "begin without end")),
+        None => (),
+    }
+    compiler.resolve()?;
+
+    let main = match compiler.labels.get("main") {
+        Some(main) => *main,
+        None => {
+            return Err(AssembleError {
+                file: compiler.file,
+                line: 1,
+                column: 1,
+                message: "The program has no main label".to_string(),
+            })
+        }
+    };
+    compiler.put(PROGRAM_START as usize, Jump(main));
+
+    Ok(Program {
+        rom: compiler.rom,
+        symbols: compiler.symbols,
+    })
+}
+
+impl Compiler {
+    /// Compiles a statement, reading the tokens after it as needed
+    fn statement(&mut self, token: Token) -> Result<(), AssembleError> {
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next(&token)?;
+                self.check_name(&name)?;
+                let addr = self.here as Address;
+                // The jump to main at PROGRAM_START only reaches 12-bit addresses
+                if name.text == "main" && addr > 0xFFF {
+                    return Err(self.error(&name, format!("main at {:#06X} is out of reach", addr)));
+                }
+                self.labels.insert(name.text.clone(), addr);
+                self.symbols.push((name.text, addr));
+            }
+            ":alias" => {
+                let name = self.next(&token)?;
+                let x = self.vx(&name)?;
+                self.aliases.insert(name.text, x);
+            }
+            ":const" => {
+                let name = self.next(&token)?;
+                self.check_name(&name)?;
+                let value = self.value(&name, i64::MIN, i64::MAX)?;
+                self.constants.insert(name.text, value);
+            }
+            ":calc" => {
+                let name = self.next(&token)?;
+                self.check_name(&name)?;
+                let value = self.braced_calc(&name)?;
+                self.constants.insert(name.text, value);
+            }
+            ":byte" => {
+                let value = match self.tokens.last() {
+                    Some(next) if next.text == "{" => self.braced_calc(&token)?,
+                    _ => self.value(&token, -0x80, 0xFF)?,
+                };
+                if !(-0x80..=0xFF).contains(&value) {
+                    return Err(self.error(&token, format!("{} does not fit in a byte", value)));
+                }
+                self.emit(&[value as u8], &token)?;
+            }
+            // The first word of the program is the jump to main
+            ":org" => {
+                self.here =
+                    self.value(&token, PROGRAM_START as i64 + 2, MEMORY_SIZE as i64 - 1)? as usize
+            }
+            ":macro" => self.define_macro(&token)?,
+            ":call" => {
+                let addr = self.address(&token)?;
+                self.emit_inst(Call(addr), &token)?;
+            }
+            "clear" => self.emit_inst(ClearDisplay, &token)?,
+            "return" | ";" => self.emit_inst(Return, &token)?,
+            "scroll-down" => {
+                let n = self.value(&token, 0, 0xF)?;
+                self.emit_inst(ScrollDown(n as u8), &token)?;
+            }
+            "scroll-up" => {
+                let n = self.value(&token, 0, 0xF)?;
+                self.emit_inst(ScrollUp(n as u8), &token)?;
+            }
+            "scroll-right" => self.emit_inst(ScrollRight, &token)?,
+            "scroll-left" => self.emit_inst(ScrollLeft, &token)?,
+            "exit" => self.emit_inst(Exit, &token)?,
+            "lores" => self.emit_inst(LowRes, &token)?,
+            "hires" => self.emit_inst(HighRes, &token)?,
+            "audio" => self.emit_inst(LoadAudioPattern, &token)?,
+            "jump" => {
+                let addr = self.address(&token)?;
+                self.emit_inst(Jump(addr), &token)?;
+            }
+            "jump0" => {
+                let addr = self.address(&token)?;
+                self.emit_inst(JumpPlusV0(addr), &token)?;
+            }
+            "sprite" => {
+                let x = self.vx(&token)?;
+                let y = self.vx(&token)?;
+                let n = self.value(&token, 0, 0xF)?;
+                self.emit_inst(Draw(x, y, n as u8), &token)?;
+            }
+            "plane" => {
+                let n = self.value(&token, 0, 0xF)?;
+                self.emit_inst(SelectPlanes(n as u8), &token)?;
+            }
+            "bcd" => {
+                let x = self.vx(&token)?;
+                self.emit_inst(BCDRepresentation(x), &token)?;
+            }
+            "save" | "load" => {
+                let x = self.vx(&token)?;
+                let inst = match (token.text.as_str(), self.range_end(&token)?) {
+                    ("save", Some(y)) => CopyRangeMemory(x, y),
+                    ("save", None) => CopyRegistersMemory(x),
+                    (_, Some(y)) => SetRangeMemory(x, y),
+                    (_, None) => SetRegistersMemory(x),
+                };
+                self.emit_inst(inst, &token)?;
+            }
+            "saveflags" => {
+                let x = self.vx(&token)?;
+                self.emit_inst(SaveFlags(x), &token)?;
+            }
+            "loadflags" => {
+                let x = self.vx(&token)?;
+                self.emit_inst(LoadFlags(x), &token)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(&token, ":=")?;
+                let x = self.vx(&token)?;
+                let inst = match token.text.as_str() {
+                    "delay" => SetDelayTimer(x),
+                    "buzzer" => SetSoundTimer(x),
+                    _ => SetPitch(x),
+                };
+                self.emit_inst(inst, &token)?;
+            }
+            "i" => self.assign_i(&token)?,
+            "if" => self.if_statement(&token)?,
+            "else" => match self.blocks.pop() {
+                Some(Block::If {
+                    jump,
+                    has_else: false,
+                    ..
+                }) => {
+                    let end = self.here;
+                    self.emit_inst(Jump(0), &token)?;
+                    let target = self.target(&token)?;
+                    self.put(jump, Jump(target));
+                    self.blocks.push(Block::If {
+                        jump: end,
+                        token,
+                        has_else: true,
+                    });
+                }
+                _ => return Err(self.error(&token, "else without begin")),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let target = self.target(&token)?;
+                    self.put(jump, Jump(target));
+                }
+                _ => return Err(self.error(&token, "end without begin")),
+            },
+            "loop" => {
+                let start = self.target(&token)?;
+                self.blocks.push(Block::Loop {
+                    start,
+                    exits: vec![],
+                    token,
+                });
+            }
+            "while" => {
+                let (skip_if, _) = self.condition(&token)?;
+                self.emit_inst(skip_if, &token)?;
+                let exit = self.here;
+                self.emit_inst(Jump(0), &token)?;
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|block| matches!(block, Block::Loop { .. }))
+                {
+                    Some(Block::Loop { exits, .. }) => exits.push(exit),
+                    _ => return Err(self.error(&token, "while outside of a loop")),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits, .. }) => {
+                    self.emit_inst(Jump(start), &token)?;
+                    let target = self.target(&token)?;
+                    for exit in exits {
+                        self.put(exit, Jump(target));
+                    }
+                }
+                _ => return Err(self.error(&token, "again without loop")),
+            },
+            text if self.register(text).is_some() => self.assign_register(&token)?,
+            text if self.macros.contains_key(text) => self.expand(&token)?,
+            text if text.starts_with(':') => {
+                return Err(self.error(&token, format!("Unsupported directive: {}", text)))
+            }
+            _ => match self.number(&token) {
+                // Numbers on their own are data
+                Some(value) => {
+                    if !(-0x80..=0xFF).contains(&value) {
+                        return Err(self.error(&token, format!("{} does not fit in a byte", value)));
+                    }
+                    self.emit(&[value as u8], &token)?;
+                }
+                // Any other word calls the label of the same name
+                None => {
+                    let addr = self.label_address(&token)?;
+                    self.emit_inst(Call(addr), &token)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Compiles `i := ...` and `i += vx`
+    fn assign_i(&mut self, token: &Token) -> Result<(), AssembleError> {
+        let op = self.next(token)?;
+        let inst = match op.text.as_str() {
+            ":=" => match self.tokens.last().map(|next| next.text.as_str()) {
+                Some("hex") => {
+                    self.tokens.pop();
+                    SetSpriteI(self.vx(token)?)
+                }
+                Some("bighex") => {
+                    self.tokens.pop();
+                    SetBigSpriteI(self.vx(token)?)
+                }
+                Some("long") => {
+                    self.tokens.pop();
+                    let addr = self.next(token)?;
+                    match self.number(&addr) {
+                        Some(value) if (0..=0xFFFF).contains(&value) => SetLongI(value as Address),
+                        Some(value) => {
+                            return Err(
+                                self.error(&addr, format!("{} does not fit in 16 bits", value))
+                            )
+                        }
+                        None if is_label(&addr.text) => {
+                            self.fixups.push(Fixup {
+                                addr: self.here,
+                                label: addr,
+                                long: true,
+                            });
+                            SetLongI(0)
+                        }
+                        None => {
+                            return Err(
+                                self.error(&addr, format!("Expected an address: {}", addr.text))
+                            )
+                        }
+                    }
+                }
+                _ => SetI(self.address(token)?),
+            },
+            "+=" => AddRegisterI(self.vx(token)?),
+            _ => return Err(self.error(&op, format!("Expected := or += after i: {}", op.text))),
+        };
+        self.emit_inst(inst, token)
+    }
+
+    /// Compiles an assignment to a register, such as `v0 := random 0xFF` or `v1 += v2`
+    fn assign_register(&mut self, token: &Token) -> Result<(), AssembleError> {
+        let x = self.register(&token.text).unwrap();
+        let op = self.next(token)?;
+        let src = self.next(&op)?;
+        let y = self.register(&src.text);
+
+        let inst = match (op.text.as_str(), src.text.as_str(), y) {
+            (":=", _, Some(y)) => SetRegister(x, y),
+            (":=", "random", _) => SetRandAnd(x, self.value(&src, -0x80, 0xFF)? as u8),
+            (":=", "delay", _) => SetToDelayTimer(x),
+            (":=", "key", _) => WaitKeyPress(x),
+            (":=", _, None) => SetRegisterByte(x, self.byte_of(&src)?),
+            ("+=", _, Some(y)) => Add(x, y),
+            ("+=", _, None) => AddByte(x, self.byte_of(&src)?),
+            ("-=", _, Some(y)) => Sub(x, y),
+            ("=-", _, Some(y)) => ReverseSub(x, y),
+            ("|=", _, Some(y)) => Or(x, y),
+            ("&=", _, Some(y)) => And(x, y),
+            ("^=", _, Some(y)) => Xor(x, y),
+            (">>=", _, Some(y)) => ShiftRight(x, y),
+            ("<<=", _, Some(y)) => ShiftLeft(x, y),
+            ("-=", _, None)
+            | ("=-", _, None)
+            | ("|=", _, None)
+            | ("&=", _, None)
+            | ("^=", _, None)
+            | (">>=", _, None)
+            | ("<<=", _, None) => {
+                return Err(self.error(&src, format!("Expected a register: {}", src.text)))
+            }
+            _ => return Err(self.error(&op, format!("Unknown operator: {}", op.text))),
+        };
+        self.emit_inst(inst, token)
+    }
+
+    /// Compiles `if <condition> then` and `if <condition> begin`
+    fn if_statement(&mut self, token: &Token) -> Result<(), AssembleError> {
+        let (skip_if, skip_unless) = self.condition(token)?;
+        let word = self.next(token)?;
+        match word.text.as_str() {
+            "then" => self.emit_inst(skip_unless, token),
+            "begin" => {
+                self.emit_inst(skip_if, token)?;
+                let jump = self.here;
+                self.emit_inst(Jump(0), token)?;
+                self.blocks.push(Block::If {
+                    jump,
+                    token: token.clone(),
+                    has_else: false,
+                });
+                Ok(())
+            }
+            _ => Err(self.error(&word, format!("Expected then or begin: {}", word.text))),
+        }
+    }
+
+    /// Reads a condition. Returns the instruction that skips when it holds, and the one that skips when it does not
+    fn condition(&mut self, token: &Token) -> Result<(Instructions, Instructions), AssembleError> {
+        let x = self.vx(token)?;
+        let op = self.next(token)?;
+        match op.text.as_str() {
+            "==" | "!=" => {
+                let src = self.next(&op)?;
+                let (equal, not_equal) = match self.register(&src.text) {
+                    Some(y) => (SkipIfEquals(x, y), SkipIfNotEquals(x, y)),
+                    None => {
+                        let byte = self.byte_of(&src)?;
+                        (SkipIfEqualsByte(x, byte), SkipIfNotEqualsByte(x, byte))
+                    }
+                };
+                if op.text == "==" {
+                    Ok((equal, not_equal))
+                } else {
+                    Ok((not_equal, equal))
+                }
+            }
+            "key" => Ok((SkipIfKeyPressed(x), SkipIfKeyNotPressed(x))),
+            "-key" => Ok((SkipIfKeyNotPressed(x), SkipIfKeyPressed(x))),
+            _ => Err(self.error(&op, format!("Expected ==, !=, key or -key: {}", op.text))),
+        }
+    }
+
+    /// Reads a macro definition: its name, its parameters and its body between braces
+    fn define_macro(&mut self, token: &Token) -> Result<(), AssembleError> {
+        let name = self.next(token)?;
+        let mut params = vec![];
+        loop {
+            let param = self.next(&name)?;
+            if param.text == "{" {
+                break;
+            }
+            params.push(param.text);
+        }
+
+        let mut body = vec![];
+        let mut depth = 0;
+        loop {
+            let next = self.next(&name)?;
+            match next.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => (),
+            }
+            body.push(next);
+        }
+
+        self.macros.insert(name.text, Macro { params, body });
+        Ok(())
+    }
+
+    /// Expands a macro, putting its body in front of the tokens left, its parameters replaced by the arguments
+    fn expand(&mut self, token: &Token) -> Result<(), AssembleError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(self.error(token, "Too many macro expansions"));
+        }
+
+        let params = self.macros[&token.text].params.clone();
+        let mut args = HashMap::new();
+        for param in params {
+            let arg = self.next(token)?;
+            args.insert(param, arg);
+        }
+
+        let body: Vec<Token> = self.macros[&token.text]
+            .body
+            .iter()
+            .map(|word| args.get(&word.text).unwrap_or(word).clone())
+            .collect();
+        self.tokens.extend(body.into_iter().rev());
+        Ok(())
+    }
+
+    /// Reads a `:calc` expression between braces and evaluates it
+    fn braced_calc(&mut self, token: &Token) -> Result<i64, AssembleError> {
+        self.expect(token, "{")?;
+        let mut expression = vec![];
+        let mut depth = 0;
+        loop {
+            let next = self.next(token)?;
+            match next.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => (),
+            }
+            expression.push(next);
+        }
+
+        let (value, rest) = self.calc(&expression, token)?;
+        match rest.first() {
+            Some(extra) => Err(self.error(extra, format!("Unexpected {}", extra.text))),
+            None => Ok(value),
+        }
+    }
+
+    /// Evaluates the expression at the start of the tokens. Returns its value and the tokens after it
+    fn calc<'a>(
+        &self,
+        tokens: &'a [Token],
+        token: &Token,
+    ) -> Result<(i64, &'a [Token]), AssembleError> {
+        let (left, rest) = self.calc_term(tokens, token)?;
+        let op = match rest.first() {
+            Some(op) if OPERATORS.contains(&op.text.as_str()) => op,
+            _ => return Ok((left, rest)),
+        };
+
+        let (right, rest) = self.calc(&rest[1..], op)?;
+        let value = match op.text.as_str() {
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" | "%" if right == 0 => return Err(self.error(op, "Division by zero")),
+            "/" => left.wrapping_div(right),
+            "%" => left.wrapping_rem(right),
+            "&" => left & right,
+            "|" => left | right,
+            "^" => left ^ right,
+            "<<" => left.wrapping_shl(right as u32),
+            ">>" => left.wrapping_shr(right as u32),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64,
+            ">" => (left > right) as i64,
+            "<=" => (left <= right) as i64,
+            ">=" => (left >= right) as i64,
+            "==" => (left == right) as i64,
+            "!=" => (left != right) as i64,
+            _ => left.wrapping_pow(right as u32),
+        };
+        Ok((value, rest))
+    }
+
+    /// Evaluates a number, a name, a unary operator and its operand, or an expression in parentheses
+    fn calc_term<'a>(
+        &self,
+        tokens: &'a [Token],
+        token: &Token,
+    ) -> Result<(i64, &'a [Token]), AssembleError> {
+        let first = tokens
+            .first()
+            .ok_or_else(|| self.error(token, format!("Expected a value after {}", token.text)))?;
+        let rest = &tokens[1..];
+        match first.text.as_str() {
+            "(" => {
+                let (value, rest) = self.calc(rest, first)?;
+                match rest.first() {
+                    Some(close) if close.text == ")" => Ok((value, &rest[1..])),
+                    _ => Err(self.error(first, "Unclosed parenthesis")),
+                }
+            }
+            "-" => self
+                .calc_term(rest, first)
+                .map(|(value, rest)| (value.wrapping_neg(), rest)),
+            "~" => self
+                .calc_term(rest, first)
+                .map(|(value, rest)| (!value, rest)),
+            "!" => self
+                .calc_term(rest, first)
+                .map(|(value, rest)| ((value == 0) as i64, rest)),
+            "HERE" => Ok((self.here as i64, rest)),
+            _ => match self.number(first) {
+                Some(value) => Ok((value, rest)),
+                None => Err(self.error(first, format!("Unknown value: {}", first.text))),
+            },
+        }
+    }
+
+    /// Fills in the addresses of labels that were used before being defined
+    fn resolve(&mut self) -> Result<(), AssembleError> {
+        for fixup in &self.fixups {
+            let addr = match self.labels.get(&fixup.label.text) {
+                Some(addr) => *addr,
+                None => {
+                    return Err(
+                        self.error(&fixup.label, format!("Unknown label: {}", fixup.label.text))
+                    )
+                }
+            };
+            let index = fixup.addr - PROGRAM_START as usize;
+            if fixup.long {
+                self.rom[index + 2..index + 4].copy_from_slice(&addr.to_be_bytes());
+            } else if addr > 0xFFF {
+                return Err(self.error(
+                    &fixup.label,
+                    format!("{} is out of reach, at {:#06X}", fixup.label.text, addr),
+                ));
+            } else {
+                self.rom[index] = (self.rom[index] & 0xF0) | (addr >> 8) as u8;
+                self.rom[index + 1] = addr as u8;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if a label or a constant cannot have this name, or if one already does
+    fn check_name(&self, name: &Token) -> Result<(), AssembleError> {
+        if !is_label(&name.text) || self.register(&name.text).is_some() {
+            return Err(self.error(name, format!("Invalid name: {}", name.text)));
+        }
+        if self.labels.contains_key(&name.text) || self.constants.contains_key(&name.text) {
+            return Err(self.error(name, format!("{} is already defined", name.text)));
+        }
+        Ok(())
+    }
+
+    /// Returns the register a word names, v0 to vf or an alias
+    fn register(&self, text: &str) -> Option<Register> {
+        if let Some(x) = self.aliases.get(text) {
+            return Some(*x);
+        }
+        let mut chars = text.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v'), Some(x), None) | (Some('V'), Some(x), None) => {
+                x.to_digit(16).map(|x| x as Register)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the value of a number, a constant or a label defined so far
+    fn number(&self, token: &Token) -> Option<i64> {
+        parse_number(&token.text)
+            .or_else(|| self.constants.get(&token.text).copied())
+            .or_else(|| self.labels.get(&token.text).map(|addr| *addr as i64))
+    }
+
+    /// Returns the value of a token that must fit in a byte. Negative values are stored in two's complement
+    fn byte_of(&self, token: &Token) -> Result<u8, AssembleError> {
+        match self.number(token) {
+            Some(value) if (-0x80..=0xFF).contains(&value) => Ok(value as u8),
+            Some(value) => Err(self.error(token, format!("{} does not fit in a byte", value))),
+            None => Err(self.error(token, format!("Unknown value: {}", token.text))),
+        }
+    }
+
+    /// Reads a value and checks that it lies between min and max
+    fn value(&mut self, token: &Token, min: i64, max: i64) -> Result<i64, AssembleError> {
+        let next = self.next(token)?;
+        match self.number(&next) {
+            Some(value) if (min..=max).contains(&value) => Ok(value),
+            Some(value) => Err(self.error(
+                &next,
+                format!(
+                    "{} does not fit: expected a value from {} to {:#X}",
+                    value, min, max
+                ),
+            )),
+            None => Err(self.error(&next, format!("Unknown value: {}", next.text))),
+        }
+    }
+
+    /// Reads a 12-bit address, which may be a label defined further down
+    fn address(&mut self, token: &Token) -> Result<Address, AssembleError> {
+        let next = self.next(token)?;
+        match self.number(&next) {
+            Some(value) if (0..=0xFFF).contains(&value) => Ok(value as Address),
+            Some(value) => Err(self.error(&next, format!("{:#X} is out of reach", value))),
+            None => self.label_address(&next),
+        }
+    }
+
+    /// Returns the address of a label. If it is not defined yet, returns 0 and fills it in at the end
+    fn label_address(&mut self, label: &Token) -> Result<Address, AssembleError> {
+        if let Some(addr) = self.labels.get(&label.text) {
+            return match *addr {
+                addr if addr <= 0xFFF => Ok(addr),
+                addr => Err(self.error(
+                    label,
+                    format!("{} is out of reach, at {:#06X}", label.text, addr),
+                )),
+            };
+        }
+        if !is_label(&label.text) {
+            return Err(self.error(label, format!("Expected an address: {}", label.text)));
+        }
+        self.fixups.push(Fixup {
+            addr: self.here,
+            label: label.clone(),
+            long: false,
+        });
+        Ok(0)
+    }
+
+    /// Returns the address of the next byte, as the target of a jump
+    fn target(&self, token: &Token) -> Result<Address, AssembleError> {
+        if self.here > 0xFFF {
+            return Err(self.error(token, format!("{:#06X} is out of reach", self.here)));
+        }
+        Ok(self.here as Address)
+    }
+
+    /// Reads a register
+    fn vx(&mut self, token: &Token) -> Result<Register, AssembleError> {
+        let next = self.next(token)?;
+        self.register(&next.text)
+            .ok_or_else(|| self.error(&next, format!("Expected a register: {}", next.text)))
+    }
+
+    /// Reads the `- vy` that ends a range of registers, if there is one
+    fn range_end(&mut self, token: &Token) -> Result<Option<Register>, AssembleError> {
+        match self.tokens.last() {
+            Some(next) if next.text == "-" => {
+                self.tokens.pop();
+                Ok(Some(self.vx(token)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the next token. Fails at the end of the source
+    fn next(&mut self, token: &Token) -> Result<Token, AssembleError> {
+        self.tokens.pop().ok_or_else(|| {
+            self.error(
+                token,
+                format!("Unexpected end of file after {}", token.text),
+            )
+        })
+    }
+
+    /// Reads the given word
+    fn expect(&mut self, token: &Token, text: &str) -> Result<(), AssembleError> {
+        let next = self.next(token)?;
+        if next.text != text {
+            return Err(self.error(&next, format!("Expected {}: {}", text, next.text)));
+        }
+        Ok(())
+    }
+
+    /// Writes an instruction at the next address
+    fn emit_inst(&mut self, inst: Instructions, token: &Token) -> Result<(), AssembleError> {
+        self.emit(&inst.encode(), token)
+    }
+
+    /// Writes bytes at the next address
+    fn emit(&mut self, bytes: &[u8], token: &Token) -> Result<(), AssembleError> {
+        if self.here + bytes.len() > MEMORY_SIZE {
+            return Err(self.error(token, "The program does not fit in memory"));
+        }
+        let index = self.here - PROGRAM_START as usize;
+        if self.rom.len() < index + bytes.len() {
+            self.rom.resize(index + bytes.len(), 0);
+        }
+        self.rom[index..index + bytes.len()].copy_from_slice(bytes);
+        self.here += bytes.len();
+        Ok(())
+    }
+
+    /// Writes an instruction at an address that was already written, such as a jump whose target is now known
+    fn put(&mut self, addr: usize, inst: Instructions) {
+        let index = addr - PROGRAM_START as usize;
+        let bytes = inst.encode();
+        self.rom[index..index + bytes.len()].copy_from_slice(&bytes);
+    }
+
+    /// Returns an error at the given token
+    fn error<S: Into<String>>(&self, token: &Token, message: S) -> AssembleError {
+        AssembleError {
+            file: self.file.clone(),
+            line: token.line,
+            column: token.column,
+            message: message.into(),
+        }
+    }
+}
+
+/// Splits the source into words separated by whitespace. Comments start with `#` and run to the end of the line
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    for (index, line) in source.lines().enumerate() {
+        let mut start = None;
+        for (j, c) in line
+            .char_indices()
+            .chain(std::iter::once((line.len(), ' ')))
+        {
+            match (start, c.is_whitespace()) {
+                (None, false) if c == '#' => break,
+                (None, false) => start = Some(j),
+                (Some(begin), true) => {
+                    tokens.push(Token {
+                        text: line[begin..j].to_string(),
+                        line: index + 1,
+                        column: begin + 1,
+                    });
+                    start = None;
+                }
+                _ => (),
+            }
+        }
+    }
+    tokens
+}
+
+/// Returns whether a word can name a label or a constant
+fn is_label(text: &str) -> bool {
+    parse_number(text).is_none()
+        && text
+            .chars()
+            .next()
+            .map_or(false, |c| c.is_alphabetic() || c == '_')
+}
+
+/// Parses a number written in decimal, in hex (`0x1F`) or in binary (`0b1010`), with an optional minus sign
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compiles source code and returns the ROM
+    fn rom(source: &str) -> Vec<u8> {
+        compile(source, Path::new("test.8o")).unwrap().rom
+    }
+
+    /// Compiles source code that has an error and returns where it is and what it is
+    fn error(source: &str) -> (usize, usize, String) {
+        let err = compile(source, Path::new("test.8o")).unwrap_err();
+        (err.line, err.column, err.message)
+    }
+
+    #[test]
+    fn loops() {
+        let source = ": main
+            v0 := 0
+            loop
+                v0 += 1
+                while v0 != 10
+            again";
+        assert_eq!(
+            rom(source),
+            [0x12, 0x02, 0x60, 0x00, 0x70, 0x01, 0x40, 0x0A, 0x12, 0x0C, 0x12, 0x04]
+        );
+    }
+
+    #[test]
+    fn conditionals() {
+        let source = ": main
+            if v1 == v2 begin
+                v0 := 1
+            else
+                v0 := 2
+            end
+            if v3 key then clear";
+        assert_eq!(
+            rom(source),
+            [
+                0x12, 0x02, 0x51, 0x20, 0x12, 0x0A, 0x60, 0x01, 0x12, 0x0C, 0x60, 0x02, 0xE3, 0xA1,
+                0x00, 0xE0
+            ]
+        );
+    }
+
+    #[test]
+    fn macros_and_calc() {
+        // Evaluated from right to left: 2 * (0x10 + 1)
+        let source = ":calc double { 2 * 0x10 + 1 }
+            :macro set reg value { reg := value }
+            : main
+                set v5 double
+                set va 0x7
+                :byte { HERE - 0x200 }";
+        assert_eq!(rom(source), [0x12, 0x02, 0x65, 0x22, 0x6A, 0x07, 0x06]);
+    }
+
+    #[test]
+    fn forward_long_label() {
+        let source = ": main
+                i := long data
+                jump main
+            :org 0x1000
+            : data 0xAB";
+        let program = compile(source, Path::new("test.8o")).unwrap();
+        assert_eq!(
+            program.rom[..8],
+            [0x12, 0x02, 0xF0, 0x00, 0x10, 0x00, 0x12, 0x02]
+        );
+        assert_eq!(program.rom.len(), 0x1000 - PROGRAM_START as usize + 1);
+        assert_eq!(program.rom[0x1000 - PROGRAM_START as usize], 0xAB);
+        assert_eq!(
+            program.symbols,
+            [("main".to_string(), 0x202), ("data".to_string(), 0x1000)]
+        );
+    }
+
+    #[test]
+    fn main_is_checked() {
+        assert_eq!(
+            error("clear"),
+            (1, 1, "The program has no main label".to_string())
+        );
+        assert_eq!(
+            error(":org 0x1000\n  : main clear"),
+            (2, 5, "main at 0x1000 is out of reach".to_string())
+        );
+        // The jump to main is at PROGRAM_START
+        assert_eq!(
+            error(":org 0x200 0x12 0x34\n: main"),
+            (
+                1,
+                6,
+                "512 does not fit: expected a value from 514 to 0xFFFF".to_string()
+            )
+        );
+    }
+}
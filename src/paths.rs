@@ -0,0 +1,30 @@
+use std::env;
+use std::path::PathBuf;
+
+/// Returns the directory where the emulator keeps user data, following the XDG base directory specification
+/// (`$XDG_DATA_HOME/chip8-rs` or `~/.local/share/chip8-rs`). On Windows, `%APPDATA%\chip8-rs` is used
+pub fn data_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".local").join("share"),
+            None => PathBuf::from(env::var_os("APPDATA")?),
+        },
+    };
+
+    Some(base.join("chip8-rs"))
+}
+
+/// Returns the directory where the emulator looks for configuration files, following the XDG base directory specification
+/// (`$XDG_CONFIG_HOME/chip8-rs` or `~/.config/chip8-rs`). On Windows, `%APPDATA%\chip8-rs` is used
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".config"),
+            None => PathBuf::from(env::var_os("APPDATA")?),
+        },
+    };
+
+    Some(base.join("chip8-rs"))
+}
@@ -0,0 +1,202 @@
+/// Names of the available quirks profiles
+pub const PROFILES: [&str; 4] = ["cosmac-vip", "chip-48", "super-chip", "modern"];
+/// Names of the individual quirk flags, as accepted by `Quirks::set()`
+pub const FLAGS: [&str; 8] = [
+    "shift-uses-vy",
+    "load-store-increments-i",
+    "load-store-increments-i-by-x",
+    "jump-uses-vx",
+    "vf-reset",
+    "clip-sprites",
+    "display-wait",
+    "wait-key-release",
+];
+
+/// Behaviors that differ between CHIP-8 interpreters.
+/// Programs written for one interpreter may rely on them, so they must match for the program to run properly
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    /// 8xy6 and 8xyE shift vy and store the result in vx, instead of shifting vx in place
+    pub shift_uses_vy: bool,
+    /// Fx55 and Fx65 leave i pointing right after the last register copied (i is incremented by x + 1)
+    pub load_store_increments_i: bool,
+    /// Fx55 and Fx65 leave i pointing at the last register copied (i is incremented by x). Has no effect with
+    /// load_store_increments_i
+    pub load_store_increments_i_by_x: bool,
+    /// Bnnn jumps to (nnn + vx), where x is the most significant digit of nnn, instead of (nnn + v0)
+    pub jump_uses_vx: bool,
+    /// 8xy1, 8xy2 and 8xy3 set vf to 0
+    pub vf_reset: bool,
+    /// Sprites drawn past the edges of the screen are clipped instead of wrapping around
+    pub clip_sprites: bool,
+    /// Dxyn waits for the next vertical blank, which limits sprite draws to 60 per second
+    pub display_wait: bool,
+    /// Fx0A resumes when the key is released, instead of as soon as it is pressed
+    pub wait_key_release: bool,
+}
+
+impl Quirks {
+    /// Behavior of the original interpreter for the COSMAC VIP
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: false,
+            vf_reset: true,
+            clip_sprites: true,
+            display_wait: true,
+            wait_key_release: true,
+        }
+    }
+
+    /// Behavior of CHIP-48 for the HP-48 calculators. Unlike SUPER-CHIP 1.1, Fx55 and Fx65 still move i, by one
+    /// less than the COSMAC VIP did
+    pub fn chip_48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            load_store_increments_i_by_x: true,
+            jump_uses_vx: true,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+            wait_key_release: false,
+        }
+    }
+
+    /// Behavior of SUPER-CHIP 1.1 for the HP-48 calculators
+    pub fn super_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: true,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+            wait_key_release: false,
+        }
+    }
+
+    /// Behavior of most modern interpreters, such as Octo. No quirk is enabled
+    pub fn modern() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: false,
+            vf_reset: false,
+            clip_sprites: false,
+            display_wait: false,
+            wait_key_release: true,
+        }
+    }
+
+    /// Returns the quirks of the profile with the given name, if there is one. See PROFILES
+    pub fn profile(name: &str) -> Option<Quirks> {
+        match name {
+            "cosmac-vip" => Some(Quirks::cosmac_vip()),
+            "chip-48" => Some(Quirks::chip_48()),
+            "super-chip" => Some(Quirks::super_chip()),
+            "modern" => Some(Quirks::modern()),
+            _ => None,
+        }
+    }
+
+    /// Sets the quirk flag with the given name. See FLAGS
+    pub fn set(&mut self, flag: &str, value: bool) -> Result<(), String> {
+        match flag {
+            "shift-uses-vy" => self.shift_uses_vy = value,
+            "load-store-increments-i" => self.load_store_increments_i = value,
+            "load-store-increments-i-by-x" => self.load_store_increments_i_by_x = value,
+            "jump-uses-vx" => self.jump_uses_vx = value,
+            "vf-reset" => self.vf_reset = value,
+            "clip-sprites" => self.clip_sprites = value,
+            "display-wait" => self.display_wait = value,
+            "wait-key-release" => self.wait_key_release = value,
+            _ => return Err(format!("Unknown quirk: {}", flag)),
+        }
+        Ok(())
+    }
+
+    /// Packs the quirks into a byte, one bit per flag in the order of FLAGS
+    pub fn to_bits(&self) -> u8 {
+        (self.shift_uses_vy as u8)
+            | (self.load_store_increments_i as u8) << 1
+            | (self.load_store_increments_i_by_x as u8) << 2
+            | (self.jump_uses_vx as u8) << 3
+            | (self.vf_reset as u8) << 4
+            | (self.clip_sprites as u8) << 5
+            | (self.display_wait as u8) << 6
+            | (self.wait_key_release as u8) << 7
+    }
+
+    /// Unpacks quirks packed by `to_bits()`
+    pub fn from_bits(bits: u8) -> Quirks {
+        Quirks {
+            shift_uses_vy: bits & 1 != 0,
+            load_store_increments_i: bits & 1 << 1 != 0,
+            load_store_increments_i_by_x: bits & 1 << 2 != 0,
+            jump_uses_vx: bits & 1 << 3 != 0,
+            vf_reset: bits & 1 << 4 != 0,
+            clip_sprites: bits & 1 << 5 != 0,
+            display_wait: bits & 1 << 6 != 0,
+            wait_key_release: bits & 1 << 7 != 0,
+        }
+    }
+
+    /// Parses an override in the form `flag=on` or `flag=off` and applies it
+    pub fn apply_override(&mut self, setting: &str) -> Result<(), String> {
+        let mut parts = setting.splitn(2, '=');
+        let flag = parts.next().unwrap_or("");
+        let value = match parts.next() {
+            Some("on") | Some("true") | Some("1") => true,
+            Some("off") | Some("false") | Some("0") => false,
+            _ => return Err(format!("Invalid quirk override: {}", setting)),
+        };
+
+        self.set(flag, value)
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::modern()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+
+    /// Returns i after running `i := 0x300`, `v3 := 0x42` and `save v3` with the quirks of the given profile
+    fn i_after_save(profile: &str) -> u16 {
+        let mut chip8 = Chip8::new();
+        chip8.set_quirks(Quirks::profile(profile).unwrap());
+        chip8
+            .load_rom(&[0xA3, 0x00, 0x63, 0x42, 0xF3, 0x55])
+            .unwrap();
+        for _ in 0..3 {
+            chip8.run_next_instruction();
+        }
+        chip8.get_i()
+    }
+
+    #[test]
+    fn load_store_moves_i_per_profile() {
+        assert_eq!(i_after_save("cosmac-vip"), 0x304);
+        assert_eq!(i_after_save("chip-48"), 0x303);
+        assert_eq!(i_after_save("super-chip"), 0x300);
+        assert_eq!(i_after_save("modern"), 0x300);
+    }
+
+    #[test]
+    fn bits_round_trip() {
+        for profile in PROFILES.iter() {
+            let quirks = Quirks::profile(profile).unwrap();
+            assert_eq!(Quirks::from_bits(quirks.to_bits()), quirks);
+        }
+    }
+}
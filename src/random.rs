@@ -0,0 +1,196 @@
+use crate::memory::Memory;
+use crate::state::{StateReader, StateWriter};
+
+/// Names of the available random number generators, as accepted by `RandomMode::from_name()`
+pub const RANDOM_MODES: [&str; 2] = ["xorshift", "cosmac-vip"];
+
+/// Algorithm used to generate the random numbers of Cxkk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RandomMode {
+    /// A 64-bit xorshift generator. Fast and with good statistical quality
+    Xorshift,
+    /// A weak generator in the spirit of the COSMAC VIP interpreter, which mixed bytes of its own code into its
+    /// result. It is an approximation and does not reproduce the numbers of the original routine
+    CosmacVip,
+}
+
+impl RandomMode {
+    /// Returns the generator with the given name, if there is one. See RANDOM_MODES
+    pub fn from_name(name: &str) -> Option<RandomMode> {
+        match name {
+            "xorshift" => Some(RandomMode::Xorshift),
+            "cosmac-vip" => Some(RandomMode::CosmacVip),
+            _ => None,
+        }
+    }
+}
+
+/// Seedable random number generator. It is part of the machine state, so a run with the same seed
+/// and the same input always plays out the same way
+pub struct Random {
+    /// Algorithm in use
+    mode: RandomMode,
+    /// State of the xorshift generator. Never 0
+    state: u64,
+    /// Counter of the COSMAC VIP style generator. Picks the memory byte added to the result
+    counter: u8,
+    /// Previous result of the COSMAC VIP style generator
+    last: u8,
+}
+
+impl Random {
+    /// Creates a new xorshift generator with the given seed
+    pub fn new(seed: u64) -> Random {
+        let mut random = Random {
+            mode: RandomMode::Xorshift,
+            state: 0,
+            counter: 0,
+            last: 0,
+        };
+        random.seed(seed);
+        random
+    }
+
+    /// Restarts the generator from the given seed
+    pub fn seed(&mut self, seed: u64) {
+        // Runs the seed through SplitMix64, so that close seeds give unrelated sequences and 0 is a valid seed
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        self.state = if z == 0 { 1 } else { z };
+        self.counter = z as u8;
+        self.last = (z >> 8) as u8;
+    }
+
+    /// Returns the algorithm in use
+    pub fn get_mode(&self) -> RandomMode {
+        self.mode
+    }
+
+    /// Sets the algorithm in use
+    pub fn set_mode(&mut self, mode: RandomMode) {
+        self.mode = mode;
+    }
+
+    /// Returns the next random byte. The COSMAC VIP style generator reads from the low memory of the machine, where
+    /// the original kept its interpreter code. The read does not trigger the watchpoints
+    pub fn next_byte(&mut self, memory: &Memory) -> u8 {
+        match self.mode {
+            RandomMode::Xorshift => {
+                self.state ^= self.state << 13;
+                self.state ^= self.state >> 7;
+                self.state ^= self.state << 17;
+                (self.state >> 56) as u8
+            }
+            RandomMode::CosmacVip => {
+                self.counter = self.counter.wrapping_add(1);
+                let byte = memory.peek_byte(self.counter as u16);
+                self.last = self.last.wrapping_add(byte).rotate_right(1) ^ self.counter;
+                self.last
+            }
+        }
+    }
+
+    /// Writes the generator to a save state
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.mode as u8);
+        state.write_u64(self.state);
+        state.write_u8(self.counter);
+        state.write_u8(self.last);
+    }
+
+    /// Restores the generator from a save state
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.mode = match state.read_u8()? {
+            0 => RandomMode::Xorshift,
+            1 => RandomMode::CosmacVip,
+            mode => return Err(format!("Unknown random number generator {}", mode)),
+        };
+        self.state = state.read_u64()?;
+        if self.state == 0 {
+            return Err("Invalid random number generator state".to_string());
+        }
+        self.counter = state.read_u8()?;
+        self.last = state.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Default for Random {
+    fn default() -> Random {
+        Random::new(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::debugger::{Access, Watchpoint};
+
+    /// v0 := random 0xFF, forever
+    const RANDOM_ROM: [u8; 4] = [0xC0, 0xFF, 0x12, 0x00];
+
+    /// Returns the next numbers the machine draws
+    fn draws(chip8: &mut Chip8, count: usize) -> Vec<u8> {
+        (0..count)
+            .map(|_| {
+                chip8.run_frame(2);
+                chip8.get_vx(0)
+            })
+            .collect()
+    }
+
+    /// Returns a machine running RANDOM_ROM with the given generator and seed
+    fn seeded(mode: RandomMode, seed: u64) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.set_random_mode(mode);
+        chip8.set_seed(seed);
+        chip8.load_rom(&RANDOM_ROM).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn seed_makes_runs_reproducible() {
+        for mode in [RandomMode::Xorshift, RandomMode::CosmacVip].iter() {
+            let numbers = draws(&mut seeded(*mode, 42), 32);
+            assert_eq!(draws(&mut seeded(*mode, 42), 32), numbers);
+            assert_ne!(draws(&mut seeded(*mode, 43), 32), numbers);
+        }
+    }
+
+    #[test]
+    fn save_states_carry_the_generator() {
+        for mode in [RandomMode::Xorshift, RandomMode::CosmacVip].iter() {
+            let mut chip8 = seeded(*mode, 7);
+            draws(&mut chip8, 10);
+            let state = chip8.save_state();
+            let numbers = draws(&mut chip8, 32);
+
+            // Loaded into a machine with another generator and another seed
+            let mut restored = seeded(RandomMode::Xorshift, 1);
+            restored.load_state(&state).unwrap();
+            assert_eq!(restored.get_random_mode(), *mode);
+            assert_eq!(draws(&mut restored, 32), numbers);
+        }
+    }
+
+    #[test]
+    fn cosmac_vip_does_not_trigger_watchpoints() {
+        let mut chip8 = Chip8::new();
+        chip8.set_random_mode(RandomMode::CosmacVip);
+        // v0 := random 0xFF, twice
+        chip8.load_rom(&[0xC0, 0xFF, 0xC0, 0xFF]).unwrap();
+        chip8.add_watchpoint(Watchpoint {
+            start: 0x000,
+            end: 0x1FF,
+            access: Access::Read,
+        });
+
+        chip8.run_frame(2);
+        assert_eq!(chip8.get_break(), None);
+        assert_eq!(chip8.get_pc(), 0x204);
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::VecDeque;
+
+/// Rate at which snapshots are taken (one per frame)
+pub const SNAPSHOTS_PER_SECOND: usize = 60;
+
+/// Tag of a delta made of XOR runs against the newer snapshot
+const DELTA_XOR: u8 = 0;
+/// Tag of a delta holding the whole older snapshot, used when the sizes differ
+const DELTA_RAW: u8 = 1;
+
+/// Ring buffer of per-frame machine snapshots, used to step backwards in time.
+/// Only the newest snapshot is kept whole. Every older one is stored as a delta against the one that followed it,
+/// which is tiny since little changes from one frame to the next
+pub struct Rewind {
+    /// Newest snapshot
+    latest: Option<Vec<u8>>,
+    /// Deltas that turn a snapshot into the one taken before it. The newest delta is at the back
+    deltas: VecDeque<Vec<u8>>,
+    /// Maximum amount of deltas kept. The oldest ones are dropped
+    capacity: usize,
+}
+
+impl Rewind {
+    /// Creates a buffer able to go back the given amount of seconds
+    pub fn new(seconds: usize) -> Rewind {
+        let capacity = seconds * SNAPSHOTS_PER_SECOND;
+        Rewind {
+            latest: None,
+            deltas: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Stores a new snapshot
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(latest) = self.latest.take() {
+            if self.deltas.len() == self.capacity {
+                self.deltas.pop_front();
+            }
+            self.deltas.push_back(encode_delta(&latest, &snapshot));
+        }
+        self.latest = Some(snapshot);
+    }
+
+    /// Steps one snapshot back in time and returns it. Returns None once the oldest one is reached
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let delta = self.deltas.pop_back()?;
+        let latest = self.latest.as_ref()?;
+        let previous = decode_delta(latest, &delta)?;
+        self.latest = Some(previous.clone());
+        Some(previous)
+    }
+
+    /// Returns the amount of snapshots that can be stepped back
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    /// Returns true if there is nothing to step back to
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Drops every snapshot, such as after loading a save state
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+}
+
+/// Encodes the delta that turns `newer` back into `older`.
+/// The XOR of both is split into runs of unchanged bytes, which are skipped, and runs of changed bytes, which are kept
+fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    if older.len() != newer.len() {
+        let mut delta = vec![DELTA_RAW];
+        delta.extend_from_slice(older);
+        return delta;
+    }
+
+    let mut delta = vec![DELTA_XOR];
+    let mut i = 0;
+    while i < older.len() {
+        // Unchanged bytes
+        let skip_start = i;
+        while i < older.len() && older[i] == newer[i] {
+            i += 1;
+        }
+        // Changed bytes
+        let changed_start = i;
+        while i < older.len() && older[i] != newer[i] {
+            i += 1;
+        }
+
+        write_varint(&mut delta, i - changed_start);
+        write_varint(&mut delta, changed_start - skip_start);
+        for j in changed_start..i {
+            delta.push(older[j] ^ newer[j]);
+        }
+    }
+
+    delta
+}
+
+/// Applies a delta made by `encode_delta()` to `newer`, returning the older snapshot.
+/// Returns None if the delta is truncated or does not fit `newer`
+fn decode_delta(newer: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    match *delta.first()? {
+        DELTA_RAW => return Some(delta[1..].to_vec()),
+        DELTA_XOR => (),
+        _ => return None,
+    }
+
+    let mut older = newer.to_vec();
+    let mut pos = 1;
+    let mut i: usize = 0;
+    while pos < delta.len() {
+        let changed = read_varint(delta, &mut pos)?;
+        i = i.checked_add(read_varint(delta, &mut pos)?)?;
+        let end = i.checked_add(changed)?;
+        let xor = delta.get(pos..pos.checked_add(changed)?)?;
+        for (byte, mask) in older.get_mut(i..end)?.iter_mut().zip(xor) {
+            *byte ^= mask;
+        }
+        pos += changed;
+        i = end;
+    }
+
+    Some(older)
+}
+
+/// Writes a LEB128 variable length integer
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Reads a LEB128 variable length integer, advancing pos. Returns None if it is truncated or does not fit a usize
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut value: usize = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        let bits = (byte & 0x7F) as usize;
+        if shift >= usize::BITS || (bits << shift) >> shift != bits {
+            return None;
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varint_round_trips() {
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, usize::MAX] {
+            let mut data = vec![];
+            write_varint(&mut data, value);
+            let mut pos = 0;
+            assert_eq!(read_varint(&data, &mut pos), Some(value));
+            assert_eq!(pos, data.len());
+
+            // Truncated
+            let mut pos = 0;
+            assert_eq!(read_varint(&data[..data.len() - 1], &mut pos), None);
+        }
+        // Longer than a usize
+        let mut pos = 0;
+        assert_eq!(read_varint(&[0xFF; 11], &mut pos), None);
+        let mut pos = 0;
+        assert_eq!(
+            read_varint(
+                &[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7F],
+                &mut pos
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn delta_round_trips() {
+        let older: Vec<u8> = (0..200).collect();
+        let mut newer = older.clone();
+        newer[0] = 0xFF;
+        newer[100..150].iter_mut().for_each(|byte| *byte = 0);
+        newer[199] ^= 1;
+
+        let delta = encode_delta(&older, &newer);
+        assert_eq!(delta[0], DELTA_XOR);
+        assert!(delta.len() < 80);
+        assert_eq!(decode_delta(&newer, &delta), Some(older.clone()));
+
+        // Unchanged snapshots only store the length of the run
+        assert_eq!(
+            decode_delta(&older, &encode_delta(&older, &older)),
+            Some(older.clone())
+        );
+
+        // Snapshots of different sizes are stored whole
+        let delta = encode_delta(&older, &newer[..10]);
+        assert_eq!(delta[0], DELTA_RAW);
+        assert_eq!(decode_delta(&newer[..10], &delta), Some(older));
+    }
+
+    #[test]
+    fn broken_deltas_are_rejected() {
+        let older = vec![1, 2, 3, 4];
+        let newer = vec![1, 5, 6, 4];
+        let delta = encode_delta(&older, &newer);
+        assert_eq!(delta, [DELTA_XOR, 2, 1, 7, 5, 0, 1]);
+        // Cut in the middle of the changed run
+        for len in 2..5 {
+            assert_eq!(
+                decode_delta(&newer, &delta[..len]),
+                None,
+                "{:?}",
+                &delta[..len]
+            );
+        }
+        assert_eq!(decode_delta(&newer, &[]), None);
+        assert_eq!(decode_delta(&newer, &[2]), None);
+        // Changes past the end of the snapshot
+        assert_eq!(decode_delta(&newer, &[DELTA_XOR, 1, 4, 0xFF]), None);
+    }
+
+    #[test]
+    fn steps_back_through_snapshots() {
+        let mut rewind = Rewind::new(1);
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.pop(), None);
+
+        for frame in 0..5u8 {
+            rewind.push(vec![frame, 0, frame * 2]);
+        }
+        assert_eq!(rewind.len(), 4);
+        assert_eq!(rewind.pop(), Some(vec![3, 0, 6]));
+        assert_eq!(rewind.pop(), Some(vec![2, 0, 4]));
+
+        // New snapshots continue from where it stepped back to
+        rewind.push(vec![9, 9, 9]);
+        assert_eq!(rewind.pop(), Some(vec![2, 0, 4]));
+        assert_eq!(rewind.pop(), Some(vec![1, 0, 2]));
+        assert_eq!(rewind.pop(), Some(vec![0, 0, 0]));
+        assert_eq!(rewind.pop(), None);
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn drops_the_oldest_snapshots() {
+        let mut rewind = Rewind::new(1);
+        for frame in 0..SNAPSHOTS_PER_SECOND + 10 {
+            rewind.push(frame.to_be_bytes().to_vec());
+        }
+        assert_eq!(rewind.len(), SNAPSHOTS_PER_SECOND);
+        let mut oldest = None;
+        while let Some(snapshot) = rewind.pop() {
+            oldest = Some(snapshot);
+        }
+        // Only the snapshots a second before the newest one are left
+        assert_eq!(oldest, Some(9usize.to_be_bytes().to_vec()));
+
+        // Without capacity, nothing is kept
+        let mut rewind = Rewind::new(0);
+        rewind.push(vec![1]);
+        rewind.push(vec![2]);
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn clear_drops_everything() {
+        let mut rewind = Rewind::new(1);
+        rewind.push(vec![1]);
+        rewind.push(vec![2]);
+        rewind.clear();
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.pop(), None);
+
+        // The next snapshot is not a delta against the dropped ones
+        rewind.push(vec![3]);
+        assert_eq!(rewind.pop(), None);
+        rewind.push(vec![4]);
+        assert_eq!(rewind.pop(), Some(vec![3]));
+    }
+}
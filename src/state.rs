@@ -0,0 +1,181 @@
+use crate::paths::data_dir;
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// First bytes of every save state
+pub const STATE_MAGIC: &[u8; 4] = b"C8ST";
+/// Version of the save state format. Bumped whenever the layout changes
+pub const STATE_VERSION: u16 = 3;
+/// Amount of numbered save state slots
+pub const SLOT_COUNT: u8 = 9;
+
+/// Serializes the machine state into the save state binary format. All values are little endian
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Creates a new writer. The magic bytes and the version are written right away
+    pub fn new() -> StateWriter {
+        let mut writer = StateWriter { data: vec![] };
+        writer.write_bytes(STATE_MAGIC);
+        writer.write_u16(STATE_VERSION);
+        writer
+    }
+
+    /// Writes a single byte
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    /// Writes a boolean as a single byte
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    /// Writes a 16-bit value
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes a 32-bit value
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes a 64-bit value
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes raw bytes. The reader must know how many to expect
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Writes bytes preceded by their length, for data whose size varies
+    pub fn write_sized_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.write_bytes(bytes);
+    }
+
+    /// Returns the serialized state
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> StateWriter {
+        StateWriter::new()
+    }
+}
+
+/// Reads back a save state produced by `StateWriter`
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    /// Position of the next byte to be read
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    /// Creates a new reader. The magic bytes and the version are checked right away
+    pub fn new(data: &'a [u8]) -> Result<StateReader<'a>, String> {
+        let mut reader = StateReader { data, pos: 0 };
+
+        if reader.read_bytes(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err("Not a save state".to_string());
+        }
+        let version = reader.read_u16()?;
+        if version != STATE_VERSION {
+            return Err(format!(
+                "Unsupported save state version {} (expected {})",
+                version, STATE_VERSION
+            ));
+        }
+
+        Ok(reader)
+    }
+
+    /// Reads a single byte
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    /// Reads a boolean written as a single byte
+    pub fn read_bool(&mut self) -> Result<bool, String> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    /// Reads a 16-bit value
+    pub fn read_u16(&mut self) -> Result<u16, String> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Reads a 32-bit value
+    pub fn read_u32(&mut self) -> Result<u32, String> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.read_bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /// Reads a 64-bit value
+    pub fn read_u64(&mut self) -> Result<u64, String> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Reads the given amount of raw bytes
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.pos + len > self.data.len() {
+            return Err("Save state is truncated".to_string());
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Reads bytes preceded by their length
+    pub fn read_sized_bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.read_u32()? as usize;
+        self.read_bytes(len)
+    }
+}
+
+/// Numbered save state slots of a ROM, kept as files under the user data directory
+pub struct StateSlots {
+    /// Directory where the slots are kept
+    dir: PathBuf,
+    /// SHA-1 of the ROM. Slot files are named after it
+    rom_hash: String,
+}
+
+impl StateSlots {
+    /// Creates the slots for the ROM with the given hash. Returns None if there is no user data directory
+    pub fn for_rom(rom_hash: &str) -> Option<StateSlots> {
+        Some(StateSlots {
+            dir: data_dir()?.join("states"),
+            rom_hash: rom_hash.to_string(),
+        })
+    }
+
+    /// Returns the path of the file for the given slot
+    pub fn get_path(&self, slot: u8) -> PathBuf {
+        self.dir.join(format!("{}.{}.state", self.rom_hash, slot))
+    }
+
+    /// Writes a save state to the given slot
+    pub fn save(&self, slot: u8, state: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.get_path(slot), state)
+    }
+
+    /// Reads the save state in the given slot
+    pub fn load(&self, slot: u8) -> io::Result<Vec<u8>> {
+        fs::read(self.get_path(slot))
+    }
+}
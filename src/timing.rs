@@ -0,0 +1,121 @@
+use crate::instructions::Instructions;
+
+/// Machine cycles the COSMAC VIP runs per 60 Hz frame. Its 1802 CPU is clocked at 1.76 MHz
+/// and a machine cycle takes 8 clock cycles
+pub const VIP_CYCLES_PER_FRAME: i32 = 3668;
+/// Machine cycles of each frame taken by the display interrupt: the 1861 video chip steals cycles to fetch
+/// the 256 bytes of the screen, and the interrupt routine ticks the timers
+pub const VIP_INTERRUPT_CYCLES: i32 = 1024 + 30;
+/// Machine cycles spent by the interpreter to fetch and decode any instruction, before running it
+pub const VIP_FETCH_CYCLES: u32 = 40;
+
+/// Returns the machine cycles the COSMAC VIP interpreter takes to run an instruction, fetch included.
+/// `skipped` tells if a skip instruction skipped, and `x` is the horizontal position of a sprite being drawn.
+/// Instructions that did not exist on the VIP cost the same as a jump
+pub fn vip_cycles(inst: &Instructions, skipped: bool, x: u8) -> u32 {
+    let skip = if skipped { 4 } else { 0 };
+
+    let cost = match *inst {
+        Instructions::ClearDisplay => 3078,
+        Instructions::Return => 10,
+        Instructions::Jump(_) => 12,
+        Instructions::Call(_) => 26,
+        Instructions::SkipIfEqualsByte(..) | Instructions::SkipIfNotEqualsByte(..) => 10 + skip,
+        Instructions::SkipIfEquals(..) | Instructions::SkipIfNotEquals(..) => 14 + skip,
+        Instructions::SetRegisterByte(..) => 6,
+        Instructions::AddByte(..) => 10,
+        Instructions::SetRegister(..)
+        | Instructions::Or(..)
+        | Instructions::And(..)
+        | Instructions::Xor(..)
+        | Instructions::Add(..)
+        | Instructions::Sub(..)
+        | Instructions::ReverseSub(..)
+        | Instructions::ShiftRight(..)
+        | Instructions::ShiftLeft(..) => 44,
+        Instructions::SetI(_) => 12,
+        Instructions::JumpPlusV0(_) => 22,
+        Instructions::SetRandAnd(..) => 36,
+        Instructions::Draw(_, _, height) => draw_cycles(height, x),
+        Instructions::SkipIfKeyPressed(_) | Instructions::SkipIfKeyNotPressed(_) => 14 + skip,
+        Instructions::SetToDelayTimer(_) => 10,
+        Instructions::WaitKeyPress(_) => 18,
+        Instructions::SetDelayTimer(_) | Instructions::SetSoundTimer(_) => 10,
+        Instructions::AddRegisterI(_) => 16,
+        Instructions::SetSpriteI(_) => 16,
+        Instructions::BCDRepresentation(_) => 84,
+        Instructions::CopyRegistersMemory(x) | Instructions::SetRegistersMemory(x) => {
+            14 + 14 * (x as u32 + 1)
+        }
+        _ => 12,
+    };
+
+    VIP_FETCH_CYCLES + cost
+}
+
+/// Returns the machine cycles taken by Dxyn. Every row of the sprite is shifted into place one bit at a time,
+/// so sprites that are not aligned to a byte boundary are slower, and they touch two bytes of the screen per row
+fn draw_cycles(height: u8, x: u8) -> u32 {
+    let shift = (x % 8) as u32;
+    let bytes_per_row = if shift == 0 { 1 } else { 2 };
+
+    26 + height as u32 * (34 + shift * 4 + bytes_per_row * 12)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::{Chip8, PROGRAM_START};
+    use crate::instructions::Instructions::*;
+
+    #[test]
+    fn instruction_costs() {
+        assert_eq!(vip_cycles(&ClearDisplay, false, 0), 3118);
+        assert_eq!(vip_cycles(&Jump(0x200), false, 0), 52);
+        assert_eq!(vip_cycles(&SkipIfEqualsByte(0, 0), false, 0), 50);
+        assert_eq!(vip_cycles(&SkipIfEqualsByte(0, 0), true, 0), 54);
+        assert_eq!(
+            vip_cycles(&CopyRegistersMemory(0xF), false, 0),
+            40 + 14 + 14 * 16
+        );
+        // Sprites off a byte boundary are shifted into place and touch two bytes per row
+        assert_eq!(vip_cycles(&Draw(0, 1, 5), false, 8), 40 + 26 + 5 * 46);
+        assert_eq!(
+            vip_cycles(&Draw(0, 1, 5), false, 11),
+            40 + 26 + 5 * (34 + 12 + 24)
+        );
+        // Instructions the VIP did not have cost as much as a jump
+        assert_eq!(vip_cycles(&HighRes, false, 0), 52);
+    }
+
+    #[test]
+    fn frames_have_a_cycle_budget() {
+        let mut chip8 = Chip8::new();
+        // v0 += 1, forever. Each loop takes 50 + 52 cycles
+        chip8.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+
+        // 25 loops take 2550 of the 2614 cycles left by the interrupt. The 26th overruns the frame
+        chip8.run_vip_frame();
+        assert_eq!(chip8.get_vx(0), 26);
+        // The 38 cycles overrun are taken from the next frame
+        chip8.run_vip_frame();
+        assert_eq!(chip8.get_vx(0), 52);
+        assert_eq!(chip8.get_pc(), PROGRAM_START + 2);
+    }
+
+    #[test]
+    fn skips_cost_more() {
+        let mut chip8 = Chip8::new();
+        // Skips over 0000, which is never run, again and again. Each skip takes 54 cycles
+        let rom: Vec<u8> = [0x30, 0x00, 0x00, 0x00]
+            .iter()
+            .copied()
+            .cycle()
+            .take(4 * 100)
+            .collect();
+        chip8.load_rom(&rom).unwrap();
+
+        chip8.run_vip_frame();
+        assert_eq!(chip8.get_pc(), PROGRAM_START + 4 * 49);
+    }
+}
@@ -0,0 +1,268 @@
+#[cfg(feature = "minifb")]
+extern crate minifb;
+
+#[cfg(feature = "minifb")]
+use crate::keymap::Keymap;
+#[cfg(feature = "minifb")]
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+
+// Defaults of the settings below. See `config` to change them
+//
+/// Scale for the window size of the emulator. Since 64x32 is too tiny of a window for today's screens, a scale is necessary
+/// Enlarges the window by a factor of WINDOW_SCALE
+pub const WINDOW_SCALE: usize = 8;
+/// Color of the pixel. White by default
+pub const PIXEL_COLOR: u32 = 0x00FF_FFFF;
+/// Colors of the pixels, indexed by their value. Only XO-CHIP programs, which draw on two planes, use the last two
+pub const PALETTE: [u32; 4] = [0x0000_0000, PIXEL_COLOR, 0x00AA_AAAA, 0x0055_5555];
+
+/// Events reported by a video backend
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// A key of the keypad (0x0 to 0xF) went down
+    KeyDown(u8),
+    /// A key of the keypad (0x0 to 0xF) went up
+    KeyUp(u8),
+    /// The user asked to save the machine state to the given slot (1 to SLOT_COUNT)
+    SaveState(u8),
+    /// The user asked to load the machine state from the given slot (1 to SLOT_COUNT)
+    LoadState(u8),
+    /// The user is holding the rewind key. Reported every frame while it is held
+    Rewind,
+    /// The user asked to run more instructions per frame
+    SpeedUp,
+    /// The user asked to run fewer instructions per frame
+    SlowDown,
+    /// The user asked to halt the program and open the debugger
+    Break,
+}
+
+/// Anything able to show the CHIP-8 screen to the user and collect their input
+pub trait VideoBackend {
+    /// Presents a frame. `coord` holds one value per pixel, row by row, with `width * height` values.
+    /// Values range from 0 to 3, one bit per plane
+    fn present(&mut self, coord: &[u8], width: usize, height: usize);
+    /// Returns true while the backend wants the emulator to keep running
+    fn is_open(&self) -> bool;
+    /// Returns the events that happened since the last call
+    fn poll_events(&mut self) -> Vec<Event>;
+}
+
+/// Video backend that never shows anything. The last frame is kept in memory so it can be inspected.
+/// Useful to run the emulator where there is no display server, such as CI machines
+pub struct HeadlessVideo {
+    /// Last frame presented
+    frame: Vec<u8>,
+    /// Width of the last frame presented
+    width: usize,
+    /// Height of the last frame presented
+    height: usize,
+    /// Amount of frames presented so far
+    frames: u64,
+    /// Amount of frames after which the backend closes itself. None means it never closes
+    frame_limit: Option<u64>,
+    /// Events to be returned by the next call to `poll_events()`
+    events: Vec<Event>,
+}
+
+impl HeadlessVideo {
+    /// Creates a new headless backend that closes after `frame_limit` frames, if any
+    pub fn new(frame_limit: Option<u64>) -> HeadlessVideo {
+        HeadlessVideo {
+            frame: vec![],
+            width: 0,
+            height: 0,
+            frames: 0,
+            frame_limit,
+            events: vec![],
+        }
+    }
+
+    /// Queues an event to be reported by the next call to `poll_events()`
+    pub fn push_event(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    /// Returns the last frame presented along with its width and height
+    pub fn get_frame(&self) -> (&[u8], usize, usize) {
+        (&self.frame, self.width, self.height)
+    }
+
+    /// Returns the amount of frames presented so far
+    pub fn get_frame_count(&self) -> u64 {
+        self.frames
+    }
+}
+
+impl VideoBackend for HeadlessVideo {
+    fn present(&mut self, coord: &[u8], width: usize, height: usize) {
+        self.frame.clear();
+        self.frame.extend_from_slice(coord);
+        self.width = width;
+        self.height = height;
+        self.frames += 1;
+    }
+
+    fn is_open(&self) -> bool {
+        match self.frame_limit {
+            Some(limit) => self.frames < limit,
+            None => true,
+        }
+    }
+
+    fn poll_events(&mut self) -> Vec<Event> {
+        self.events.drain(..).collect()
+    }
+}
+
+/// Video backend that draws to a minifb window and reads the keyboard
+#[cfg(feature = "minifb")]
+pub struct MinifbVideo {
+    /// Buffer with pixel values of displayed window. The coordinates of the CHIP-8 screen are maped here according to the scale
+    buffer: Vec<u32>,
+    /// Window that displays the graphics and handles input (keyboard)
+    window: Window,
+    /// Window width. Product of the screen width and the scale
+    window_width: usize,
+    /// Window height. Product of the screen height and the scale
+    window_height: usize,
+    /// Colors of the pixels, indexed by their value
+    palette: [u32; 4],
+    /// Maps the keyboard to the keypad
+    keymap: Keymap,
+}
+
+#[cfg(feature = "minifb")]
+impl MinifbVideo {
+    /// Creates a new window with size determined by the function parameters, enlarged by a factor of `scale`.
+    /// The keyboard is mapped to the keypad with the given keymap
+    pub fn new(width: usize, height: usize, scale: usize, keymap: Keymap) -> MinifbVideo {
+        let window_width = width * scale;
+        let window_height = height * scale;
+        let buffer = vec![0; window_width * window_height];
+
+        let window = Window::new(
+            "CHIP-8 Emulator",
+            window_width,
+            window_height,
+            WindowOptions::default(),
+        )
+        .expect("Error creating window");
+
+        MinifbVideo {
+            buffer,
+            window,
+            window_width,
+            window_height,
+            palette: PALETTE,
+            keymap,
+        }
+    }
+
+    /// Sets the colors of the pixels, indexed by their value
+    pub fn set_palette(&mut self, palette: [u32; 4]) {
+        self.palette = palette;
+    }
+
+    /// Maps pixels to represent the original window in a higher scale
+    fn map_pixels(&mut self, coord: &[u8], width: usize, height: usize) {
+        // Integer scale that makes the screen fit the window
+        let scale = (self.window_width / width).min(self.window_height / height);
+
+        for (i, bit) in coord.iter().enumerate() {
+            let x = (i % width) * scale;
+            let y = (i / width) * scale;
+            let color = self.palette[*bit as usize & 0b11];
+
+            // Update buffer to reflect the original virtual window that coord represents
+            for j in 0..scale {
+                let start = self.window_width * (y + j) + x;
+                for pixel in &mut self.buffer[start..start + scale] {
+                    *pixel = color;
+                }
+            }
+        }
+    }
+
+    /// Returns the keypad key a keyboard key is mapped to, if any. Keys are looked up by their minifb name
+    fn map_key(&self, key: Key) -> Option<u8> {
+        self.keymap.get(&format!("{:?}", key))
+    }
+}
+
+#[cfg(feature = "minifb")]
+impl VideoBackend for MinifbVideo {
+    fn present(&mut self, coord: &[u8], width: usize, height: usize) {
+        self.map_pixels(coord, width, height);
+        self.window
+            .update_with_buffer(&self.buffer, self.window_width, self.window_height)
+            .expect("Error drawing to window");
+    }
+
+    /// Returns true if window is open and if ESC is not pressed
+    fn is_open(&self) -> bool {
+        self.window.is_open() && !self.window.is_key_down(minifb::Key::Escape)
+    }
+
+    fn poll_events(&mut self) -> Vec<Event> {
+        let mut events = vec![];
+
+        // Every keypad key that went down or up since the last call
+        let pressed = self
+            .window
+            .get_keys_pressed(KeyRepeat::No)
+            .unwrap_or_default();
+        for key in pressed.into_iter().filter_map(|key| self.map_key(key)) {
+            events.push(Event::KeyDown(key));
+        }
+        let released = self.window.get_keys_released().unwrap_or_default();
+        for key in released.into_iter().filter_map(|key| self.map_key(key)) {
+            events.push(Event::KeyUp(key));
+        }
+
+        // Backspace steps back in time while held
+        if self.window.is_key_down(Key::Backspace) {
+            events.push(Event::Rewind);
+        }
+
+        // Page Up and Page Down change the emulated clock speed
+        if self.window.is_key_pressed(Key::PageUp, KeyRepeat::No) {
+            events.push(Event::SpeedUp);
+        }
+        if self.window.is_key_pressed(Key::PageDown, KeyRepeat::No) {
+            events.push(Event::SlowDown);
+        }
+
+        // Pause halts the program for the debugger
+        if self.window.is_key_pressed(Key::Pause, KeyRepeat::No) {
+            events.push(Event::Break);
+        }
+
+        // F1 to F9 load the state in slots 1 to 9. Holding shift saves it instead
+        let function_keys = [
+            Key::F1,
+            Key::F2,
+            Key::F3,
+            Key::F4,
+            Key::F5,
+            Key::F6,
+            Key::F7,
+            Key::F8,
+            Key::F9,
+        ];
+        let shift =
+            self.window.is_key_down(Key::LeftShift) || self.window.is_key_down(Key::RightShift);
+        for (slot, key) in function_keys.iter().enumerate() {
+            if self.window.is_key_pressed(*key, KeyRepeat::No) {
+                let slot = slot as u8 + 1;
+                events.push(if shift {
+                    Event::SaveState(slot)
+                } else {
+                    Event::LoadState(slot)
+                });
+            }
+        }
+
+        events
+    }
+}